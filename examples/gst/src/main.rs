@@ -3,10 +3,10 @@ use std::{io::Write as _, sync::Arc};
 
 use gstreamer::prelude::*;
 use rszlm::{
-    init::EnvInitBuilder,
+    init::{EnvInitBuilder, LogLevel, LogSink},
     media::Media,
     obj::{CodecArgs, CodecId, VideoCodecArgs},
-    server::{http_server_start, rtmp_server_start, rtsp_server_start},
+    server::ServerSet,
 };
 use tokio_util::sync::CancellationToken;
 
@@ -258,15 +258,19 @@ fn run_zlm(cancel: CancellationToken) {
     let _ = std::thread::Builder::new()
         .name("zlm_serve".to_string())
         .spawn(move || {
-            EnvInitBuilder::default()
-                .log_level(0)
-                .log_mask(0)
+            let _runtime = EnvInitBuilder::default()
+                .log_level(LogLevel::Trace)
+                .log_mask(LogSink::empty())
                 .thread_num(20)
-                .build();
-
-            http_server_start(8553, false);
-            rtsp_server_start(8554, false);
-            rtmp_server_start(8555, false);
+                .build()
+                .expect("init zlm");
+
+            let _servers = ServerSet::new()
+                .http(8553)
+                .rtsp(8554)
+                .rtmp(8555)
+                .start()
+                .expect("start zlm servers");
 
             loop {
                 if cancel.is_cancelled() {
@@ -1,19 +1,19 @@
 use rszlm::{
     event::EVENTS,
-    init::EnvInitBuilder,
+    init::{EnvInitBuilder, LogLevel, LogSink},
     player::Mp4ProxyPlayer,
-    server::{rtsp_server_start, stop_all_server},
+    server::ServerSet,
 };
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    EnvInitBuilder::default()
-        .log_level(0)
-        .log_mask(0)
+    let _runtime = EnvInitBuilder::default()
+        .log_level(LogLevel::Trace)
+        .log_mask(LogSink::empty())
         .thread_num(20)
-        .build();
+        .build()?;
 
-    rtsp_server_start(8554, false);
+    let servers = ServerSet::new().rtsp(8554).start()?;
 
     EVENTS.write().unwrap().on_media_play(move |msg| {
         println!("media play: {}", msg.url_info.stream());
@@ -30,6 +30,6 @@ async fn main() -> anyhow::Result<()> {
     });
 
     tokio::signal::ctrl_c().await?;
-    stop_all_server();
+    servers.stop();
     Ok(())
 }
@@ -8,9 +8,9 @@ use hyper_util::{client::legacy::connect::HttpConnector, rt::TokioExecutor};
 use once_cell::sync::{Lazy, OnceCell};
 use rszlm::{
     event::EVENTS,
-    init::{EnvIni, EnvInitBuilder},
+    init::{EnvIni, EnvInitBuilder, LogLevel, LogSink},
     player::ProxyPlayerBuilder,
-    server::{http_server_start, rtmp_server_start, rtsp_server_start, stop_all_server},
+    server::ServerSet,
 };
 use tokio::{runtime::Handle, sync::RwLock};
 use tokio_util::sync::CancellationToken;
@@ -196,11 +196,12 @@ fn start_zlm_background(
     runtime: tokio::runtime::Handle,
 ) -> anyhow::Result<()> {
     tokio::task::spawn_blocking(move || {
-        EnvInitBuilder::default()
-            .log_level(0)
-            .log_mask(0)
+        let _runtime = EnvInitBuilder::default()
+            .log_level(LogLevel::Trace)
+            .log_mask(LogSink::empty())
             .thread_num(20)
-            .build();
+            .build()
+            .expect("init zlm");
         {
             let ini = EnvIni::global().lock().unwrap();
             ini.set_option_int("protocol.hls_demand", 1);
@@ -212,9 +213,12 @@ fn start_zlm_background(
             println!("ini: {}", ini.dump());
         }
 
-        http_server_start(8553, false);
-        rtsp_server_start(8554, false);
-        rtmp_server_start(8555, false);
+        let servers = ServerSet::new()
+            .http(8553)
+            .rtsp(8554)
+            .rtmp(8555)
+            .start()
+            .expect("start zlm servers");
         {
             let mut events = EVENTS.write().unwrap();
             let tx_clone = tx.clone();
@@ -330,7 +334,7 @@ fn start_zlm_background(
             std::thread::sleep(std::time::Duration::from_millis(1000));
         }
 
-        stop_all_server();
+        servers.stop();
         println!("zlm server stopped");
     });
 
@@ -73,15 +73,16 @@ fn use_prebuilt() -> bool {
 /// Platform-specific asset file name inside a release, e.g.
 /// `zlmediakit_master_linux_amd64_latest.tar.gz`.
 ///
-/// The default branch follows the active features: `webrtc` builds need the
-/// `feature_transcode2` package (it ships a `libmk_api` compiled with WebRTC),
-/// everything else uses `master`. `ZLM_BRANCH` overrides this.
+/// The default branch follows the active features: `webrtc` and `transcode`
+/// builds need the `feature_transcode2` package (it ships a `libmk_api`
+/// compiled with WebRTC and FFmpeg), everything else uses `master`.
+/// `ZLM_BRANCH` overrides this.
 ///
 /// Note: as of the current releases, `feature_transcode2` only ships
 /// linux/{amd64,arm64} — webrtc dynamic builds on macOS/Windows have no prebuilt
 /// and must set `ZLM_BUILD_FROM_SOURCE=1` (or point `ZLM_DIR` at a local build).
 fn prebuilt_asset_name() -> String {
-    let default_branch = if cfg!(feature = "webrtc") {
+    let default_branch = if cfg!(feature = "webrtc") || cfg!(feature = "transcode") {
         "feature_transcode2"
     } else {
         "master"
@@ -211,6 +212,14 @@ fn build() -> io::Result<()> {
     #[cfg(not(feature = "webrtc"))]
     cmake.define("ENABLE_WEBRTC", "OFF");
 
+    // mk_decoder / mk_swscale are only compiled in with FFmpeg, raw
+    // yuv/pcm input of mk_media needs x264 and faac
+    #[cfg(feature = "transcode")]
+    cmake
+        .define("ENABLE_FFMPEG", "ON")
+        .define("ENABLE_X264", "ON")
+        .define("ENABLE_FAAC", "ON");
+
     cmake.register_dep("OPENSSL");
 
     let dst = cmake.build();
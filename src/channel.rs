@@ -0,0 +1,541 @@
+//! Linear "TV channel" publishing: one continuous stream from a schedule of
+//! MP4 clips and live pulls, with a fallback slate.
+//!
+//! Every input is re-stamped onto the channel's time line, so timestamps
+//! stay monotonic across switches, and a switch only takes effect at the new
+//! input's first keyframe. Live inputs are pulled with a
+//! [`ProxyPlayer`](crate::player::ProxyPlayer) into a helper stream and tapped
+//! from there; when one closes (or does not come up within the live timeout),
+//! the entry's backup plays for the rest of its slot, then the slate.
+//!
+//! ```ignore
+//! let channel = Scheduler::builder(DEFAULT_VHOST, "tv", "one")
+//!     .entry(Entry::file(Clip::new("news.mp4")))
+//!     .entry(
+//!         Entry::live("rtsp://studio/live")
+//!             .duration(Duration::from_secs(3600))
+//!             .backup(Input::File(Clip::new("rerun.mp4"))),
+//!     )
+//!     .slate("technical-difficulties.mp4")
+//!     .looping(true)
+//!     .start()?;
+//! ```
+
+use std::{
+    path::PathBuf,
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    frame::Frame,
+    media::{AutoMedia, AutoOptions, Media},
+    mp4::{Clip, Due, Mp4File, PacedFile},
+    obj::CodecId,
+    player::HelperPull,
+    timestamp::Splicer,
+};
+
+/// Where an entry's media comes from.
+#[derive(Debug, Clone)]
+pub enum Input {
+    File(Clip),
+    /// Any URL a [`ProxyPlayer`](crate::player::ProxyPlayer) can pull.
+    Live(String),
+}
+
+/// One slot of the schedule.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub input: Input,
+    /// Slot length; without one, files play to their out point and live
+    /// inputs until they close.
+    pub duration: Option<Duration>,
+    /// Plays for the rest of the slot if the input fails.
+    pub backup: Option<Input>,
+}
+
+impl Entry {
+    pub fn file(clip: Clip) -> Self {
+        Self {
+            input: Input::File(clip),
+            duration: None,
+            backup: None,
+        }
+    }
+
+    pub fn live(url: &str) -> Self {
+        Self {
+            input: Input::Live(url.to_string()),
+            duration: None,
+            backup: None,
+        }
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn backup(mut self, backup: Input) -> Self {
+        self.backup = Some(backup);
+        self
+    }
+}
+
+pub struct SchedulerBuilder {
+    vhost: String,
+    app: String,
+    stream: String,
+    entries: Vec<Entry>,
+    slate: Option<PathBuf>,
+    looping: bool,
+    live_timeout: Duration,
+    options: AutoOptions,
+}
+
+impl SchedulerBuilder {
+    pub fn entry(mut self, entry: Entry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// MP4 played in a loop when an input fails before the end of its slot,
+    /// and when the schedule has run out. A slate whose samples cannot be
+    /// read is dropped rather than reopened.
+    pub fn slate(mut self, path: impl Into<PathBuf>) -> Self {
+        self.slate = Some(path.into());
+        self
+    }
+
+    /// Starts the schedule over at its end.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// How long a live input may take to come up. Default 10 seconds.
+    pub fn live_timeout(mut self, timeout: Duration) -> Self {
+        self.live_timeout = timeout;
+        self
+    }
+
+    /// Whether the channel has video and audio. The codecs come from the
+    /// first input that plays; entries, backups and the slate are passed
+    /// through as they are, so all of them need those codecs.
+    pub fn tracks(mut self, video: bool, audio: bool) -> Self {
+        self.options.video = video;
+        self.options.audio = audio;
+        self
+    }
+
+    pub fn start(self) -> anyhow::Result<Scheduler> {
+        if let Some(slate) = &self.slate {
+            // fail early rather than when the slate is first needed
+            Mp4File::open(slate)?;
+        }
+        let output = Output {
+            splicer: Splicer::new(self.options.video),
+            media: Media::auto(
+                &self.vhost,
+                &self.app,
+                &self.stream,
+                false,
+                false,
+                self.options,
+            ),
+        };
+
+        let (events, receiver) = mpsc::channel();
+        let runner = Runner {
+            vhost: self.vhost,
+            stream: self.stream,
+            entries: self.entries,
+            next: 0,
+            slate: self.slate,
+            looping: self.looping,
+            live_timeout: self.live_timeout,
+            output,
+            current: None,
+            inputs: 0,
+            error: None,
+            events: receiver,
+            sender: events.clone(),
+        };
+        let thread = std::thread::Builder::new()
+            .name("channel".to_string())
+            .spawn(move || runner.run())?;
+        Ok(Scheduler {
+            events,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Publishes a schedule as one stream on its own thread, until dropped or
+/// until nothing is left to play: a non-looping schedule has played out
+/// and there is no slate, or the slate could not be read.
+pub struct Scheduler {
+    events: mpsc::Sender<Event>,
+    thread: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Scheduler {
+    pub fn builder(vhost: &str, app: &str, stream: &str) -> SchedulerBuilder {
+        SchedulerBuilder {
+            vhost: vhost.to_string(),
+            app: app.to_string(),
+            stream: stream.to_string(),
+            entries: Vec::new(),
+            slate: None,
+            looping: false,
+            live_timeout: Duration::from_secs(10),
+            options: AutoOptions::default(),
+        }
+    }
+
+    /// Appends an entry to the schedule; it starts right away if only the
+    /// slate was playing.
+    pub fn push(&self, entry: Entry) {
+        let _ = self.events.send(Event::Push(entry));
+    }
+
+    /// Ends the current slot now.
+    pub fn skip(&self) {
+        let _ = self.events.send(Event::Skip);
+    }
+
+    /// Waits until the channel stops by itself; returns the last input
+    /// error, e.g. why the slate could not be read, if any.
+    pub fn join(mut self) -> anyhow::Result<()> {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => anyhow::bail!("channel thread panicked"),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the channel is still publishing.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let _ = self.events.send(Event::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+enum Event {
+    /// A frame of live input `id`.
+    Frame(u64, Frame),
+    /// Live input `id` closed.
+    Closed(u64),
+    Push(Entry),
+    Skip,
+    Stop,
+}
+
+/// The channel's stream and its time line.
+struct Output {
+    media: AutoMedia,
+    splicer: Splicer,
+}
+
+impl Output {
+    fn write(&mut self, codec: CodecId, dts: u64, pts: u64, data: &[u8], keyframe: bool) {
+        if let Some(ts) = self.splicer.splice(codec, dts, pts, keyframe) {
+            self.media
+                .input_frame(&Frame::new(codec, ts.dts, ts.pts, data));
+        }
+    }
+}
+
+enum Source {
+    /// Repeats if it is the slate.
+    File(PacedFile),
+    Live {
+        id: u64,
+        pull: HelperPull,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Main(usize),
+    Backup,
+    Slate,
+}
+
+struct Current {
+    source: Source,
+    role: Role,
+    deadline: Option<Instant>,
+}
+
+struct Runner {
+    vhost: String,
+    stream: String,
+    entries: Vec<Entry>,
+    next: usize,
+    slate: Option<PathBuf>,
+    looping: bool,
+    live_timeout: Duration,
+    output: Output,
+    current: Option<Current>,
+    /// Live inputs started so far, to tell their events apart.
+    inputs: u64,
+    /// Latest input failure, returned once nothing is left to play.
+    error: Option<anyhow::Error>,
+    events: mpsc::Receiver<Event>,
+    sender: mpsc::Sender<Event>,
+}
+
+impl Runner {
+    fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            if self.current.is_none() && !self.start_next() {
+                return self.error.map_or(Ok(()), Err);
+            }
+            let timeout = self.pump();
+            let event = match self.events.recv_timeout(timeout) {
+                Ok(event) => Some(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+
+            match event {
+                Some(Event::Stop) => break,
+                Some(Event::Frame(id, frame)) if self.live_id() == Some(id) => {
+                    let keyframe = frame.is_key_frame() || frame.is_config_frame();
+                    if let Ok(codec) = frame.codec_id() {
+                        self.output
+                            .write(codec, frame.dts(), frame.pts(), frame.data(), keyframe);
+                    }
+                }
+                Some(Event::Closed(id)) if self.live_id() == Some(id) => self.fail(),
+                Some(Event::Push(entry)) => {
+                    self.entries.push(entry);
+                    let idle = self.current.as_ref().is_some_and(|current| {
+                        current.role == Role::Slate && current.deadline.is_none()
+                    });
+                    if idle {
+                        self.current = None;
+                    }
+                }
+                Some(Event::Skip) => self.current = None,
+                // frames and closes of inputs switched away from
+                Some(Event::Frame(..) | Event::Closed(_)) | None => {}
+            }
+
+            if let Some(current) = &self.current {
+                if current
+                    .deadline
+                    .is_some_and(|deadline| deadline <= Instant::now())
+                {
+                    self.finish();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn live_id(&self) -> Option<u64> {
+        match &self.current {
+            Some(Current {
+                source: Source::Live { id, .. },
+                ..
+            }) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Starts the next entry, or the slate once the schedule ran out;
+    /// `false` if there is nothing left to play.
+    fn start_next(&mut self) -> bool {
+        for _ in 0..=self.entries.len() {
+            if self.next >= self.entries.len() {
+                if self.looping && !self.entries.is_empty() {
+                    self.next = 0;
+                } else {
+                    return self.start_slate(None);
+                }
+            }
+            let index = self.next;
+            self.next += 1;
+            let entry = self.entries[index].clone();
+            let deadline = entry.duration.map(|duration| Instant::now() + duration);
+            if self.start(&entry.input, Role::Main(index), deadline) {
+                return true;
+            }
+            if let Some(backup) = &entry.backup {
+                if self.start(backup, Role::Backup, deadline) {
+                    return true;
+                }
+            }
+            if deadline.is_some() && self.start_slate(deadline) {
+                return true;
+            }
+        }
+        self.start_slate(None)
+    }
+
+    fn start_slate(&mut self, deadline: Option<Instant>) -> bool {
+        let Some(slate) = self.slate.clone() else {
+            return false;
+        };
+        let started = self.start(&Input::File(Clip::new(slate)), Role::Slate, deadline);
+        if !started {
+            // it would fail again on every reopen
+            self.slate = None;
+        }
+        started
+    }
+
+    fn start(&mut self, input: &Input, role: Role, deadline: Option<Instant>) -> bool {
+        let source = match input {
+            Input::File(clip) => match PacedFile::open(clip, role == Role::Slate) {
+                Ok(file) => Source::File(file),
+                Err(err) => {
+                    self.error = Some(err);
+                    return false;
+                }
+            },
+            Input::Live(url) => {
+                self.inputs += 1;
+                let id = self.inputs;
+                let sender = self.sender.clone();
+                let pull = HelperPull::start(&self.vhost, &self.stream, url, move || {
+                    let _ = sender.send(Event::Closed(id));
+                });
+                Source::Live { id, pull }
+            }
+        };
+        self.output.splicer.switch();
+        self.current = Some(Current {
+            source,
+            role,
+            deadline,
+        });
+        true
+    }
+
+    /// The current input ended or its slot is over.
+    fn finish(&mut self) {
+        let ended_early = self.current.as_ref().is_some_and(|current| {
+            current
+                .deadline
+                .is_some_and(|deadline| deadline > Instant::now())
+        });
+        if ended_early {
+            self.fail();
+        } else {
+            self.current = None;
+        }
+    }
+
+    /// The current input stopped before the end of its slot: backup, then
+    /// slate for the rest of it.
+    fn fail(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        drop(current.source);
+        if let Role::Main(index) = current.role {
+            if let Some(backup) = self.entries[index].backup.clone() {
+                if self.start(&backup, Role::Backup, current.deadline) {
+                    return;
+                }
+            }
+        }
+        if current.role != Role::Slate && current.deadline.is_some() {
+            self.start_slate(current.deadline);
+        }
+    }
+
+    /// Sends due file samples and attaches live inputs; returns how long to
+    /// wait for events.
+    fn pump(&mut self) -> Duration {
+        let mut until = self
+            .current
+            .as_ref()
+            .and_then(|current| current.deadline)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let mut failed = None;
+        let mut ended = false;
+
+        match self.current.as_mut().map(|current| &mut current.source) {
+            Some(Source::File(file)) => loop {
+                match file.next_due() {
+                    Due::Sample(sample) => self.output.write(
+                        sample.codec,
+                        sample.dts,
+                        sample.pts,
+                        &sample.data,
+                        sample.keyframe,
+                    ),
+                    Due::Wait(wait) => {
+                        until = Some(until.map_or(wait, |until| until.min(wait)));
+                        break;
+                    }
+                    // the slate starts over
+                    Due::Looped => self.output.splicer.rebase(),
+                    Due::End => {
+                        ended = true;
+                        break;
+                    }
+                    Due::Failed(err) => {
+                        failed = Some(err);
+                        break;
+                    }
+                }
+            },
+            Some(Source::Live { id, pull }) if !pull.is_attached() => {
+                let (id, sender) = (*id, self.sender.clone());
+                pull.attach(move |frame| {
+                    let _ = sender.send(Event::Frame(id, frame.clone()));
+                });
+                if !pull.is_attached() {
+                    if pull.elapsed() > self.live_timeout {
+                        failed = Some(anyhow::anyhow!(
+                            "live input did not come up within {:?}",
+                            self.live_timeout
+                        ));
+                    } else {
+                        let poll = Duration::from_millis(100);
+                        until = Some(until.map_or(poll, |until| until.min(poll)));
+                    }
+                }
+            }
+            // attached: frames come in as events
+            Some(Source::Live { .. }) | None => {}
+        }
+
+        if let Some(err) = failed {
+            if self
+                .current
+                .as_ref()
+                .is_some_and(|current| current.role == Role::Slate)
+            {
+                // it would fail again on every reopen
+                self.slate = None;
+            }
+            self.error = Some(err);
+            self.fail();
+            return Duration::ZERO;
+        }
+        if ended {
+            self.finish();
+            return Duration::ZERO;
+        }
+        // nothing timed to do: wait for events only
+        until.unwrap_or(Duration::from_secs(1))
+    }
+}
@@ -0,0 +1,787 @@
+//! Typed ZLMediaKit server configuration.
+//!
+//! [`ServerConfig`] mirrors the sections of ZLMediaKit's `config.ini` that are
+//! commonly tuned. Every field is optional: `None` keeps ZLMediaKit's built-in
+//! default, `Some` overrides it. Keys of sections that are not modelled here
+//! (e.g. `[api]`, `[ffmpeg]`, `[cluster]`) are kept verbatim in
+//! [`ServerConfig::extra`] so a full `config.ini` round-trips.
+//!
+//! [`reload_from_file`] / [`ConfigWatcher`] apply an edited file to the running
+//! server without a restart.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let mut config = ServerConfig::from_ini_file("config.ini")?;
+//! config.hls.seg_dur = Some(4);
+//! config.validate()?;
+//! config.apply(&EnvIni::global().lock().unwrap());
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::{
+    event::{self, ConfigChangedMessage},
+    init::EnvIni,
+};
+
+/// A value that can be stored in a ZLMediaKit ini entry.
+pub trait ConfigValue: Sized {
+    fn to_ini(&self) -> String;
+    fn from_ini(value: &str) -> anyhow::Result<Self>;
+}
+
+impl ConfigValue for bool {
+    fn to_ini(&self) -> String {
+        (*self as i32).to_string()
+    }
+
+    fn from_ini(value: &str) -> anyhow::Result<Self> {
+        match value.trim() {
+            "1" | "true" | "on" | "yes" => Ok(true),
+            "0" | "false" | "off" | "no" | "" => Ok(false),
+            v => anyhow::bail!("invalid bool `{}`", v),
+        }
+    }
+}
+
+impl ConfigValue for String {
+    fn to_ini(&self) -> String {
+        self.clone()
+    }
+
+    fn from_ini(value: &str) -> anyhow::Result<Self> {
+        Ok(value.to_string())
+    }
+}
+
+macro_rules! config_value_from_str {
+    ($($ty:ty),*) => {
+        $(
+            impl ConfigValue for $ty {
+                fn to_ini(&self) -> String {
+                    self.to_string()
+                }
+
+                fn from_ini(value: &str) -> anyhow::Result<Self> {
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|e| anyhow::anyhow!("invalid {} `{}`: {}", stringify!($ty), value, e))
+                }
+            }
+        )*
+    };
+}
+
+config_value_from_str!(i32, u16, u32, u64, f32);
+
+macro_rules! config_section {
+    (
+        $(#[$meta:meta])*
+        $name:ident = $section:literal {
+            $( $(#[$fmeta:meta])* $field:ident: $ty:ty = $key:literal, )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq)]
+        #[cfg_attr(feature = "toml", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "toml", serde(default, deny_unknown_fields))]
+        pub struct $name {
+            $(
+                $(#[$fmeta])*
+                #[doc = concat!("`", $section, ".", $key, "`")]
+                #[cfg_attr(feature = "toml", serde(rename = $key, skip_serializing_if = "Option::is_none"))]
+                pub $field: Option<$ty>,
+            )*
+        }
+
+        impl $name {
+            /// Section name in `config.ini`.
+            pub const SECTION: &'static str = $section;
+
+            /// Every key this section knows about, without the section prefix.
+            pub const KEYS: &'static [&'static str] = &[$($key),*];
+
+            /// `true` if no value of the section is set.
+            pub fn is_empty(&self) -> bool {
+                true $(&& self.$field.is_none())*
+            }
+
+            fn entries(&self, out: &mut Vec<(String, String)>) {
+                $(
+                    if let Some(v) = &self.$field {
+                        out.push((concat!($section, ".", $key).to_string(), v.to_ini()));
+                    }
+                )*
+            }
+
+            /// Returns `Ok(false)` if `key` is not part of this section.
+            fn set(&mut self, key: &str, value: &str) -> anyhow::Result<bool> {
+                match key {
+                    $(
+                        $key => {
+                            self.$field = Some(
+                                <$ty as ConfigValue>::from_ini(value)
+                                    .with_context(|| concat!($section, ".", $key))?,
+                            )
+                        }
+                    )*
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }
+        }
+    };
+}
+
+config_section! {
+    /// `[general]`
+    GeneralConfig = "general" {
+        media_server_id: String = "mediaServerId",
+        enable_vhost: bool = "enableVhost",
+        flow_threshold: u32 = "flowThreshold",
+        max_stream_wait_ms: u32 = "maxStreamWaitMS",
+        stream_none_reader_delay_ms: u32 = "streamNoneReaderDelayMS",
+        reset_when_replay: bool = "resetWhenRePlay",
+        merge_write_ms: u32 = "mergeWriteMS",
+        wait_track_ready_ms: u32 = "wait_track_ready_ms",
+        wait_add_track_ms: u32 = "wait_add_track_ms",
+        unready_frame_cache: u32 = "unready_frame_cache",
+        check_nvidia_dev: bool = "check_nvidia_dev",
+        enable_ffmpeg_log: bool = "enable_ffmpeg_log",
+        listen_ip: String = "listen_ip",
+        broadcast_player_count_changed: bool = "broadcast_player_count_changed",
+    }
+}
+
+config_section! {
+    /// `[hls]`
+    HlsConfig = "hls" {
+        file_buf_size: u32 = "fileBufSize",
+        seg_dur: u32 = "segDur",
+        seg_num: u32 = "segNum",
+        seg_delay: u32 = "segDelay",
+        seg_retain: u32 = "segRetain",
+        seg_keep: bool = "segKeep",
+        broadcast_record_ts: bool = "broadcastRecordTs",
+        delete_delay_sec: u32 = "deleteDelaySec",
+        fast_register: bool = "fastRegister",
+    }
+}
+
+config_section! {
+    /// `[hook]`, web hook urls and their delivery settings.
+    HookConfig = "hook" {
+        enable: bool = "enable",
+        timeout_sec: u32 = "timeoutSec",
+        alive_interval: f32 = "alive_interval",
+        retry: u32 = "retry",
+        retry_delay: f32 = "retry_delay",
+        stream_changed_schemas: String = "stream_changed_schemas",
+        on_flow_report: String = "on_flow_report",
+        on_http_access: String = "on_http_access",
+        on_play: String = "on_play",
+        on_publish: String = "on_publish",
+        on_record_mp4: String = "on_record_mp4",
+        on_record_ts: String = "on_record_ts",
+        on_rtsp_auth: String = "on_rtsp_auth",
+        on_rtsp_realm: String = "on_rtsp_realm",
+        on_shell_login: String = "on_shell_login",
+        on_stream_changed: String = "on_stream_changed",
+        on_stream_none_reader: String = "on_stream_none_reader",
+        on_stream_not_found: String = "on_stream_not_found",
+        on_server_started: String = "on_server_started",
+        on_server_exited: String = "on_server_exited",
+        on_server_keepalive: String = "on_server_keepalive",
+        on_send_rtp_stopped: String = "on_send_rtp_stopped",
+        on_rtp_server_timeout: String = "on_rtp_server_timeout",
+    }
+}
+
+config_section! {
+    /// `[http]`
+    HttpConfig = "http" {
+        port: u16 = "port",
+        sslport: u16 = "sslport",
+        char_set: String = "charSet",
+        keep_alive_second: u32 = "keepAliveSecond",
+        max_req_size: u32 = "maxReqSize",
+        send_buf_size: u32 = "sendBufSize",
+        root_path: String = "rootPath",
+        not_found: String = "notFound",
+        dir_menu: bool = "dirMenu",
+        virtual_path: String = "virtualPath",
+        forbid_cache_suffix: String = "forbidCacheSuffix",
+        forwarded_ip_header: String = "forwarded_ip_header",
+        allow_cross_domains: bool = "allow_cross_domains",
+        allow_ip_range: String = "allow_ip_range",
+    }
+}
+
+config_section! {
+    /// `[rtmp]`
+    RtmpConfig = "rtmp" {
+        port: u16 = "port",
+        sslport: u16 = "sslport",
+        handshake_second: u32 = "handshakeSecond",
+        keep_alive_second: u32 = "keepAliveSecond",
+        direct_proxy: bool = "directProxy",
+        enhanced: bool = "enhanced",
+    }
+}
+
+config_section! {
+    /// `[rtsp]`
+    RtspConfig = "rtsp" {
+        port: u16 = "port",
+        sslport: u16 = "sslport",
+        auth_basic: bool = "authBasic",
+        direct_proxy: bool = "directProxy",
+        handshake_second: u32 = "handshakeSecond",
+        keep_alive_second: u32 = "keepAliveSecond",
+        low_latency: bool = "lowLatency",
+        /// -1: auto, 0: tcp, 1: udp, 2: multicast
+        rtp_transport_type: i32 = "rtpTransportType",
+    }
+}
+
+config_section! {
+    /// `[rtp_proxy]`
+    RtpProxyConfig = "rtp_proxy" {
+        port: u16 = "port",
+        dump_dir: String = "dumpDir",
+        timeout_sec: u32 = "timeoutSec",
+        /// `"<min>-<max>"`, used by `openRtpServer` with port 0
+        port_range: String = "port_range",
+        h264_pt: u32 = "h264_pt",
+        h265_pt: u32 = "h265_pt",
+        ps_pt: u32 = "ps_pt",
+        opus_pt: u32 = "opus_pt",
+        gop_cache: bool = "gop_cache",
+        rtp_g711_dur_ms: u32 = "rtp_g711_dur_ms",
+        udp_recv_socket_buffer: u32 = "udp_recv_socket_buffer",
+    }
+}
+
+config_section! {
+    /// `[rtc]`, only used by builds with the `webrtc` feature.
+    RtcConfig = "rtc" {
+        port: u16 = "port",
+        tcp_port: u16 = "tcpPort",
+        timeout_sec: u32 = "timeoutSec",
+        extern_ip: String = "externIP",
+        remb_bit_rate: u32 = "rembBitRate",
+        preferred_codec_a: String = "preferredCodecA",
+        preferred_codec_v: String = "preferredCodecV",
+        start_bitrate: u32 = "start_bitrate",
+        max_bitrate: u32 = "max_bitrate",
+        min_bitrate: u32 = "min_bitrate",
+        max_rtp_cache_ms: u32 = "maxRtpCacheMS",
+        max_rtp_cache_size: u32 = "maxRtpCacheSize",
+        datachannel_echo: bool = "datachannel_echo",
+    }
+}
+
+config_section! {
+    /// `[srt]`
+    SrtConfig = "srt" {
+        port: u16 = "port",
+        timeout_sec: u32 = "timeoutSec",
+        latency_mul: u32 = "latencyMul",
+        pkt_buf_size: u32 = "pktBufSize",
+        pass_phrase: String = "passPhrase",
+    }
+}
+
+config_section! {
+    /// `[record]`
+    RecordConfig = "record" {
+        app_name: String = "appName",
+        file_buf_size: u32 = "fileBufSize",
+        sample_ms: u32 = "sampleMS",
+        fast_start: bool = "fastStart",
+        file_repeat: bool = "fileRepeat",
+        enable_fmp4: bool = "enableFmp4",
+    }
+}
+
+config_section! {
+    /// `[protocol]`, default protocol conversion options of every stream.
+    ProtocolConfig = "protocol" {
+        /// 0: absolute, 1: system, 2: relative
+        modify_stamp: i32 = "modify_stamp",
+        enable_audio: bool = "enable_audio",
+        add_mute_audio: bool = "add_mute_audio",
+        auto_close: bool = "auto_close",
+        continue_push_ms: u32 = "continue_push_ms",
+        paced_sender_ms: u32 = "paced_sender_ms",
+        enable_hls: bool = "enable_hls",
+        enable_hls_fmp4: bool = "enable_hls_fmp4",
+        enable_mp4: bool = "enable_mp4",
+        enable_rtsp: bool = "enable_rtsp",
+        enable_rtmp: bool = "enable_rtmp",
+        enable_ts: bool = "enable_ts",
+        enable_fmp4: bool = "enable_fmp4",
+        mp4_as_player: bool = "mp4_as_player",
+        mp4_max_second: u32 = "mp4_max_second",
+        mp4_save_path: String = "mp4_save_path",
+        hls_save_path: String = "hls_save_path",
+        hls_demand: bool = "hls_demand",
+        rtsp_demand: bool = "rtsp_demand",
+        rtmp_demand: bool = "rtmp_demand",
+        ts_demand: bool = "ts_demand",
+        fmp4_demand: bool = "fmp4_demand",
+    }
+}
+
+/// Typed view of ZLMediaKit's `config.ini`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "toml", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "toml", serde(default, deny_unknown_fields))]
+pub struct ServerConfig {
+    #[cfg_attr(
+        feature = "toml",
+        serde(skip_serializing_if = "GeneralConfig::is_empty")
+    )]
+    pub general: GeneralConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "HlsConfig::is_empty"))]
+    pub hls: HlsConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "HookConfig::is_empty"))]
+    pub hook: HookConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "HttpConfig::is_empty"))]
+    pub http: HttpConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "RtmpConfig::is_empty"))]
+    pub rtmp: RtmpConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "RtspConfig::is_empty"))]
+    pub rtsp: RtspConfig,
+    #[cfg_attr(
+        feature = "toml",
+        serde(skip_serializing_if = "RtpProxyConfig::is_empty")
+    )]
+    pub rtp_proxy: RtpProxyConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "RtcConfig::is_empty"))]
+    pub rtc: RtcConfig,
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "SrtConfig::is_empty"))]
+    pub srt: SrtConfig,
+    #[cfg_attr(
+        feature = "toml",
+        serde(skip_serializing_if = "RecordConfig::is_empty")
+    )]
+    pub record: RecordConfig,
+    #[cfg_attr(
+        feature = "toml",
+        serde(skip_serializing_if = "ProtocolConfig::is_empty")
+    )]
+    pub protocol: ProtocolConfig,
+    /// Entries this struct does not model, keyed `section.key`.
+    #[cfg_attr(feature = "toml", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub extra: BTreeMap<String, String>,
+}
+
+/// One key whose value in [`EnvIni`] differs from a [`ServerConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// `section.key`
+    pub key: String,
+    /// Value currently held by the ini, `None` if the key is absent.
+    pub current: Option<String>,
+    /// Value the config wants.
+    pub wanted: String,
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses ini text, either sectioned (`[hls]` / `segDur=2`) or flat
+    /// (`hls.segDur=2`), as written by ZLMediaKit and [`EnvIni::dump`].
+    pub fn from_ini(text: &str) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for (key, value) in parse_ini(text) {
+            config.set(&key, &value)?;
+        }
+        Ok(config)
+    }
+
+    pub fn from_ini_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("read config {}", path.display()))?;
+        Self::from_ini(&text)
+    }
+
+    /// Loads `.toml` files as TOML (with the `toml` feature), anything else as ini.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        #[cfg(feature = "toml")]
+        if path.extension().is_some_and(|e| e == "toml") {
+            return Self::from_toml_file(path);
+        }
+        Self::from_ini_file(path)
+    }
+
+    /// Renders the set values as sectioned ini text.
+    pub fn to_ini(&self) -> String {
+        let mut sections: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+        for (key, value) in self.entries() {
+            let (section, name) = key.split_once('.').unwrap_or(("", key.as_str()));
+            sections
+                .entry(section.to_string())
+                .or_default()
+                .push((name.to_string(), value));
+        }
+
+        let mut out = String::new();
+        for (section, entries) in sections {
+            if !section.is_empty() {
+                out.push_str(&format!("[{}]\n", section));
+            }
+            for (key, value) in entries {
+                out.push_str(&format!("{}={}\n", key, value));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn save_ini(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_ini())
+            .with_context(|| format!("write config {}", path.display()))
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("read config {}", path.display()))?;
+        Self::from_toml(&text)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn save_toml(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_toml()?)
+            .with_context(|| format!("write config {}", path.display()))
+    }
+
+    /// Sets one `section.key` entry; unmodelled keys go to [`extra`](Self::extra).
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let known = match key.split_once('.') {
+            Some((section, name)) => match section {
+                GeneralConfig::SECTION => self.general.set(name, value)?,
+                HlsConfig::SECTION => self.hls.set(name, value)?,
+                HookConfig::SECTION => self.hook.set(name, value)?,
+                HttpConfig::SECTION => self.http.set(name, value)?,
+                RtmpConfig::SECTION => self.rtmp.set(name, value)?,
+                RtspConfig::SECTION => self.rtsp.set(name, value)?,
+                RtpProxyConfig::SECTION => self.rtp_proxy.set(name, value)?,
+                RtcConfig::SECTION => self.rtc.set(name, value)?,
+                SrtConfig::SECTION => self.srt.set(name, value)?,
+                RecordConfig::SECTION => self.record.set(name, value)?,
+                ProtocolConfig::SECTION => self.protocol.set(name, value)?,
+                _ => false,
+            },
+            None => false,
+        };
+        if !known {
+            self.extra.insert(key.to_string(), value.to_string());
+        }
+        Ok(())
+    }
+
+    /// All set values as `(section.key, value)` pairs, `extra` last.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        self.general.entries(&mut out);
+        self.hls.entries(&mut out);
+        self.hook.entries(&mut out);
+        self.http.entries(&mut out);
+        self.rtmp.entries(&mut out);
+        self.rtsp.entries(&mut out);
+        self.rtp_proxy.entries(&mut out);
+        self.rtc.entries(&mut out);
+        self.srt.entries(&mut out);
+        self.record.entries(&mut out);
+        self.protocol.entries(&mut out);
+        out.extend(self.extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+        out
+    }
+
+    /// Checks for likely mistakes: unknown keys in typed sections (usually a
+    /// typo such as `hls.segDuration`), conflicting ports, malformed port
+    /// ranges and hook urls. All problems are reported in one error.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        let typed_sections = [
+            GeneralConfig::SECTION,
+            HlsConfig::SECTION,
+            HookConfig::SECTION,
+            HttpConfig::SECTION,
+            RtmpConfig::SECTION,
+            RtspConfig::SECTION,
+            RtpProxyConfig::SECTION,
+            RtcConfig::SECTION,
+            SrtConfig::SECTION,
+            RecordConfig::SECTION,
+            ProtocolConfig::SECTION,
+        ];
+        for key in self.extra.keys() {
+            if let Some((section, _)) = key.split_once('.') {
+                if typed_sections.contains(&section) {
+                    errors.push(format!("unknown key `{}`", key));
+                }
+            }
+        }
+
+        let tcp_ports = [
+            ("http.port", self.http.port),
+            ("http.sslport", self.http.sslport),
+            ("rtmp.port", self.rtmp.port),
+            ("rtmp.sslport", self.rtmp.sslport),
+            ("rtsp.port", self.rtsp.port),
+            ("rtsp.sslport", self.rtsp.sslport),
+            ("rtp_proxy.port", self.rtp_proxy.port),
+            ("rtc.tcpPort", self.rtc.tcp_port),
+        ];
+        let udp_ports = [
+            ("rtp_proxy.port", self.rtp_proxy.port),
+            ("rtc.port", self.rtc.port),
+            ("srt.port", self.srt.port),
+        ];
+        for (proto, ports) in [("tcp", &tcp_ports[..]), ("udp", &udp_ports[..])] {
+            let mut seen: HashMap<u16, &str> = HashMap::new();
+            for (key, port) in ports {
+                match port {
+                    Some(port) if *port != 0 => {
+                        if let Some(other) = seen.insert(*port, key) {
+                            errors.push(format!(
+                                "{} port {} used by both `{}` and `{}`",
+                                proto, port, other, key
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(range) = &self.rtp_proxy.port_range {
+            let parsed = range.split_once('-').and_then(|(a, b)| {
+                Some((a.trim().parse::<u16>().ok()?, b.trim().parse::<u16>().ok()?))
+            });
+            match parsed {
+                Some((min, max)) if min < max => {}
+                _ => errors.push(format!("invalid `rtp_proxy.port_range` `{}`", range)),
+            }
+        }
+
+        if self.hls.seg_dur == Some(0) {
+            errors.push("`hls.segDur` must be greater than 0".to_string());
+        }
+
+        let hooks = [
+            ("hook.on_flow_report", &self.hook.on_flow_report),
+            ("hook.on_http_access", &self.hook.on_http_access),
+            ("hook.on_play", &self.hook.on_play),
+            ("hook.on_publish", &self.hook.on_publish),
+            ("hook.on_record_mp4", &self.hook.on_record_mp4),
+            ("hook.on_record_ts", &self.hook.on_record_ts),
+            ("hook.on_rtsp_auth", &self.hook.on_rtsp_auth),
+            ("hook.on_rtsp_realm", &self.hook.on_rtsp_realm),
+            ("hook.on_shell_login", &self.hook.on_shell_login),
+            ("hook.on_stream_changed", &self.hook.on_stream_changed),
+            (
+                "hook.on_stream_none_reader",
+                &self.hook.on_stream_none_reader,
+            ),
+            ("hook.on_stream_not_found", &self.hook.on_stream_not_found),
+            ("hook.on_server_started", &self.hook.on_server_started),
+            ("hook.on_server_exited", &self.hook.on_server_exited),
+            ("hook.on_server_keepalive", &self.hook.on_server_keepalive),
+            ("hook.on_send_rtp_stopped", &self.hook.on_send_rtp_stopped),
+            (
+                "hook.on_rtp_server_timeout",
+                &self.hook.on_rtp_server_timeout,
+            ),
+        ];
+        for (key, url) in hooks {
+            if let Some(url) = url {
+                if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+                    errors.push(format!("`{}` is not an http(s) url: `{}`", key, url));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid server config:\n  {}", errors.join("\n  "))
+        }
+    }
+
+    /// Writes every set value into `ini`.
+    pub fn apply(&self, ini: &EnvIni) {
+        for (key, value) in self.entries() {
+            ini.set_option(&key, &value);
+        }
+    }
+
+    /// Set values that differ from what `ini` currently holds.
+    pub fn diff(&self, ini: &EnvIni) -> Vec<ConfigDiff> {
+        let current: HashMap<String, String> = parse_ini(&ini.dump()).into_iter().collect();
+        self.entries()
+            .into_iter()
+            .filter_map(|(key, wanted)| {
+                let current = current.get(&key).cloned();
+                if current.as_deref() == Some(wanted.as_str()) {
+                    None
+                } else {
+                    Some(ConfigDiff {
+                        key,
+                        current,
+                        wanted,
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+/// Re-reads `path` and applies the values that differ from the global ini.
+///
+/// The changed entries are merged in one [`EnvIni::load_string`] call, which
+/// makes ZLMediaKit broadcast `kBroadcastReloadConfig` once, then
+/// [`Event::on_config_changed`](crate::event::Event::on_config_changed) is
+/// fired. Keys missing from the file keep their current value. An invalid file
+/// (see [`ServerConfig::validate`]) is rejected without touching the ini.
+/// Returns the applied changes; nothing is broadcast if there are none.
+pub fn reload_from_file(path: impl AsRef<Path>) -> anyhow::Result<Vec<ConfigDiff>> {
+    let wanted = ServerConfig::from_file(path)?;
+    wanted.validate()?;
+
+    let (old, new, changes) = {
+        let ini = EnvIni::global().lock().unwrap();
+        let changes = wanted.diff(&ini);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+
+        let old = ServerConfig::from_ini(&ini.dump())?;
+        let text: String = changes
+            .iter()
+            .map(|c| format!("{}={}\n", c.key, c.wanted))
+            .collect();
+        ini.load_string(&text);
+        let new = ServerConfig::from_ini(&ini.dump())?;
+        (old, new, changes)
+    };
+
+    event::emit_config_changed(ConfigChangedMessage {
+        old,
+        new,
+        changes: changes.clone(),
+    });
+    Ok(changes)
+}
+
+/// Polls a config file and calls [`reload_from_file`] whenever its
+/// modification time changes. Stops when dropped.
+pub struct ConfigWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    /// `on_error` receives failed reloads (unreadable or invalid file); the
+    /// previous config stays active and the next change is retried.
+    pub fn spawn(
+        path: impl Into<PathBuf>,
+        interval: Duration,
+        on_error: impl Fn(anyhow::Error) + Send + 'static,
+    ) -> Self {
+        let path = path.into();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("rszlm-config-watch".into())
+            .spawn(move || {
+                let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+                let mut last = modified(&path);
+                while !stop_clone.load(Ordering::Relaxed) {
+                    std::thread::sleep(interval);
+                    let now = modified(&path);
+                    if now.is_some() && now != last {
+                        last = now;
+                        if let Err(e) = reload_from_file(&path) {
+                            on_error(e);
+                        }
+                    }
+                }
+            })
+            .expect("spawn config watcher thread");
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Flattens ini text into `(section.key, value)` pairs, skipping `;`/`#` comments.
+pub(crate) fn parse_ini(text: &str) -> Vec<(String, String)> {
+    let mut section = String::new();
+    let mut out = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", section, key)
+            };
+            out.push((key, value.trim().to_string()));
+        }
+    }
+    out
+}
@@ -0,0 +1,341 @@
+//! MPEG-TS and MPEG-PS demuxers in Rust, for republishing streams that do
+//! not come in through ZLMediaKit's own listeners (files, pipes, multicast
+//! on other interfaces).
+//!
+//! ```ignore
+//! let mut publisher = Republisher::new(ts::Demuxer::new(), DEFAULT_VHOST, "live", "cam");
+//! let socket = std::net::UdpSocket::bind("0.0.0.0:1234")?;
+//! let mut buf = [0u8; 1500];
+//! loop {
+//!     let len = socket.recv(&mut buf)?;
+//!     publisher.push(&buf[..len]);
+//! }
+//! ```
+
+pub mod ps;
+pub mod ts;
+
+use std::time::Duration;
+
+use crate::{
+    frame::Frame,
+    media::{AutoMedia, AutoOptions, Media},
+    obj::CodecId,
+    timestamp::{Normalizer, TrackKind},
+};
+
+/// One access unit (video) or audio frame out of a demuxer.
+#[derive(Debug, Clone)]
+pub struct EsFrame {
+    pub codec: CodecId,
+    /// 90 kHz, 33 bit.
+    pub dts: u64,
+    /// 90 kHz, 33 bit.
+    pub pts: u64,
+    /// Annex-B for H264/H265, with ADTS header for AAC.
+    pub data: Vec<u8>,
+    /// Whether the PES carried a PTS; if not, `dts` and `pts` are those of
+    /// the stream's previous frame.
+    pub timed: bool,
+}
+
+impl EsFrame {
+    /// Builds a [`Frame`] with the 90 kHz timestamps turned into milliseconds,
+    /// as is; see [`Republisher`] for a cleaned up time line.
+    pub fn to_frame(&self) -> Frame {
+        Frame::new(self.codec, self.dts / 90, self.pts / 90, &self.data)
+    }
+}
+
+/// Something that turns a byte stream into [`EsFrame`]s.
+pub trait Demux {
+    /// Feeds bytes in any chunking; returns the frames completed by them.
+    fn push(&mut self, data: &[u8]) -> Vec<EsFrame>;
+
+    /// Returns what is still buffered, e.g. at end of file.
+    fn flush(&mut self) -> Vec<EsFrame>;
+
+    /// Codecs announced by the stream (PMT / PSM), once known.
+    fn codecs(&self) -> Option<Vec<CodecId>>;
+}
+
+/// Codec of an ISO 13818-1 `stream_type`.
+pub fn stream_type_codec(stream_type: u8) -> Option<CodecId> {
+    match stream_type {
+        0x03 | 0x04 => Some(CodecId::MP3),
+        0x0F => Some(CodecId::AAC),
+        0x1B => Some(CodecId::H264),
+        0x24 => Some(CodecId::H265),
+        // GB28181
+        0x80 => Some(CodecId::SVACV),
+        0x90 => Some(CodecId::G711A),
+        0x91 => Some(CodecId::G711U),
+        0x92 => Some(CodecId::G722),
+        0x93 => Some(CodecId::G723),
+        0x99 => Some(CodecId::G729),
+        0x9B => Some(CodecId::SVACA),
+        _ => None,
+    }
+}
+
+/// Timestamps and payload of a PES packet: `(pts, dts, payload)`.
+pub(crate) fn parse_pes(pes: &[u8]) -> Option<(Option<u64>, Option<u64>, &[u8])> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return None;
+    }
+    let flags = pes[7] >> 6;
+    let header_end = 9 + pes[8] as usize;
+    if pes.len() < header_end {
+        return None;
+    }
+    // timestamps must fit in the header they are flagged in
+    let pts = match flags & 0x2 {
+        0 => None,
+        _ => Some(read_timestamp(pes.get(9..header_end.min(14))?)?),
+    };
+    let dts = match flags {
+        0x3 => Some(read_timestamp(pes.get(14..header_end.min(19))?)?),
+        _ => None,
+    };
+    Some((pts, dts, &pes[header_end..]))
+}
+
+fn read_timestamp(data: &[u8]) -> Option<u64> {
+    let b = data.get(..5)?;
+    Some(
+        ((b[0] as u64 >> 1) & 0x07) << 30
+            | (b[1] as u64) << 22
+            | ((b[2] as u64) >> 1) << 15
+            | (b[3] as u64) << 7
+            | (b[4] as u64) >> 1,
+    )
+}
+
+/// Splits a PES payload of ADTS frames into [`EsFrame`]s, advancing the
+/// timestamps by 1024 samples per frame.
+pub(crate) fn split_adts(payload: &[u8], pts: u64, dts: u64, timed: bool, out: &mut Vec<EsFrame>) {
+    const SAMPLE_RATES: [u64; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+    let mut rest = payload;
+    let mut index = 0;
+    while rest.len() >= 7 && rest[0] == 0xFF && rest[1] & 0xF0 == 0xF0 {
+        let len =
+            ((rest[3] as usize & 0x03) << 11) | (rest[4] as usize) << 3 | (rest[5] as usize) >> 5;
+        if len < 7 || len > rest.len() {
+            break;
+        }
+        let sample_rate = SAMPLE_RATES
+            .get(((rest[2] >> 2) & 0x0F) as usize)
+            .copied()
+            .unwrap_or(44100);
+        let offset = index * 1024 * 90000 / sample_rate;
+        out.push(EsFrame {
+            codec: CodecId::AAC,
+            dts: (dts + offset) & 0x1_FFFF_FFFF,
+            pts: (pts + offset) & 0x1_FFFF_FFFF,
+            data: rest[..len].to_vec(),
+            timed,
+        });
+        rest = &rest[len..];
+        index += 1;
+    }
+}
+
+/// Turns a finished PES payload into frames. A PES without PTS (only
+/// required every 700 ms) is passed on untimed, with the `(pts, dts)` of the
+/// stream's `last` PES; a timed one updates it.
+pub(crate) fn emit_pes(
+    codec: CodecId,
+    pes: &[u8],
+    last: &mut Option<(u64, u64)>,
+    out: &mut Vec<EsFrame>,
+) {
+    let Some((pts, dts, payload)) = parse_pes(pes) else {
+        return;
+    };
+    if payload.is_empty() {
+        return;
+    }
+    let timed = pts.is_some();
+    let (pts, dts) = match pts {
+        Some(pts) => *last.insert((pts, dts.unwrap_or(pts))),
+        None => last.unwrap_or_default(),
+    };
+    if codec == CodecId::AAC {
+        split_adts(payload, pts, dts, timed, out);
+    } else {
+        out.push(EsFrame {
+            codec,
+            dts,
+            pts,
+            data: payload.to_vec(),
+            timed,
+        });
+    }
+}
+
+/// Publishes what a [`Demux`] produces as a ZLMediaKit stream, with
+/// timestamps fed through a [`Normalizer`] for 33 bit 90 kHz clocks.
+///
+/// The tracks are set up from the stream types of the PMT / PSM; ZLMediaKit
+/// takes the resolution from the SPS and the AAC format from the ADTS
+/// headers. MP3 is announced as 44.1 kHz stereo and G7xx as 8 kHz mono.
+/// Codecs ZLMediaKit has no track for are dropped. Without a PMT / PSM the
+/// tracks are probed from the frames (see [`Media::auto`]).
+pub struct Republisher<D> {
+    demuxer: D,
+    vhost: String,
+    app: String,
+    stream: String,
+    output: Option<Output>,
+    normalizer: Normalizer,
+}
+
+enum Output {
+    Direct(Box<Media>),
+    Auto(AutoMedia),
+}
+
+impl<D: Demux> Republisher<D> {
+    pub fn new(demuxer: D, vhost: &str, app: &str, stream: &str) -> Self {
+        Self {
+            demuxer,
+            vhost: vhost.to_string(),
+            app: app.to_string(),
+            stream: stream.to_string(),
+            output: None,
+            normalizer: Normalizer::new(90000, 90000)
+                .wrap_bits(33)
+                .shared_clock(true),
+        }
+    }
+
+    /// Demuxes `data` and publishes the frames; the stream is created with
+    /// the first frame.
+    pub fn push(&mut self, data: &[u8]) {
+        let frames = self.demuxer.push(data);
+        self.publish(frames);
+    }
+
+    /// Publishes what the demuxer still buffers.
+    pub fn flush(&mut self) {
+        let frames = self.demuxer.flush();
+        self.publish(frames);
+    }
+
+    /// The published stream, once created.
+    pub fn media(&self) -> Option<&Media> {
+        self.output.as_ref().map(|output| match output {
+            Output::Direct(media) => media,
+            Output::Auto(media) => media.media(),
+        })
+    }
+
+    fn publish(&mut self, frames: Vec<EsFrame>) {
+        for es in frames {
+            if self.output.is_none() {
+                self.output = Some(self.create_output());
+            }
+            let kind = TrackKind::from(es.codec);
+            let ts = if es.timed {
+                self.normalizer.normalize(kind, Some(es.dts), Some(es.pts))
+            } else {
+                // follows the stream's last frame
+                self.normalizer.normalize(kind, None, None)
+            };
+            let frame = Frame::new(es.codec, ts.dts, ts.pts, &es.data);
+            match self.output.as_ref() {
+                Some(Output::Direct(media)) => media.input_frame(&frame),
+                Some(Output::Auto(media)) => media.input_frame(&frame),
+                None => false,
+            };
+        }
+    }
+
+    fn create_output(&self) -> Output {
+        let Some(codecs) = self.demuxer.codecs().filter(|codecs| !codecs.is_empty()) else {
+            // no PSM: whatever shows up within the timeout
+            let options = AutoOptions {
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            };
+            return Output::Auto(Media::auto(
+                &self.vhost,
+                &self.app,
+                &self.stream,
+                false,
+                false,
+                options,
+            ));
+        };
+
+        let media = Media::new(&self.vhost, &self.app, &self.stream, 0.0, false, false);
+        for codec in codecs {
+            if codec.is_video() {
+                media.init_video(codec, 0, 0, 25.0, 0);
+            } else {
+                let (sample_rate, channels) = match codec {
+                    CodecId::AAC | CodecId::MP3 => (44100, 2),
+                    _ => (8000, 1),
+                };
+                media.init_audio(codec, sample_rate, channels, 0);
+            }
+        }
+        media.init_complete();
+        Output::Direct(Box::new(media))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ADTS frame of `len` bytes at 48 kHz.
+    fn adts(len: usize) -> Vec<u8> {
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x4C,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            (len << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.resize(len, 0);
+        frame
+    }
+
+    #[test]
+    fn splits_adts_timestamps() {
+        let payload = [adts(10), adts(12), adts(9)].concat();
+        let mut out = Vec::new();
+        // starts right before the 33 bit rollover
+        let start = (1 << 33) - 1000;
+        split_adts(&payload, start, start, true, &mut out);
+        let dts: Vec<u64> = out.iter().map(|frame| frame.dts).collect();
+        // 1024 samples at 48 kHz are 1920 ticks
+        assert_eq!(dts, [start, 920, 2840]);
+        assert_eq!(out[1].data.len(), 12);
+        assert!(out.iter().all(|frame| frame.timed));
+    }
+
+    #[test]
+    fn reads_pes_timestamps() {
+        let pes = [
+            0, 0, 1, 0xE0, 0, 0, 0x80, 0xC0, 10, // PTS and DTS
+            0x31, 0, 0x01, 0x1C, 0x21, // PTS 3600
+            0x11, 0, 0x01, 0x0E, 0x11, // DTS 1800
+            0xAB,
+        ];
+        assert_eq!(parse_pes(&pes), Some((Some(3600), Some(1800), &[0xAB][..])));
+        // header length beyond the packet
+        assert_eq!(parse_pes(&pes[..12]), None);
+
+        let mut last = None;
+        let mut out = Vec::new();
+        emit_pes(CodecId::H264, &pes, &mut last, &mut out);
+        assert_eq!(last, Some((3600, 1800)));
+        assert_eq!((out[0].pts, out[0].dts), (3600, 1800));
+    }
+}
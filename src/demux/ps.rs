@@ -0,0 +1,284 @@
+//! MPEG-PS (ISO 13818-1 program stream) demuxer, as sent by GB28181 devices:
+//! pack headers, system header, program stream map and PES.
+
+use std::collections::HashMap;
+
+use super::{emit_pes, parse_pes, stream_type_codec, Demux, EsFrame};
+use crate::obj::CodecId;
+
+const PACK_HEADER: u8 = 0xBA;
+const SYSTEM_HEADER: u8 = 0xBB;
+const STREAM_MAP: u8 = 0xBC;
+const PROGRAM_END: u8 = 0xB9;
+
+/// Frame being reassembled from PES packets of one stream id; large video
+/// frames are split over several PES, only the first carrying a PTS.
+#[derive(Debug)]
+struct Pending {
+    codec: CodecId,
+    header: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+/// Demuxes a program stream fed in any chunking.
+///
+/// Without a program stream map, `0xE0..` streams are taken as H264 (H265 if
+/// the first NAL header says so) and `0xC0..` as AAC if ADTS, G711A
+/// otherwise.
+#[derive(Debug, Default)]
+pub struct Demuxer {
+    buffer: Vec<u8>,
+    /// `stream_id` to codec, from the PSM.
+    stream_map: HashMap<u8, CodecId>,
+    has_map: bool,
+    pending: HashMap<u8, Pending>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the unit at the start of `data`, `None` if incomplete.
+    fn unit_len(data: &[u8]) -> Option<usize> {
+        match data[3] {
+            PACK_HEADER => {
+                let stuffing = (*data.get(13)? & 0x07) as usize;
+                Some(14 + stuffing)
+            }
+            PROGRAM_END => Some(4),
+            _ => Some(6 + ((*data.get(4)? as usize) << 8 | *data.get(5)? as usize)),
+        }
+    }
+
+    fn stream_map(&mut self, psm: &[u8]) {
+        // 6 byte header, 2 flag bytes, then program_stream_info
+        let Some(info_len) = psm.get(8..10) else {
+            return;
+        };
+        let info_len = (info_len[0] as usize) << 8 | info_len[1] as usize;
+        let Some(map) = psm.get(10 + info_len..) else {
+            return;
+        };
+        let Some(map_len) = map.get(..2) else {
+            return;
+        };
+        let map_len = (map_len[0] as usize) << 8 | map_len[1] as usize;
+        let mut entries = map.get(2..2 + map_len).unwrap_or_default();
+        while entries.len() >= 4 {
+            let es_info_len = (entries[2] as usize) << 8 | entries[3] as usize;
+            if let Some(codec) = stream_type_codec(entries[0]) {
+                self.stream_map.insert(entries[1], codec);
+            }
+            entries = entries.get(4 + es_info_len..).unwrap_or_default();
+        }
+        self.has_map = true;
+    }
+
+    fn guess_codec(stream_id: u8, payload: &[u8]) -> Option<CodecId> {
+        match stream_id {
+            0xE0..=0xEF => {
+                let nal = crate::nal::split_annexb(payload).next()?;
+                // H265 parameter sets and AUD read as reserved H264 types
+                let h265 = matches!((nal[0] >> 1) & 0x3F, 32..=35) && nal[0] & 0x81 == 0;
+                Some(if h265 { CodecId::H265 } else { CodecId::H264 })
+            }
+            0xC0..=0xDF => Some(
+                if payload.len() >= 2 && payload[0] == 0xFF && payload[1] & 0xF0 == 0xF0 {
+                    CodecId::AAC
+                } else {
+                    CodecId::G711A
+                },
+            ),
+            _ => None,
+        }
+    }
+
+    fn pes(&mut self, stream_id: u8, pes: &[u8], out: &mut Vec<EsFrame>) {
+        let Some((pts, _, payload)) = parse_pes(pes) else {
+            return;
+        };
+        if pts.is_some() {
+            self.finish(stream_id, out);
+            let codec = match self.stream_map.get(&stream_id) {
+                Some(codec) => *codec,
+                None => match Self::guess_codec(stream_id, payload) {
+                    Some(codec) => {
+                        self.stream_map.insert(stream_id, codec);
+                        codec
+                    }
+                    None => return,
+                },
+            };
+            let header_len = pes.len() - payload.len();
+            self.pending.insert(
+                stream_id,
+                Pending {
+                    codec,
+                    header: pes[..header_len].to_vec(),
+                    payload: payload.to_vec(),
+                },
+            );
+        } else if let Some(pending) = self.pending.get_mut(&stream_id) {
+            pending.payload.extend_from_slice(payload);
+        }
+    }
+
+    fn finish(&mut self, stream_id: u8, out: &mut Vec<EsFrame>) {
+        if let Some(mut pending) = self.pending.remove(&stream_id) {
+            pending.header.append(&mut pending.payload);
+            // pending frames start with a PES that has a PTS
+            emit_pes(pending.codec, &pending.header, &mut None, out);
+        }
+    }
+}
+
+impl Demux for Demuxer {
+    fn push(&mut self, data: &[u8]) -> Vec<EsFrame> {
+        let mut out = Vec::new();
+        self.buffer.extend_from_slice(data);
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut pos = 0;
+        while buffer.len() - pos >= 6 {
+            let unit = &buffer[pos..];
+            if unit[..3] != [0, 0, 1] || unit[3] < PROGRAM_END {
+                // resync on the next start code
+                pos += 1;
+                continue;
+            }
+            let Some(len) = Self::unit_len(unit).filter(|len| *len <= unit.len()) else {
+                break;
+            };
+            let unit = &unit[..len];
+            match unit[3] {
+                PACK_HEADER | SYSTEM_HEADER | PROGRAM_END => {}
+                STREAM_MAP => self.stream_map(unit),
+                stream_id @ (0xC0..=0xEF) => self.pes(stream_id, unit, &mut out),
+                _ => {}
+            }
+            pos += len;
+        }
+        self.buffer = buffer[pos..].to_vec();
+        out
+    }
+
+    fn flush(&mut self) -> Vec<EsFrame> {
+        let mut out = Vec::new();
+        let ids: Vec<u8> = self.pending.keys().copied().collect();
+        for stream_id in ids {
+            self.finish(stream_id, &mut out);
+        }
+        out
+    }
+
+    fn codecs(&self) -> Option<Vec<CodecId>> {
+        self.has_map
+            .then(|| self.stream_map.values().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bounded PES, with a PTS unless `pts` is `None`.
+    fn pes(stream_id: u8, pts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut header = match pts {
+            Some(ts) => vec![
+                0x80,
+                0x80,
+                5,
+                0x20 | ((ts >> 29) & 0x0E) as u8 | 1,
+                (ts >> 22) as u8,
+                ((ts >> 14) & 0xFE) as u8 | 1,
+                (ts >> 7) as u8,
+                ((ts << 1) & 0xFE) as u8 | 1,
+            ],
+            None => vec![0x80, 0x00, 0],
+        };
+        header.extend_from_slice(payload);
+        let mut pes = vec![0, 0, 1, stream_id];
+        pes.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        pes.extend(header);
+        pes
+    }
+
+    fn pack_header() -> Vec<u8> {
+        vec![0, 0, 1, PACK_HEADER, 0x44, 0, 4, 0, 4, 1, 0, 0, 3, 0xF8]
+    }
+
+    /// A PSM announcing H265 on `0xE0` and G711U on `0xC0`.
+    fn stream_map() -> Vec<u8> {
+        let body = [
+            0x80, 0x01, // flags
+            0x00, 0x00, // no program info
+            0x00, 0x08, // map length
+            0x24, 0xE0, 0x00, 0x00, // H265
+            0x91, 0xC0, 0x00, 0x00, // G711U
+            0, 0, 0, 0, // CRC
+        ];
+        let mut psm = vec![0, 0, 1, STREAM_MAP, 0, body.len() as u8];
+        psm.extend_from_slice(&body);
+        psm
+    }
+
+    const H264_IDR: [u8; 6] = [0, 0, 0, 1, 0x65, 0x88];
+
+    #[test]
+    fn takes_codecs_from_stream_map() {
+        let mut demuxer = Demuxer::new();
+        let mut ps = pack_header();
+        ps.extend(stream_map());
+        // would be guessed as H264 and G711A
+        ps.extend(pes(0xE0, Some(3600), &H264_IDR));
+        ps.extend(pes(0xC0, Some(3600), &[0xD5; 160]));
+        assert!(demuxer.push(&ps).is_empty());
+        let mut codecs = demuxer.codecs().unwrap();
+        codecs.sort();
+        let mut expected = vec![CodecId::H265, CodecId::G711U];
+        expected.sort();
+        assert_eq!(codecs, expected);
+
+        let mut frames = demuxer.flush();
+        frames.sort_by_key(|frame| frame.codec.is_video());
+        assert_eq!(frames[0].codec, CodecId::G711U);
+        assert_eq!(frames[1].codec, CodecId::H265);
+    }
+
+    #[test]
+    fn guesses_codecs_without_stream_map() {
+        let mut demuxer = Demuxer::new();
+        let vps = [0, 0, 0, 1, 0x40, 0x01, 0x0C];
+        let adts = [0xFF, 0xF1, 0x50, 0x80, 0x01, 0x1F, 0xFC, 0x21];
+        let mut ps = pack_header();
+        ps.extend(pes(0xE0, Some(3600), &vps));
+        ps.extend(pes(0xC0, Some(3600), &adts));
+        ps.extend(pes(0xE1, Some(3600), &H264_IDR));
+        ps.extend(pes(0xC1, Some(3600), &[0xD5; 160]));
+        demuxer.push(&ps);
+        assert_eq!(demuxer.codecs(), None);
+
+        let mut codecs: Vec<CodecId> = demuxer.flush().iter().map(|frame| frame.codec).collect();
+        codecs.sort();
+        let mut expected = vec![CodecId::H265, CodecId::AAC, CodecId::H264, CodecId::G711A];
+        expected.sort();
+        assert_eq!(codecs, expected);
+    }
+
+    #[test]
+    fn joins_frame_split_over_pes() {
+        let mut demuxer = Demuxer::new();
+        let mut ps = pack_header();
+        ps.extend(pes(0xE0, Some(3600), &H264_IDR));
+        ps.extend(pes(0xE0, None, &[0x11; 4]));
+        // split mid-PES
+        assert!(demuxer.push(&ps[..20]).is_empty());
+        assert!(demuxer.push(&ps[20..]).is_empty());
+
+        let frames = demuxer.push(&pes(0xE0, Some(7200), &H264_IDR));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].pts, 3600);
+        assert_eq!(frames[0].data, [&H264_IDR[..], &[0x11; 4]].concat());
+        assert_eq!(demuxer.flush()[0].pts, 7200);
+    }
+}
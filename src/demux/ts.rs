@@ -0,0 +1,337 @@
+//! MPEG-TS (ISO 13818-1) demuxer: PAT, PMT and PES of the first program.
+
+use std::collections::HashMap;
+
+use super::{emit_pes, stream_type_codec, Demux, EsFrame};
+use crate::obj::CodecId;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+#[derive(Debug)]
+struct Pes {
+    codec: CodecId,
+    data: Vec<u8>,
+    continuity: Option<u8>,
+    /// `(pts, dts)` of the last timed PES.
+    last: Option<(u64, u64)>,
+}
+
+/// Demuxes 188 byte transport stream packets, fed in any chunking.
+#[derive(Debug, Default)]
+pub struct Demuxer {
+    buffer: Vec<u8>,
+    pmt_pid: Option<u16>,
+    /// Set once the PMT was seen.
+    codecs: Option<Vec<CodecId>>,
+    streams: HashMap<u16, Pes>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn packet(&mut self, packet: &[u8], out: &mut Vec<EsFrame>) {
+        let start = packet[1] & 0x40 != 0;
+        let pid = (packet[1] as u16 & 0x1F) << 8 | packet[2] as u16;
+        let adaptation = (packet[3] >> 4) & 0x03;
+        let continuity = packet[3] & 0x0F;
+        if adaptation & 0x01 == 0 {
+            return;
+        }
+        let mut offset = 4;
+        if adaptation & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        let Some(payload) = packet.get(offset..) else {
+            return;
+        };
+
+        if pid == 0 {
+            self.pat(psi_section(payload, start));
+        } else if Some(pid) == self.pmt_pid {
+            self.pmt(psi_section(payload, start));
+        } else if let Some(pes) = self.streams.get_mut(&pid) {
+            let expected = pes.continuity.map(|last| (last + 1) & 0x0F);
+            pes.continuity = Some(continuity);
+            if start {
+                if !pes.data.is_empty() {
+                    emit_pes(
+                        pes.codec,
+                        &std::mem::take(&mut pes.data),
+                        &mut pes.last,
+                        out,
+                    );
+                }
+            } else if pes.data.is_empty() || expected != Some(continuity) {
+                // lost the start of this PES
+                pes.data.clear();
+                return;
+            }
+            pes.data.extend_from_slice(payload);
+
+            // bounded PES (usually audio) are done as soon as they are full
+            if pes.data.len() >= 6 {
+                let len = (pes.data[4] as usize) << 8 | pes.data[5] as usize;
+                if len != 0 && pes.data.len() >= len + 6 {
+                    pes.data.truncate(len + 6);
+                    emit_pes(
+                        pes.codec,
+                        &std::mem::take(&mut pes.data),
+                        &mut pes.last,
+                        out,
+                    );
+                }
+            }
+        }
+    }
+
+    fn pat(&mut self, section: Option<&[u8]>) {
+        let Some(section) = section.filter(|section| section[0] == 0x00) else {
+            return;
+        };
+        self.pmt_pid = section_body(section, 8)
+            .chunks_exact(4)
+            .find(|entry| entry[0] != 0 || entry[1] != 0) // program 0 is the NIT
+            .map(|entry| (entry[2] as u16 & 0x1F) << 8 | entry[3] as u16);
+    }
+
+    fn pmt(&mut self, section: Option<&[u8]>) {
+        let Some(section) = section.filter(|section| section[0] == 0x02 && section.len() >= 12)
+        else {
+            return;
+        };
+        let info_len = (section[10] as usize & 0x0F) << 8 | section[11] as usize;
+        let mut entries = section_body(section, 12 + info_len);
+        let mut codecs = Vec::new();
+        while entries.len() >= 5 {
+            let pid = (entries[1] as u16 & 0x1F) << 8 | entries[2] as u16;
+            let es_info_len = (entries[3] as usize & 0x0F) << 8 | entries[4] as usize;
+            if let Some(codec) = stream_type_codec(entries[0]) {
+                codecs.push(codec);
+                self.streams
+                    .entry(pid)
+                    .and_modify(|pes| pes.codec = codec)
+                    .or_insert(Pes {
+                        codec,
+                        data: Vec::new(),
+                        continuity: None,
+                        last: None,
+                    });
+            }
+            entries = entries.get(5 + es_info_len..).unwrap_or_default();
+        }
+        self.codecs = Some(codecs);
+    }
+}
+
+/// The section starting in this packet, if any. Sections spanning packets
+/// are not reassembled; PAT and PMT fit in one.
+fn psi_section(payload: &[u8], start: bool) -> Option<&[u8]> {
+    if !start {
+        return None;
+    }
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let len = (*section.get(1)? as usize & 0x0F) << 8 | *section.get(2)? as usize;
+    section.get(..3 + len).filter(|section| section.len() >= 12)
+}
+
+/// Section bytes from `start` up to the CRC.
+fn section_body(section: &[u8], start: usize) -> &[u8] {
+    section.get(start..section.len() - 4).unwrap_or_default()
+}
+
+impl Demux for Demuxer {
+    fn push(&mut self, data: &[u8]) -> Vec<EsFrame> {
+        let mut out = Vec::new();
+        self.buffer.extend_from_slice(data);
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let mut pos = 0;
+        while buffer.len() - pos >= PACKET_SIZE {
+            if buffer[pos] != SYNC_BYTE {
+                // resync on the next sync byte
+                pos += 1;
+                continue;
+            }
+            self.packet(&buffer[pos..pos + PACKET_SIZE], &mut out);
+            pos += PACKET_SIZE;
+        }
+        buffer.drain(..pos);
+        self.buffer = buffer;
+        out
+    }
+
+    fn flush(&mut self) -> Vec<EsFrame> {
+        let mut out = Vec::new();
+        for pes in self.streams.values_mut() {
+            if !pes.data.is_empty() {
+                emit_pes(
+                    pes.codec,
+                    &std::mem::take(&mut pes.data),
+                    &mut pes.last,
+                    &mut out,
+                );
+            }
+        }
+        out
+    }
+
+    fn codecs(&self) -> Option<Vec<CodecId>> {
+        self.codecs.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO_PID: u16 = 0x101;
+    const AUDIO_PID: u16 = 0x102;
+
+    /// One transport packet, padded with an adaptation field.
+    fn packet(pid: u16, start: bool, continuity: u8, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() <= 184);
+        let mut packet = vec![SYNC_BYTE, (start as u8) << 6 | (pid >> 8) as u8, pid as u8];
+        if payload.len() == 184 {
+            packet.push(0x10 | continuity);
+        } else {
+            packet.push(0x30 | continuity);
+            let stuffing = 183 - payload.len();
+            packet.push(stuffing as u8);
+            if stuffing > 0 {
+                packet.push(0);
+                packet.resize(packet.len() + stuffing - 1, 0xFF);
+            }
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A PSI section with a dummy CRC, behind a zero pointer field.
+    fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = 5 + body.len() + 4;
+        let mut section = vec![0, table_id, 0xB0 | (len >> 8) as u8, len as u8];
+        section.extend_from_slice(&[0x00, 0x01, 0xC1, 0x00, 0x00]);
+        section.extend_from_slice(body);
+        section.extend_from_slice(&[0; 4]);
+        section
+    }
+
+    fn program_tables() -> Vec<u8> {
+        // program 1 on PID 0x100
+        let mut ts = packet(0, true, 0, &section(0x00, &[0x00, 0x01, 0xE1, 0x00]));
+        let pmt = [
+            0xE1, 0x01, 0xF0, 0x00, // PCR PID, no program info
+            0x1B, 0xE1, 0x01, 0xF0, 0x00, // H264
+            0x0F, 0xE1, 0x02, 0xF0, 0x00, // AAC
+        ];
+        ts.extend(packet(0x100, true, 0, &section(0x02, &pmt)));
+        ts
+    }
+
+    fn timestamp(marker: u8, ts: u64) -> [u8; 5] {
+        [
+            marker << 4 | ((ts >> 29) & 0x0E) as u8 | 1,
+            (ts >> 22) as u8,
+            ((ts >> 14) & 0xFE) as u8 | 1,
+            (ts >> 7) as u8,
+            ((ts << 1) & 0xFE) as u8 | 1,
+        ]
+    }
+
+    /// An unbounded video PES, with a PTS unless `pts` is `None`.
+    fn pes(pts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xE0, 0, 0, 0x80];
+        match pts {
+            Some(pts) => {
+                pes.extend_from_slice(&[0x80, 5]);
+                pes.extend_from_slice(&timestamp(0x2, pts));
+            }
+            None => pes.extend_from_slice(&[0x00, 0]),
+        }
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    #[test]
+    fn reads_codecs_from_pmt() {
+        let mut demuxer = Demuxer::new();
+        assert_eq!(demuxer.codecs(), None);
+        // split mid-packet
+        let tables = program_tables();
+        assert!(demuxer.push(&tables[..100]).is_empty());
+        assert!(demuxer.push(&tables[100..]).is_empty());
+        assert_eq!(demuxer.codecs(), Some(vec![CodecId::H264, CodecId::AAC]));
+    }
+
+    #[test]
+    fn joins_pes_split_across_packets() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(&program_tables());
+        let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let first = pes(Some(900_000), &payload);
+        let mut ts = packet(VIDEO_PID, true, 0, &first[..184]);
+        ts.extend(packet(VIDEO_PID, false, 1, &first[184..]));
+        assert!(demuxer.push(&ts).is_empty());
+
+        // the next PES start finishes the first
+        let frames = demuxer.push(&packet(VIDEO_PID, true, 2, &pes(Some(903_600), &[9; 8])));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].codec, CodecId::H264);
+        assert_eq!((frames[0].dts, frames[0].pts), (900_000, 900_000));
+        assert!(frames[0].timed);
+        assert_eq!(frames[0].data, payload);
+        assert_eq!(demuxer.flush()[0].data, [9; 8]);
+    }
+
+    #[test]
+    fn drops_pes_after_continuity_gap() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(&program_tables());
+        let first = pes(Some(0), &[1; 300]);
+        let mut ts = packet(VIDEO_PID, true, 0, &first[..184]);
+        // continuity counter 1 was lost
+        ts.extend(packet(VIDEO_PID, false, 2, &first[184..]));
+        ts.extend(packet(VIDEO_PID, true, 3, &pes(Some(3600), &[2; 8])));
+        assert!(demuxer.push(&ts).is_empty());
+        let frames = demuxer.flush();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].pts, 3600);
+    }
+
+    #[test]
+    fn passes_on_pes_without_pts() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(&program_tables());
+        let mut ts = packet(VIDEO_PID, true, 0, &pes(Some(3600), &[1; 8]));
+        ts.extend(packet(VIDEO_PID, true, 1, &pes(None, &[2; 8])));
+        ts.extend(packet(VIDEO_PID, true, 2, &pes(Some(10_800), &[3; 8])));
+        let frames = demuxer.push(&ts);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].timed);
+        assert!(!frames[1].timed);
+        assert_eq!(
+            (frames[1].pts, frames[1].data.as_slice()),
+            (3600, &[2; 8][..])
+        );
+        assert!(demuxer.flush()[0].timed);
+    }
+
+    #[test]
+    fn finishes_bounded_audio_pes() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(&program_tables());
+        // one ADTS frame of 16 bytes
+        let mut adts = vec![0xFF, 0xF1, 0x50, 0x80, 0x02, 0x1F, 0xFC];
+        adts.resize(16, 0xAA);
+        let mut audio = vec![0, 0, 1, 0xC0, 0, (3 + 5 + adts.len()) as u8, 0x80, 0x80, 5];
+        audio.extend_from_slice(&timestamp(0x2, 1800));
+        audio.extend_from_slice(&adts);
+        let frames = demuxer.push(&packet(AUDIO_PID, true, 0, &audio));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].codec, CodecId::AAC);
+        assert_eq!(frames[0].data, adts);
+    }
+}
@@ -7,7 +7,9 @@ use std::{
 };
 
 use crate::{
+    config::{ConfigDiff, ServerConfig},
     const_ptr_to_string, const_str_to_ptr,
+    init::LogLevel,
     obj::{AuthInvoker, MediaInfo, MediaSource, Parser, RecordInfo, RtcTransport, SockInfo},
 };
 
@@ -16,7 +18,7 @@ pub static EVENTS: Lazy<RwLock<Event>> = Lazy::new(|| RwLock::new(Event::new()))
 #[derive(Default)]
 pub struct Event {
     inner: mk_events,
-    on_media_changed: Option<Arc<dyn Fn(MediaChangedMessage) + Sync + Send>>,
+    pub(crate) on_media_changed: Option<Arc<dyn Fn(MediaChangedMessage) + Sync + Send>>,
     on_media_publish: Option<Arc<dyn Fn(MediaPublishMessage) + Sync + Send>>,
     on_media_not_found: Option<Arc<dyn Fn(MediaNotFoundMessage) -> bool + Sync + Send>>,
     on_media_play: Option<Arc<dyn Fn(MediaPlayMessage) -> anyhow::Result<()> + Sync + Send>>,
@@ -29,14 +31,15 @@ pub struct Event {
     on_record_ts: Option<Arc<dyn Fn(RecordTsMessage) + Sync + Send>>,
     on_shell_login: Option<Arc<dyn Fn(ShellLoginMessage) + Sync + Send>>,
     on_flow_report: Option<Arc<dyn Fn(FlowReportMessage) + Sync + Send>>,
-    on_log: Option<Arc<dyn Fn(LogMessage) + Sync + Send>>,
+    pub(crate) on_log: Option<Arc<dyn Fn(LogMessage) + Sync + Send>>,
     on_media_send_rtp_stop: Option<Arc<dyn Fn(MediaSendRtpStopMessage) + Sync + Send>>,
-    on_rtc_sctp_connecting: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
-    on_rtc_sctp_connected: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
-    on_rtc_sctp_failed: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
-    on_rtc_sctp_closed: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
-    on_rtc_sctp_send: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
-    on_rtc_sctp_received: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_connecting: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_connected: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_failed: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_closed: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_send: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    pub(crate) on_rtc_sctp_received: Option<Arc<dyn Fn(RtcSctpStateMessage) + Sync + Send>>,
+    on_config_changed: Option<Arc<dyn Fn(ConfigChangedMessage) + Sync + Send>>,
 }
 
 impl Event {
@@ -204,6 +207,40 @@ impl Event {
         self.inner.on_mk_rtc_sctp_failed = Some(on_mk_rtc_sctp_failed);
         self.subscribe();
     }
+
+    /// Fired by [`config::reload_from_file`](crate::config::reload_from_file)
+    /// after changed values were applied to the global ini.
+    pub fn on_config_changed(&mut self, cb: impl Fn(ConfigChangedMessage) + Sync + Send + 'static) {
+        self.on_config_changed = Some(Arc::new(cb));
+    }
+
+    /// Hooks the events [`metrics`](crate::metrics) counts, whether or not a
+    /// callback is registered for them. Their trampolines are no-ops without one.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn observe_for_metrics(&mut self) {
+        self.inner.on_mk_media_changed = Some(on_mk_media_changed);
+        self.inner.on_mk_media_no_reader = Some(on_mk_media_no_reader);
+        self.inner.on_mk_flow_report = Some(on_mk_flow_report);
+        self.inner.on_mk_record_mp4 = Some(on_mk_record_mp4);
+        self.inner.on_mk_record_ts = Some(on_mk_record_ts);
+        self.subscribe();
+    }
+}
+
+pub struct ConfigChangedMessage {
+    /// Global config before the reload.
+    pub old: ServerConfig,
+    /// Global config after the reload.
+    pub new: ServerConfig,
+    /// The values that were applied.
+    pub changes: Vec<ConfigDiff>,
+}
+
+pub(crate) fn emit_config_changed(msg: ConfigChangedMessage) {
+    let cb = EVENTS.read().unwrap().on_config_changed.clone();
+    if let Some(cb) = cb {
+        cb(msg);
+    }
 }
 
 pub enum RtcSctpStateMessage {
@@ -215,6 +252,21 @@ pub enum RtcSctpStateMessage {
     Received(RtcTransport, u16, u32, Vec<u8>),
 }
 
+impl RtcSctpStateMessage {
+    /// The transport the event belongs to; use [`RtcTransport::id`] to
+    /// correlate events of the same session.
+    pub fn transport(&self) -> &RtcTransport {
+        match self {
+            RtcSctpStateMessage::Connecting(t)
+            | RtcSctpStateMessage::Connected(t)
+            | RtcSctpStateMessage::Closed(t)
+            | RtcSctpStateMessage::Failed(t)
+            | RtcSctpStateMessage::Send(t, _)
+            | RtcSctpStateMessage::Received(t, _, _, _) => t,
+        }
+    }
+}
+
 extern "C" fn on_mk_rtc_sctp_failed(rtc_transport: mk_rtc_transport) {
     crate::ffi_guard(|| {
         let cb = EVENTS.read().unwrap().on_rtc_sctp_failed.clone();
@@ -328,6 +380,7 @@ extern "C" fn on_mk_media_send_rtp_stop(
     });
 }
 
+#[derive(Clone)]
 pub struct LogMessage {
     pub level: i32,
     pub file: String,
@@ -336,6 +389,13 @@ pub struct LogMessage {
     pub message: String,
 }
 
+impl LogMessage {
+    /// `level` as a [`LogLevel`]; unknown values map to `Error`.
+    pub fn log_level(&self) -> LogLevel {
+        LogLevel::try_from(self.level).unwrap_or(LogLevel::Error)
+    }
+}
+
 extern "C" fn on_mk_log(
     level: ::std::os::raw::c_int,
     file: *const ::std::os::raw::c_char,
@@ -381,15 +441,18 @@ extern "C" fn on_mk_flow_report(
     sender: mk_sock_info,
 ) {
     crate::ffi_guard(|| {
+        let msg = FlowReportMessage {
+            url_info: url_info.into(),
+            total_bytes,
+            total_seconds,
+            is_player: is_player != 0,
+            sender: sender.into(),
+        };
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_flow_report(&msg);
         let cb = EVENTS.read().unwrap().on_flow_report.clone();
         if let Some(cb) = cb {
-            cb(FlowReportMessage {
-                url_info: url_info.into(),
-                total_bytes,
-                total_seconds,
-                is_player: is_player != 0,
-                sender: sender.into(),
-            })
+            cb(msg)
         }
     });
 }
@@ -397,10 +460,52 @@ extern "C" fn on_mk_flow_report(
 pub struct ShellLoginMessage {
     pub user_name: String,
     pub passwd: String,
-    pub invoker: AuthInvoker,
+    pub invoker: ShellLoginInvoker,
     pub sender: SockInfo,
 }
 
+impl ShellLoginMessage {
+    pub fn allow(&self) {
+        self.invoker.allow()
+    }
+
+    pub fn deny(&self, reason: &str) {
+        self.invoker.deny(reason)
+    }
+}
+
+/// Answers a shell login; exactly one of `allow`/`deny`/`check` must be called,
+/// otherwise the telnet session waits forever. Clone it to answer after the
+/// callback has returned.
+#[derive(Debug, Clone)]
+pub struct ShellLoginInvoker(AuthInvoker);
+
+impl ShellLoginInvoker {
+    pub fn allow(&self) {
+        self.0.allow()
+    }
+
+    pub fn deny(&self, reason: &str) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_auth_denied("shell");
+        self.0.deny(reason)
+    }
+
+    /// Allows on `Ok`, denies with the error message on `Err`.
+    pub fn check(&self, result: anyhow::Result<()>) {
+        match result {
+            Ok(_) => self.allow(),
+            Err(e) => self.deny(&e.to_string()),
+        }
+    }
+}
+
+impl From<mk_auth_invoker> for ShellLoginInvoker {
+    fn from(inner: mk_auth_invoker) -> Self {
+        Self(AuthInvoker::from(inner))
+    }
+}
+
 extern "C" fn on_mk_shell_login(
     user_name: *const ::std::os::raw::c_char,
     passwd: *const ::std::os::raw::c_char,
@@ -419,7 +524,7 @@ extern "C" fn on_mk_shell_login(
             cb(ShellLoginMessage {
                 user_name: u,
                 passwd: p,
-                invoker: AuthInvoker::from(invoker),
+                invoker: ShellLoginInvoker::from(invoker),
                 sender: SockInfo::from(sender),
             })
         }
@@ -432,11 +537,12 @@ pub struct RecordTsMessage {
 
 extern "C" fn on_mk_record_ts(ts: mk_record_info) {
     crate::ffi_guard(|| {
+        let ts = RecordInfo::from(ts);
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_record(&ts, "ts");
         let cb = EVENTS.read().unwrap().on_record_ts.clone();
         if let Some(cb) = cb {
-            cb(RecordTsMessage {
-                ts: RecordInfo::from(ts),
-            })
+            cb(RecordTsMessage { ts })
         }
     });
 }
@@ -447,11 +553,12 @@ pub struct RecordMp4Message {
 
 extern "C" fn on_mk_record_mp4(mp4: mk_record_info) {
     crate::ffi_guard(|| {
+        let mp4 = RecordInfo::from(mp4);
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_record(&mp4, "mp4");
         let cb = EVENTS.read().unwrap().on_record_mp4.clone();
         if let Some(cb) = cb {
-            cb(RecordMp4Message {
-                mp4: RecordInfo::from(mp4),
-            })
+            cb(RecordMp4Message { mp4 })
         }
     });
 }
@@ -670,8 +777,29 @@ impl HttpResponseInvoker {
             mk_http_response_invoker_do_string(self.0, code, p_argv.as_mut_ptr(), body.as_ptr())
         }
     }
+
+    /// Like [`invoke`](Self::invoke), for binary bodies.
+    pub fn invoke_bytes(&self, code: i32, headers: Vec<String>, body: &[u8]) {
+        let cstr_argv: Vec<_> = headers
+            .iter()
+            .map(|arg| std::ffi::CString::new(arg.as_str()).unwrap())
+            .collect();
+
+        let mut p_argv: Vec<_> = cstr_argv.iter().map(|arg| arg.as_ptr()).collect();
+        p_argv.push(std::ptr::null_mut());
+
+        unsafe {
+            let body = mk_http_body_from_string(body.as_ptr() as *const _, body.len());
+            mk_http_response_invoker_do(self.0, code, p_argv.as_mut_ptr(), body);
+            mk_http_body_release(body);
+        }
+    }
 }
 
+// a cloned invoker may be answered from any thread
+unsafe impl Send for HttpResponseInvoker {}
+unsafe impl Sync for HttpResponseInvoker {}
+
 impl From<mk_http_response_invoker> for HttpResponseInvoker {
     fn from(invoker: mk_http_response_invoker) -> Self {
         HttpResponseInvoker(invoker, false)
@@ -699,6 +827,8 @@ pub(crate) extern "C" fn on_mk_media_changed(
     sender: mk_media_source,
 ) {
     crate::ffi_guard(|| {
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_media_changed(regist != 0, &sender.into());
         let cb = EVENTS.read().unwrap().on_media_changed.clone();
         if let Some(cb) = cb {
             match regist {
@@ -717,6 +847,8 @@ pub enum MediaChangedMessage {
 
 pub(crate) extern "C" fn on_mk_media_no_reader(sender: mk_media_source) {
     crate::ffi_guard(|| {
+        #[cfg(feature = "metrics")]
+        crate::metrics::on_media_no_reader(&sender.into());
         let cb = EVENTS.read().unwrap().on_media_no_reader.clone();
         if let Some(cb) = cb {
             cb(MediaNoReaderMessage {
@@ -771,7 +903,11 @@ pub(crate) extern "C" fn on_mk_media_play(
                 sender: sock_info,
             }) {
                 Ok(_) => invoker.allow(),
-                Err(e) => invoker.deny(&format!("on_media_play callback error: {:?}", e)),
+                Err(e) => {
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::on_auth_denied("play");
+                    invoker.deny(&format!("on_media_play callback error: {:?}", e))
+                }
             }
         } else {
             invoker.allow()
@@ -812,6 +948,10 @@ pub struct PublishAuthInvoker(mk_publish_auth_invoker, bool);
 
 impl PublishAuthInvoker {
     pub fn call(&self, err_msg: &str, enable_mp4: bool, enable_hls: bool) -> anyhow::Result<()> {
+        #[cfg(feature = "metrics")]
+        if !err_msg.is_empty() {
+            crate::metrics::on_auth_denied("publish");
+        }
         unsafe {
             mk_publish_auth_invoker_do(
                 self.0,
@@ -825,6 +965,10 @@ impl PublishAuthInvoker {
 
     #[allow(dead_code)]
     pub fn call_with_config(&self, err_msg: &str) -> anyhow::Result<()> {
+        #[cfg(feature = "metrics")]
+        if !err_msg.is_empty() {
+            crate::metrics::on_auth_denied("publish");
+        }
         unsafe {
             let init = mk_ini_default();
             mk_publish_auth_invoker_do2(self.0, CString::new(err_msg)?.as_ptr(), init)
@@ -0,0 +1,604 @@
+//! One published stream fed from an ordered list of inputs, switching to the
+//! next one when the active input closes or stalls.
+//!
+//! Readers stay connected to the output stream across switches: timestamps
+//! continue where the previous input ended (see
+//! [`Splicer`](crate::timestamp::Splicer)), and the new input's codec config
+//! (SPS/PPS/VPS) is sent again ahead of its first keyframe. An input counts as
+//! up once it delivers a keyframe; with [`switch_back`](StreamBuilder::switch_back)
+//! a recovered input of higher priority takes over again at that keyframe.
+//!
+//! ```ignore
+//! let stream = failover::Stream::builder(DEFAULT_VHOST, "live", "main")
+//!     .input(Input::push("ingest", "main"))
+//!     .input(Input::pull("rtmp://backup/live/main"))
+//!     .input(Input::file("slate.mp4"))
+//!     .on_change(|change| println!("{:?}", change))
+//!     .start()?;
+//! ```
+
+use once_cell::sync::Lazy;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, Once,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event::{MediaChangedMessage, EVENTS},
+    frame::Frame,
+    media::{AutoMedia, AutoOptions, Media},
+    mp4::{Clip, Due, PacedFile},
+    mux::Tap,
+    obj::CodecId,
+    player::HelperPull,
+    timestamp::Splicer,
+};
+
+/// Streams with push inputs, told when a media source unregisters.
+static WATCHERS: Lazy<Mutex<Vec<mpsc::Sender<Event>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static LISTEN: Once = Once::new();
+
+/// Registers on the `on_media_changed` event of [`EVENTS`], once, so push
+/// inputs go down as soon as their stream closes. Handlers set there before
+/// are still called, ahead of this one; set later, they replace it and push
+/// inputs are only taken down by the stall timeout.
+fn watch_unregister(sender: mpsc::Sender<Event>) {
+    LISTEN.call_once(|| {
+        let mut events = EVENTS.write().unwrap();
+        let previous = events.on_media_changed.clone();
+        events.on_media_changed(move |msg| {
+            let source = match &msg {
+                MediaChangedMessage::UnRegist(source) => {
+                    Some((source.vhost(), source.app(), source.stream()))
+                }
+                MediaChangedMessage::Regist(_) => None,
+            };
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            if let Some((vhost, app, stream)) = source {
+                // streams that were dropped have closed their receiver
+                WATCHERS.lock().unwrap().retain(|sender| {
+                    let event = Event::Unregistered {
+                        vhost: vhost.clone(),
+                        app: app.clone(),
+                        stream: stream.clone(),
+                    };
+                    sender.send(event).is_ok()
+                });
+            }
+        });
+    });
+    WATCHERS.lock().unwrap().push(sender);
+}
+
+/// How often taps are looked up and stalls checked.
+const TICK: Duration = Duration::from_millis(250);
+
+/// No active input, in [`Stream::active`]'s atomic.
+const NONE: usize = usize::MAX;
+
+/// A source of frames for a [`Stream`].
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// A stream published on the same vhost, e.g. an RTMP or RTSP push.
+    Push { app: String, stream: String },
+    /// Any URL a [`ProxyPlayer`](crate::player::ProxyPlayer) can pull;
+    /// pulled again after it closes.
+    Pull(String),
+    /// MP4 file played in a loop, e.g. a slate of last resort. Always up.
+    File(PathBuf),
+}
+
+impl Input {
+    pub fn push(app: &str, stream: &str) -> Self {
+        Input::Push {
+            app: app.to_string(),
+            stream: stream.to_string(),
+        }
+    }
+
+    pub fn pull(url: &str) -> Self {
+        Input::Pull(url.to_string())
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Input::File(path.into())
+    }
+}
+
+/// Reported through [`StreamBuilder::on_change`]; inputs by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateChange {
+    /// The input delivered a keyframe after being down.
+    Up(usize),
+    /// The input closed or stalled.
+    Down(usize),
+    /// Output frames now come from this input.
+    Active(usize),
+    /// No input is up; readers stay connected but get no frames.
+    Lost,
+}
+
+type OnChange = Box<dyn Fn(StateChange) + Send + 'static>;
+
+pub struct StreamBuilder {
+    vhost: String,
+    app: String,
+    stream: String,
+    inputs: Vec<Input>,
+    stall_timeout: Duration,
+    retry_interval: Duration,
+    switch_back: bool,
+    hls: bool,
+    mp4: bool,
+    options: AutoOptions,
+    on_change: Option<OnChange>,
+}
+
+impl StreamBuilder {
+    /// Adds an input; earlier inputs have priority.
+    pub fn input(mut self, input: Input) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// An input without frames for this long is down. Default 3 seconds.
+    /// A push input also goes down as soon as its stream unregisters.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// Wait before pulling a closed [`Input::Pull`] again. Default 5 seconds.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Go back to an input of higher priority once it is up again, rather
+    /// than staying on the active one until it fails. Default true.
+    pub fn switch_back(mut self, switch_back: bool) -> Self {
+        self.switch_back = switch_back;
+        self
+    }
+
+    pub fn hls_enabled(mut self, enabled: bool) -> Self {
+        self.hls = enabled;
+        self
+    }
+
+    pub fn mp4_enabled(mut self, enabled: bool) -> Self {
+        self.mp4 = enabled;
+        self
+    }
+
+    /// Whether the output has video and audio. The codecs are those of the
+    /// first input that comes up; inputs are not transcoded, so one with
+    /// other codecs cannot be switched to cleanly.
+    pub fn tracks(mut self, video: bool, audio: bool) -> Self {
+        self.options.video = video;
+        self.options.audio = audio;
+        self
+    }
+
+    /// Called on the stream's thread for every state change.
+    pub fn on_change(mut self, cb: impl Fn(StateChange) + Send + 'static) -> Self {
+        self.on_change = Some(Box::new(cb));
+        self
+    }
+
+    pub fn start(self) -> anyhow::Result<Stream> {
+        if self.inputs.is_empty() {
+            anyhow::bail!("failover stream without inputs");
+        }
+        let slots = self
+            .inputs
+            .into_iter()
+            .map(|input| {
+                let feed = match &input {
+                    Input::File(path) => Feed::File(PacedFile::open(&Clip::new(path), true)?),
+                    Input::Push { .. } => Feed::Push { tap: None },
+                    Input::Pull(_) => Feed::Pull {
+                        pull: None,
+                        retry_at: Instant::now(),
+                    },
+                };
+                Ok(Slot {
+                    up: matches!(input, Input::File(_)),
+                    input,
+                    feed,
+                    generation: 0,
+                    last_frame: Instant::now(),
+                    config: Vec::new(),
+                    in_config: false,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (events, receiver) = mpsc::channel();
+        if slots
+            .iter()
+            .any(|slot| matches!(slot.input, Input::Push { .. }))
+        {
+            watch_unregister(events.clone());
+        }
+
+        let active = Arc::new(AtomicUsize::new(NONE));
+        let video = self.options.video;
+        let runner = Runner {
+            video,
+            media: Media::auto(
+                &self.vhost,
+                &self.app,
+                &self.stream,
+                self.hls,
+                self.mp4,
+                self.options,
+            ),
+            splicer: Splicer::new(video),
+            vhost: self.vhost,
+            stream: self.stream,
+            slots,
+            current: None,
+            needs_config: false,
+            stall_timeout: self.stall_timeout,
+            retry_interval: self.retry_interval,
+            switch_back: self.switch_back,
+            on_change: self.on_change,
+            active: active.clone(),
+            events: receiver,
+            sender: events.clone(),
+        };
+        let thread = std::thread::Builder::new()
+            .name("failover".to_string())
+            .spawn(move || runner.run())?;
+        Ok(Stream {
+            events,
+            active,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Publishes the first healthy of its inputs, on its own thread, until
+/// dropped.
+pub struct Stream {
+    events: mpsc::Sender<Event>,
+    active: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Stream {
+    pub fn builder(vhost: &str, app: &str, stream: &str) -> StreamBuilder {
+        StreamBuilder {
+            vhost: vhost.to_string(),
+            app: app.to_string(),
+            stream: stream.to_string(),
+            inputs: Vec::new(),
+            stall_timeout: Duration::from_secs(3),
+            retry_interval: Duration::from_secs(5),
+            switch_back: true,
+            hls: false,
+            mp4: false,
+            options: AutoOptions::default(),
+            on_change: None,
+        }
+    }
+
+    /// Index of the input output frames come from.
+    pub fn active(&self) -> Option<usize> {
+        match self.active.load(Ordering::Relaxed) {
+            NONE => None,
+            index => Some(index),
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = self.events.send(Event::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+enum Event {
+    /// A frame of input `index`, from the tap of `generation`.
+    Frame(usize, u64, Frame),
+    /// The player of input `index`, `generation`, closed.
+    Closed(usize, u64),
+    /// A media source unregistered; push inputs reading it are down.
+    Unregistered {
+        vhost: String,
+        app: String,
+        stream: String,
+    },
+    Stop,
+}
+
+enum Feed {
+    Push {
+        tap: Option<Tap>,
+    },
+    Pull {
+        pull: Option<HelperPull>,
+        retry_at: Instant,
+    },
+    /// Paced from when the file became active.
+    File(PacedFile),
+}
+
+struct Slot {
+    input: Input,
+    feed: Feed,
+    up: bool,
+    /// Bumped on every (re)attach, so frames of old taps are told apart.
+    generation: u64,
+    last_frame: Instant,
+    /// Latest run of config frames, sent again on a switch.
+    config: Vec<Frame>,
+    in_config: bool,
+}
+
+struct Runner {
+    vhost: String,
+    stream: String,
+    video: bool,
+    media: AutoMedia,
+    splicer: Splicer,
+    slots: Vec<Slot>,
+    current: Option<usize>,
+    /// The active input's config still has to go out before its keyframe.
+    needs_config: bool,
+    stall_timeout: Duration,
+    retry_interval: Duration,
+    switch_back: bool,
+    on_change: Option<OnChange>,
+    active: Arc<AtomicUsize>,
+    events: mpsc::Receiver<Event>,
+    sender: mpsc::Sender<Event>,
+}
+
+impl Runner {
+    fn run(mut self) {
+        self.choose();
+        let mut next_tick = Instant::now();
+        loop {
+            if next_tick <= Instant::now() {
+                self.tick();
+                next_tick = Instant::now() + TICK;
+            }
+            let wait = self
+                .pump()
+                .min(next_tick.saturating_duration_since(Instant::now()));
+            match self.events.recv_timeout(wait) {
+                Ok(Event::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Ok(Event::Frame(index, generation, frame)) => {
+                    if self.slots[index].generation == generation {
+                        self.frame(index, frame);
+                    }
+                }
+                Ok(Event::Closed(index, generation)) => {
+                    if self.slots[index].generation == generation {
+                        self.down(index);
+                    }
+                }
+                Ok(Event::Unregistered { vhost, app, stream }) => {
+                    self.unregistered(&vhost, &app, &stream)
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+
+    fn notify(&self, change: StateChange) {
+        if let Some(cb) = &self.on_change {
+            cb(change);
+        }
+    }
+
+    /// Makes the preferred input that is up the active one.
+    fn choose(&mut self) {
+        let keep = self
+            .current
+            .filter(|&index| !self.switch_back && self.slots[index].up);
+        let next = keep.or_else(|| self.slots.iter().position(|slot| slot.up));
+        if next == self.current {
+            return;
+        }
+        self.current = next;
+        self.active.store(next.unwrap_or(NONE), Ordering::Relaxed);
+        match next {
+            Some(index) => {
+                self.splicer.switch();
+                let slot = &mut self.slots[index];
+                if let Feed::File(file) = &mut slot.feed {
+                    file.reanchor();
+                }
+                self.needs_config = !matches!(slot.feed, Feed::File(_));
+                self.notify(StateChange::Active(index));
+            }
+            None => self.notify(StateChange::Lost),
+        }
+    }
+
+    fn frame(&mut self, index: usize, frame: Frame) {
+        let Ok(codec) = frame.codec_id() else {
+            return;
+        };
+        let slot = &mut self.slots[index];
+        slot.last_frame = Instant::now();
+        if frame.is_config_frame() {
+            if !slot.in_config {
+                slot.config.clear();
+                slot.in_config = true;
+            }
+            slot.config.push(frame.clone());
+        } else {
+            slot.in_config = false;
+        }
+
+        if !slot.up && (frame.is_key_frame() || !self.video) {
+            slot.up = true;
+            self.notify(StateChange::Up(index));
+            self.choose();
+        }
+        if self.current != Some(index) {
+            return;
+        }
+
+        if frame.is_config_frame() {
+            // the input sends its own
+            self.needs_config = false;
+        } else if self.needs_config && codec.is_video() && frame.is_key_frame() {
+            self.needs_config = false;
+            for config in self.slots[index].config.clone() {
+                if let Ok(codec) = config.codec_id() {
+                    self.write(codec, frame.dts(), frame.pts(), config.data(), true);
+                }
+            }
+        }
+        let keyframe = frame.is_key_frame() || frame.is_config_frame();
+        self.write(codec, frame.dts(), frame.pts(), frame.data(), keyframe);
+    }
+
+    fn write(&mut self, codec: CodecId, dts: u64, pts: u64, data: &[u8], keyframe: bool) {
+        if let Some(ts) = self.splicer.splice(codec, dts, pts, keyframe) {
+            self.media
+                .input_frame(&Frame::new(codec, ts.dts, ts.pts, data));
+        }
+    }
+
+    /// Input `index` closed or stalled.
+    fn down(&mut self, index: usize) {
+        let retry_at = Instant::now() + self.retry_interval;
+        let slot = &mut self.slots[index];
+        slot.generation += 1;
+        match &mut slot.feed {
+            Feed::Push { tap } => *tap = None,
+            Feed::Pull { pull, retry_at: at } => {
+                *pull = None;
+                *at = retry_at;
+            }
+            Feed::File(_) => {}
+        }
+        if slot.up {
+            slot.up = false;
+            self.notify(StateChange::Down(index));
+            self.choose();
+        }
+    }
+
+    /// Takes down the attached push inputs reading `vhost/app/stream`.
+    fn unregistered(&mut self, vhost: &str, app: &str, stream: &str) {
+        if vhost != self.vhost {
+            return;
+        }
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            let reading = match (&slot.input, &slot.feed) {
+                (Input::Push { app: a, stream: s }, Feed::Push { tap }) => {
+                    tap.is_some() && a == app && s == stream
+                }
+                _ => false,
+            };
+            if reading {
+                self.down(index);
+            }
+        }
+    }
+
+    /// Attaches taps, restarts pulls and detects stalls.
+    fn tick(&mut self) {
+        for index in 0..self.slots.len() {
+            let stalled = {
+                let slot = &self.slots[index];
+                let attached = match &slot.feed {
+                    Feed::Push { tap } => tap.is_some(),
+                    Feed::Pull { pull, .. } => pull.as_ref().is_some_and(HelperPull::is_attached),
+                    Feed::File(_) => false,
+                };
+                attached && slot.last_frame.elapsed() > self.stall_timeout
+            };
+            if stalled {
+                self.down(index);
+                continue;
+            }
+
+            let slot = &mut self.slots[index];
+            let generation = slot.generation;
+            let sender = self.sender.clone();
+            let sink = move |frame: &Frame| {
+                let _ = sender.send(Event::Frame(index, generation, frame.clone()));
+            };
+            match (&slot.input, &mut slot.feed) {
+                (Input::Push { app, stream }, Feed::Push { tap }) if tap.is_none() => {
+                    *tap = Tap::find(&self.vhost, app, stream, sink);
+                    if tap.is_some() {
+                        slot.last_frame = Instant::now();
+                    }
+                }
+                (Input::Pull(url), Feed::Pull { pull, retry_at }) => match pull {
+                    None if *retry_at <= Instant::now() => {
+                        let sender = self.sender.clone();
+                        *pull = Some(HelperPull::start(
+                            &self.vhost,
+                            &self.stream,
+                            url,
+                            move || {
+                                let _ = sender.send(Event::Closed(index, generation));
+                            },
+                        ));
+                    }
+                    Some(pull) if !pull.is_attached() => {
+                        pull.attach(sink);
+                        if pull.is_attached() {
+                            slot.last_frame = Instant::now();
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends due samples of an active file input; returns how long until
+    /// the next one is due.
+    fn pump(&mut self) -> Duration {
+        let Some(index) = self.current else {
+            return TICK;
+        };
+        loop {
+            let Feed::File(file) = &mut self.slots[index].feed else {
+                return TICK;
+            };
+            match file.next_due() {
+                Due::Sample(sample) => self.write(
+                    sample.codec,
+                    sample.dts,
+                    sample.pts,
+                    &sample.data,
+                    sample.keyframe,
+                ),
+                Due::Wait(wait) => return wait,
+                // the time line continues where the file ended
+                Due::Looped => self.splicer.rebase(),
+                Due::End | Due::Failed(_) => {
+                    // broken or empty file
+                    self.slots[index].up = false;
+                    self.notify(StateChange::Down(index));
+                    self.choose();
+                    return Duration::ZERO;
+                }
+            }
+        }
+    }
+}
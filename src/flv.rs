@@ -0,0 +1,946 @@
+//! FLV in Rust: a tag reader turning `.flv` files or HTTP-FLV bodies into
+//! [`Frame`]s and [`TrackConfig`]s, and a writer turning frames back into
+//! FLV, without going through [`FlvRecorder`](crate::recorder::FlvRecorder).
+//!
+//! H264 uses legacy AVC tags, H265 enhanced RTMP `hvc1` tags; the reader
+//! also takes legacy codec id 12 HEVC and enhanced `avc1`/`av01`/`vp09`.
+//!
+//! ```ignore
+//! // republish a file
+//! let media = Media::new(DEFAULT_VHOST, "live", "file", 0.0, false, false);
+//! let mut reader = flv::Reader::new();
+//! for tag in reader.push(&std::fs::read("in.flv")?) {
+//!     match tag {
+//!         flv::Tag::Config(config) => { config.init(&media); }
+//!         flv::Tag::Frame(frame) => { media.input_frame(&frame); }
+//!     }
+//! }
+//!
+//! // and write a live stream to a file
+//! let mut file = std::fs::File::create("out.flv")?;
+//! let tap = flv::tap(&source, move |bytes| { let _ = file.write_all(bytes); });
+//! ```
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    frame::Frame,
+    media::Media,
+    mux::SinkQueue,
+    nal::{self, BitReader, NalKind, NalUnit, ParameterSets},
+    obj::{AudioCodecArgs, CodecArgs, CodecId, MediaSource, Track, TrackDelegate, VideoCodecArgs},
+};
+
+const TAG_AUDIO: u8 = 8;
+const TAG_VIDEO: u8 = 9;
+const TAG_SCRIPT: u8 = 18;
+
+const AAC_SAMPLE_RATES: [i32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Codec setup from a sequence header, for [`Media`] or [`Track::new`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackConfig {
+    Video {
+        codec: CodecId,
+        width: i32,
+        height: i32,
+        fps: Option<f32>,
+        /// Decoder configuration record (`avcC`, `hvcC`, ...).
+        record: Vec<u8>,
+    },
+    Audio {
+        codec: CodecId,
+        sample_rate: i32,
+        channels: i32,
+        /// AudioSpecificConfig for AAC, empty otherwise.
+        config: Vec<u8>,
+    },
+}
+
+impl TrackConfig {
+    pub fn codec(&self) -> CodecId {
+        match self {
+            TrackConfig::Video { codec, .. } | TrackConfig::Audio { codec, .. } => *codec,
+        }
+    }
+
+    pub fn to_track(&self) -> Track {
+        Track::new(self.codec(), Some(self.codec_args()))
+    }
+
+    fn set_size(&mut self, size: (u32, u32, Option<f32>)) {
+        if let TrackConfig::Video {
+            width, height, fps, ..
+        } = self
+        {
+            (*width, *height) = (size.0 as i32, size.1 as i32);
+            *fps = fps.or(size.2);
+        }
+    }
+
+    /// Sets up the matching track of `media`.
+    pub fn init(&self, media: &Media) -> bool {
+        match self {
+            TrackConfig::Video {
+                codec,
+                width,
+                height,
+                fps,
+                ..
+            } => media.init_video(*codec, *width, *height, fps.unwrap_or(25.0), 0),
+            TrackConfig::Audio {
+                codec,
+                sample_rate,
+                channels,
+                ..
+            } => media.init_audio(*codec, *sample_rate, *channels, 0),
+        }
+    }
+
+    fn codec_args(&self) -> CodecArgs {
+        match self {
+            TrackConfig::Video {
+                width, height, fps, ..
+            } => CodecArgs::Video(VideoCodecArgs {
+                width: *width,
+                height: *height,
+                fps: fps.unwrap_or(25.0),
+            }),
+            TrackConfig::Audio {
+                sample_rate,
+                channels,
+                ..
+            } => CodecArgs::Audio(AudioCodecArgs {
+                channels: *channels,
+                sample_rate: *sample_rate,
+            }),
+        }
+    }
+}
+
+/// What the [`Reader`] makes of a tag.
+pub enum Tag {
+    /// A sequence header; the parameter sets also come as a config
+    /// [`Frame`] right after. AV1 and VP9 headers without the picture size
+    /// come right before the first frame that has it (a keyframe) instead.
+    Config(TrackConfig),
+    /// Annex-B video, ADTS AAC or raw audio; timestamps in milliseconds.
+    Frame(Frame),
+}
+
+/// Parses an FLV byte stream fed in any chunking, with or without the
+/// `FLV` file header.
+#[derive(Debug, Default)]
+pub struct Reader {
+    buffer: Vec<u8>,
+    header_checked: bool,
+    /// NAL length size of the current AVC/HEVC configuration.
+    nal_length_size: usize,
+    /// Object type, sample rate index, channels of the AAC configuration.
+    aac: Option<(u8, u8, u8)>,
+    /// AV1/VP9 configuration waiting for a frame with the picture size.
+    pending_video: Option<TrackConfig>,
+}
+
+impl Reader {
+    pub fn new() -> Self {
+        Self {
+            nal_length_size: 4,
+            ..Default::default()
+        }
+    }
+
+    /// Feeds bytes; returns what the completed tags carried.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Tag> {
+        self.buffer.extend_from_slice(data);
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut pos = 0;
+        let mut out = Vec::new();
+
+        if !self.header_checked {
+            if buffer.len() < 13 {
+                self.buffer = buffer;
+                return out;
+            }
+            if buffer.starts_with(b"FLV") {
+                pos = (u32::from_be_bytes([buffer[5], buffer[6], buffer[7], buffer[8]]) as usize)
+                    .max(9)
+                    + 4;
+                if buffer.len() < pos {
+                    // the header continues in the next chunk
+                    self.buffer = buffer;
+                    return out;
+                }
+            }
+            self.header_checked = true;
+        }
+
+        // tag header, body, previous tag size
+        while buffer.len() >= pos + 11 {
+            let header = &buffer[pos..pos + 11];
+            let size = be24(&header[1..4]) as usize;
+            if buffer.len() < pos + 11 + size + 4 {
+                break;
+            }
+            let timestamp = be24(&header[4..7]) | (header[7] as u32) << 24;
+            let body = &buffer[pos + 11..pos + 11 + size];
+            match header[0] & 0x1F {
+                TAG_VIDEO => self.video(body, timestamp as u64, &mut out),
+                TAG_AUDIO => self.audio(body, timestamp as u64, &mut out),
+                _ => {}
+            }
+            pos += 11 + size + 4;
+        }
+        self.buffer = buffer[pos..].to_vec();
+        out
+    }
+
+    fn video(&mut self, body: &[u8], dts: u64, out: &mut Vec<Tag>) {
+        let Some(&first) = body.first() else {
+            return;
+        };
+        // (codec, sequence header, coded frame with composition time, payload)
+        let (codec, kind, payload) = if first & 0x80 != 0 {
+            // enhanced RTMP: packet type and FourCC
+            let Some(fourcc) = body.get(1..5) else {
+                return;
+            };
+            let codec = match fourcc {
+                b"avc1" => CodecId::H264,
+                b"hvc1" => CodecId::H265,
+                b"av01" => CodecId::AV1,
+                b"vp09" => CodecId::VP9,
+                _ => return,
+            };
+            let payload = &body[5..];
+            match (first & 0x0F, codec) {
+                (0, _) => (codec, VideoPacket::Header, payload),
+                (1, CodecId::H264 | CodecId::H265) => (codec, VideoPacket::Frame, payload),
+                (1 | 3, _) => (codec, VideoPacket::FrameNoCts, payload),
+                _ => return,
+            }
+        } else {
+            let codec = match first & 0x0F {
+                7 => CodecId::H264,
+                12 => CodecId::H265,
+                _ => return,
+            };
+            let Some(&packet_type) = body.get(1) else {
+                return;
+            };
+            let payload = body.get(2..).unwrap_or_default();
+            match packet_type {
+                0 => (
+                    codec,
+                    VideoPacket::Header,
+                    payload.get(3..).unwrap_or_default(),
+                ),
+                1 => (codec, VideoPacket::Frame, payload),
+                _ => return,
+            }
+        };
+
+        match kind {
+            VideoPacket::Header => self.video_header(codec, payload, dts, out),
+            VideoPacket::Frame | VideoPacket::FrameNoCts => {
+                let (cts, data) = if kind == VideoPacket::Frame {
+                    let Some(cts) = payload.get(..3) else {
+                        return;
+                    };
+                    // SI24
+                    ((be24(cts) as i32) << 8 >> 8, &payload[3..])
+                } else {
+                    (0, payload)
+                };
+                let pts = (dts as i64 + cts as i64).max(0) as u64;
+                let data = match codec {
+                    CodecId::H264 | CodecId::H265 => {
+                        length_prefixed_to_annexb(data, self.nal_length_size)
+                    }
+                    _ => data.to_vec(),
+                };
+                if self
+                    .pending_video
+                    .as_ref()
+                    .is_some_and(|config| config.codec() == codec)
+                {
+                    let size = match codec {
+                        CodecId::AV1 => parse_av1_size(&data),
+                        _ => parse_vp9_size(&data).map(|(width, height)| (width, height, None)),
+                    };
+                    if let Some(size) = size {
+                        let mut config = self.pending_video.take().unwrap();
+                        config.set_size(size);
+                        out.push(Tag::Config(config));
+                    }
+                }
+                if !data.is_empty() {
+                    out.push(Tag::Frame(Frame::new(codec, dts, pts, data)));
+                }
+            }
+        }
+    }
+
+    fn video_header(&mut self, codec: CodecId, record: &[u8], dts: u64, out: &mut Vec<Tag>) {
+        let nals = match codec {
+            CodecId::H264 => parse_avcc(record),
+            CodecId::H265 => parse_hvcc(record),
+            _ => None,
+        };
+        let (mut width, mut height, mut fps) = (0, 0, None);
+        // av1C may carry the sequence header; vpcC has no picture size
+        if let Some((w, h, rate)) = record
+            .get(4..)
+            .filter(|_| codec == CodecId::AV1)
+            .and_then(parse_av1_size)
+        {
+            (width, height, fps) = (w as i32, h as i32, rate);
+        }
+        if let Some((length_size, nals)) = &nals {
+            self.nal_length_size = *length_size;
+            let info = nals
+                .iter()
+                .filter_map(|nal| NalUnit::parse(codec, nal).ok()?.sps_info()?.ok())
+                .next();
+            if let Some(info) = info {
+                (width, height, fps) = (info.width as i32, info.height as i32, info.fps);
+            }
+        }
+
+        let config = TrackConfig::Video {
+            codec,
+            width,
+            height,
+            fps,
+            record: record.to_vec(),
+        };
+        if width == 0 && matches!(codec, CodecId::AV1 | CodecId::VP9) {
+            self.pending_video = Some(config);
+        } else {
+            self.pending_video = None;
+            out.push(Tag::Config(config));
+        }
+        if let Some((_, nals)) = nals {
+            let mut config = Vec::new();
+            for nal in nals {
+                config.extend_from_slice(&[0, 0, 0, 1]);
+                config.extend_from_slice(&nal);
+            }
+            if !config.is_empty() {
+                out.push(Tag::Frame(Frame::new(codec, dts, dts, config)));
+            }
+        }
+    }
+
+    fn audio(&mut self, body: &[u8], dts: u64, out: &mut Vec<Tag>) {
+        let Some(&first) = body.first() else {
+            return;
+        };
+        let codec = match first >> 4 {
+            10 => CodecId::AAC,
+            7 => CodecId::G711A,
+            8 => CodecId::G711U,
+            2 => CodecId::MP3,
+            _ => return,
+        };
+        let payload = &body[1..];
+        if codec != CodecId::AAC {
+            if !payload.is_empty() {
+                out.push(Tag::Frame(Frame::new(codec, dts, dts, payload)));
+            }
+            return;
+        }
+
+        match payload.split_first() {
+            Some((0, config)) if config.len() >= 2 => {
+                let object_type = config[0] >> 3;
+                let rate_index = (config[0] & 0x07) << 1 | config[1] >> 7;
+                let channels = (config[1] >> 3) & 0x0F;
+                self.aac = Some((object_type, rate_index, channels));
+                out.push(Tag::Config(TrackConfig::Audio {
+                    codec,
+                    sample_rate: AAC_SAMPLE_RATES
+                        .get(rate_index as usize)
+                        .copied()
+                        .unwrap_or(44100),
+                    channels: channels as i32,
+                    config: config.to_vec(),
+                }));
+            }
+            Some((1, raw)) if !raw.is_empty() => {
+                let Some((object_type, rate_index, channels)) = self.aac else {
+                    return;
+                };
+                let adts = config_to_adts(object_type, rate_index, channels, raw);
+                out.push(Tag::Frame(Frame::new(codec, dts, dts, adts)));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VideoPacket {
+    Header,
+    Frame,
+    FrameNoCts,
+}
+
+fn be24(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32
+}
+
+/// Prepends an ADTS header to a raw AAC frame.
+pub(crate) fn config_to_adts(object_type: u8, rate_index: u8, channels: u8, raw: &[u8]) -> Vec<u8> {
+    let len = raw.len() + 7;
+    let mut adts = vec![
+        0xFF,
+        0xF1,
+        (object_type.saturating_sub(1) & 0x03) << 6 | rate_index << 2 | channels >> 2,
+        (channels & 0x03) << 6 | (len >> 11) as u8,
+        (len >> 3) as u8,
+        ((len & 0x07) << 5) as u8 | 0x1F,
+        0xFC,
+    ];
+    adts.extend_from_slice(raw);
+    adts
+}
+
+pub(crate) fn length_prefixed_to_annexb(mut data: &[u8], length_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    while data.len() >= length_size {
+        let len = data[..length_size]
+            .iter()
+            .fold(0usize, |len, &byte| len << 8 | byte as usize);
+        let Some(nal) = data.get(length_size..length_size + len) else {
+            break;
+        };
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(nal);
+        data = &data[length_size + len..];
+    }
+    out
+}
+
+/// NAL length size and parameter sets of an AVCDecoderConfigurationRecord.
+pub(crate) fn parse_avcc(record: &[u8]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let length_size = (*record.get(4)? & 0x03) as usize + 1;
+    let mut nals = Vec::new();
+    let mut rest = record.get(5..)?;
+    // SPS count, then PPS count
+    for mask in [0x1F, 0xFF] {
+        let (&count, tail) = rest.split_first()?;
+        rest = tail;
+        for _ in 0..count & mask {
+            let len = (*rest.first()? as usize) << 8 | *rest.get(1)? as usize;
+            nals.push(rest.get(2..2 + len)?.to_vec());
+            rest = &rest[2 + len..];
+        }
+    }
+    Some((length_size, nals))
+}
+
+/// NAL length size and parameter sets of an HEVCDecoderConfigurationRecord.
+pub(crate) fn parse_hvcc(record: &[u8]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let length_size = (*record.get(21)? & 0x03) as usize + 1;
+    let mut nals = Vec::new();
+    let mut rest = record.get(23..)?;
+    for _ in 0..*record.get(22)? {
+        let count = (*rest.get(1)? as usize) << 8 | *rest.get(2)? as usize;
+        rest = rest.get(3..)?;
+        for _ in 0..count {
+            let len = (*rest.first()? as usize) << 8 | *rest.get(1)? as usize;
+            nals.push(rest.get(2..2 + len)?.to_vec());
+            rest = &rest[2 + len..];
+        }
+    }
+    Some((length_size, nals))
+}
+
+/// Picture size and frame rate from the AV1 sequence header OBU among
+/// `obus` (low overhead format, as in `av1C` and FLV/MP4 samples).
+pub(crate) fn parse_av1_size(mut obus: &[u8]) -> Option<(u32, u32, Option<f32>)> {
+    while let Some(&header) = obus.first() {
+        let kind = (header >> 3) & 0x0F;
+        let mut pos = 1 + (header >> 2 & 0x01) as usize;
+        let size = if header & 0x02 != 0 {
+            // leb128
+            let mut size = 0usize;
+            for i in 0..8 {
+                let byte = *obus.get(pos)?;
+                pos += 1;
+                size |= ((byte & 0x7F) as usize) << (7 * i);
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            size
+        } else {
+            obus.len().checked_sub(pos)?
+        };
+        let payload = obus.get(pos..pos.checked_add(size)?)?;
+        if kind == 1 {
+            return parse_av1_sequence_header(payload).ok();
+        }
+        obus = &obus[pos + size..];
+    }
+    None
+}
+
+fn parse_av1_sequence_header(data: &[u8]) -> anyhow::Result<(u32, u32, Option<f32>)> {
+    let mut r = BitReader::new(data);
+    r.skip(4)?; // seq_profile, still_picture
+    let mut fps = None;
+    if r.flag()? {
+        r.skip(5)?; // reduced_still_picture_header: seq_level_idx
+    } else {
+        let mut decoder_model = false;
+        let mut delay_bits = 0;
+        if r.flag()? {
+            let num_units_in_display_tick = r.bits(32)?;
+            let time_scale = r.bits(32)?;
+            if num_units_in_display_tick > 0 {
+                fps = Some(time_scale as f32 / num_units_in_display_tick as f32);
+            }
+            if r.flag()? {
+                r.ue()?; // num_ticks_per_picture_minus_1 (uvlc)
+            }
+            decoder_model = r.flag()?;
+            if decoder_model {
+                delay_bits = r.bits(5)? as usize + 1;
+                r.skip(32 + 5 + 5)?;
+            }
+        }
+        let initial_display_delay = r.flag()?;
+        for _ in 0..=r.bits(5)? {
+            r.skip(12)?; // operating_point_idc
+            if r.bits(5)? > 7 {
+                r.skip(1)?; // seq_tier
+            }
+            if decoder_model && r.flag()? {
+                r.skip(2 * delay_bits + 1)?;
+            }
+            if initial_display_delay && r.flag()? {
+                r.skip(4)?;
+            }
+        }
+    }
+    let width_bits = r.bits(4)? + 1;
+    let height_bits = r.bits(4)? + 1;
+    Ok((r.bits(width_bits)? + 1, r.bits(height_bits)? + 1, fps))
+}
+
+/// Picture size from the uncompressed header of a VP9 keyframe.
+pub(crate) fn parse_vp9_size(frame: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(frame);
+    if r.bits(2).ok()? != 2 {
+        return None; // frame_marker
+    }
+    let profile = r.bits(1).ok()? | r.bits(1).ok()? << 1;
+    if profile == 3 {
+        r.skip(1).ok()?;
+    }
+    // show_existing_frame, then frame_type 0 for keyframes
+    if r.flag().ok()? || r.flag().ok()? {
+        return None;
+    }
+    r.skip(2).ok()?; // show_frame, error_resilient_mode
+    if r.bits(24).ok()? != 0x49_83_42 {
+        return None;
+    }
+    if profile >= 2 {
+        r.skip(1).ok()?; // ten_or_twelve_bit
+    }
+    let subsampling = profile == 1 || profile == 3;
+    if r.bits(3).ok()? != 7 {
+        // color_range, then subsampling_x/y and a reserved bit
+        r.skip(if subsampling { 4 } else { 1 }).ok()?;
+    } else if subsampling {
+        r.skip(1).ok()?;
+    }
+    Some((r.bits(16).ok()? + 1, r.bits(16).ok()? + 1))
+}
+
+/// Turns frames into FLV tags; sequence headers are written whenever the
+/// parameter sets (or the AAC configuration) change.
+#[derive(Debug, Default)]
+pub struct Writer {
+    video: HashMap<CodecId, ParameterSets>,
+    video_record: Option<Vec<u8>>,
+    audio_config: Option<Vec<u8>>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The FLV file header, including the first previous tag size.
+    pub fn header(has_video: bool, has_audio: bool) -> Vec<u8> {
+        let flags = (has_audio as u8) << 2 | has_video as u8;
+        vec![b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0]
+    }
+
+    /// Tags for one frame; empty for unsupported codecs and for video before
+    /// its parameter sets were seen.
+    pub fn write_frame(&mut self, frame: &Frame) -> Vec<u8> {
+        match frame.codec_id() {
+            Ok(codec @ (CodecId::H264 | CodecId::H265)) => self.write_video(codec, frame),
+            Ok(CodecId::AAC) => self.write_aac(frame),
+            Ok(CodecId::G711A) => tag(TAG_AUDIO, frame.dts(), &[&[0x72], frame.data()]),
+            Ok(CodecId::G711U) => tag(TAG_AUDIO, frame.dts(), &[&[0x82], frame.data()]),
+            Ok(CodecId::MP3) => tag(TAG_AUDIO, frame.dts(), &[&[0x2F], frame.data()]),
+            _ => Vec::new(),
+        }
+    }
+
+    /// An `onMetaData` script tag with the stream's size and rates.
+    pub fn metadata(&self, width: u32, height: u32, fps: f32) -> Vec<u8> {
+        let mut body = vec![2, 0, 10];
+        body.extend_from_slice(b"onMetaData");
+        body.extend_from_slice(&[8, 0, 0, 0, 3]);
+        for (name, value) in [
+            ("width", width as f64),
+            ("height", height as f64),
+            ("framerate", fps as f64),
+        ] {
+            body.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            body.extend_from_slice(name.as_bytes());
+            body.push(0);
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        body.extend_from_slice(&[0, 0, 9]);
+        tag(TAG_SCRIPT, 0, &[&body])
+    }
+
+    fn write_video(&mut self, codec: CodecId, frame: &Frame) -> Vec<u8> {
+        let sets = self.video.entry(codec).or_default();
+        let mut payload = Vec::new();
+        let mut keyframe = frame.is_key_frame();
+        for data in nal::split_annexb(frame.data()) {
+            let Ok(unit) = NalUnit::parse(codec, data) else {
+                continue;
+            };
+            if sets.update(&unit) || unit.kind() == NalKind::Aud {
+                continue;
+            }
+            keyframe |= unit.is_keyframe();
+            payload.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            payload.extend_from_slice(data);
+        }
+
+        let mut out = Vec::new();
+        if sets.is_complete(codec) {
+            let record = match codec {
+                CodecId::H265 => sets.hevc_config_record(),
+                _ => sets.avc_config_record(),
+            };
+            if record.is_some() && self.video_record != record {
+                let header: &[u8] = match codec {
+                    CodecId::H265 => &[0x90, b'h', b'v', b'c', b'1'],
+                    _ => &[0x17, 0, 0, 0, 0],
+                };
+                out.extend(tag(
+                    TAG_VIDEO,
+                    frame.dts(),
+                    &[header, record.as_deref().unwrap_or_default()],
+                ));
+                self.video_record = record;
+            }
+        }
+        if payload.is_empty() || self.video_record.is_none() {
+            return out;
+        }
+
+        let cts = (frame.pts() as i64 - frame.dts() as i64) as i32;
+        let cts = &cts.to_be_bytes()[1..];
+        let frame_type = if keyframe { 1 } else { 2 };
+        let header: Vec<u8> = match codec {
+            CodecId::H265 => [&[0x80 | frame_type << 4 | 1], &b"hvc1"[..], cts].concat(),
+            _ => [&[frame_type << 4 | 7, 1][..], cts].concat(),
+        };
+        out.extend(tag(TAG_VIDEO, frame.dts(), &[&header, &payload]));
+        out
+    }
+
+    fn write_aac(&mut self, frame: &Frame) -> Vec<u8> {
+        let data = frame.data();
+        let Some((config, header_len)) = adts_to_config(data) else {
+            return Vec::new();
+        };
+
+        let mut out = Vec::new();
+        if self.audio_config.as_ref() != Some(&config) {
+            out.extend(tag(TAG_AUDIO, frame.dts(), &[&[0xAF, 0], &config]));
+            self.audio_config = Some(config);
+        }
+        if let Some(raw) = data.get(header_len..) {
+            out.extend(tag(TAG_AUDIO, frame.dts(), &[&[0xAF, 1], raw]));
+        }
+        out
+    }
+}
+
+/// AudioSpecificConfig and header length of an ADTS frame.
+pub(crate) fn adts_to_config(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    if data.len() < 7 || data[0] != 0xFF || data[1] & 0xF0 != 0xF0 {
+        return None;
+    }
+    let header_len = if data[1] & 0x01 == 0 { 9 } else { 7 };
+    let object_type = (data[2] >> 6) + 1;
+    let rate_index = (data[2] >> 2) & 0x0F;
+    let channels = (data[2] & 0x01) << 2 | data[3] >> 6;
+    let config = vec![
+        object_type << 3 | rate_index >> 1,
+        (rate_index & 0x01) << 7 | channels << 3,
+    ];
+    Some((config, header_len))
+}
+
+/// One tag with its trailing previous tag size.
+fn tag(kind: u8, timestamp: u64, parts: &[&[u8]]) -> Vec<u8> {
+    let size: usize = parts.iter().map(|part| part.len()).sum();
+    let timestamp = timestamp as u32;
+    let mut out = Vec::with_capacity(11 + size + 4);
+    out.push(kind);
+    out.extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+    out.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+    out.push((timestamp >> 24) as u8);
+    out.extend_from_slice(&[0, 0, 0]);
+    for part in parts {
+        out.extend_from_slice(part);
+    }
+    out.extend_from_slice(&((11 + size) as u32).to_be_bytes());
+    out
+}
+
+/// FLV output of a live source, see [`tap`]; removes its track delegates on
+/// drop.
+pub struct Tap {
+    delegates: Vec<(Track, TrackDelegate)>,
+}
+
+/// Writes the FLV header, then every frame of `source`'s tracks as FLV tags
+/// to `sink`, e.g. a file or an HTTP-FLV response body. The first video
+/// bytes follow the first keyframe's parameter sets.
+pub fn tap(source: &MediaSource, sink: impl FnMut(&[u8]) + Send + 'static) -> Tap {
+    let tracks: Vec<Track> = (0..source.track_count())
+        .filter_map(|index| source.get_track(index))
+        .collect();
+    let has_video = tracks.iter().any(Track::is_video);
+    let has_audio = tracks.iter().any(|track| !track.is_video());
+
+    let mut sink = sink;
+    sink(&Writer::header(has_video, has_audio));
+    let shared = Arc::new(SinkQueue::new(Writer::new(), move |bytes: Vec<u8>| {
+        sink(&bytes)
+    }));
+
+    let delegates = tracks
+        .into_iter()
+        .map(|track| {
+            let shared = shared.clone();
+            let delegate = track.add_delegate(move |frame| {
+                shared.write(|writer| {
+                    let bytes = writer.write_frame(frame);
+                    if bytes.is_empty() {
+                        Vec::new()
+                    } else {
+                        vec![bytes]
+                    }
+                });
+            });
+            (track, delegate)
+        })
+        .collect();
+    Tap { delegates }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        for (track, delegate) in self.delegates.drain(..) {
+            track.del_delegate(delegate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H264_SPS: [u8; 19] = [
+        0x67, 0x42, 0x00, 0x1F, 0xF4, 0x02, 0x80, 0x2D, 0xD0, 0x80, 0x00, 0x00, 0x03, 0x00, 0x80,
+        0x00, 0x00, 0x1E, 0x40,
+    ];
+    const H265_SPS: [u8; 40] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00,
+        0x00, 0x03, 0x00, 0x5D, 0xA0, 0x02, 0x80, 0x80, 0x2D, 0x16, 0x5A, 0xEA, 0xF0, 0x88, 0x04,
+        0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0x66,
+    ];
+
+    fn annexb(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+            .collect()
+    }
+
+    /// Reads `flv` fed in chunks of `chunk` bytes.
+    fn read(flv: &[u8], chunk: usize) -> Vec<Tag> {
+        let mut reader = Reader::new();
+        flv.chunks(chunk)
+            .flat_map(|chunk| reader.push(chunk))
+            .collect()
+    }
+
+    fn video_config(tag: &Tag) -> (CodecId, i32, i32, Option<f32>, Vec<u8>) {
+        match tag {
+            Tag::Config(TrackConfig::Video {
+                codec,
+                width,
+                height,
+                fps,
+                record,
+            }) => (*codec, *width, *height, *fps, record.clone()),
+            _ => panic!("not a video config"),
+        }
+    }
+
+    fn frame(tag: &Tag) -> &Frame {
+        match tag {
+            Tag::Frame(frame) => frame,
+            _ => panic!("not a frame"),
+        }
+    }
+
+    #[test]
+    fn round_trips_h264() {
+        let idr = [0x65, 0x88, 0x84, 0x00];
+        let mut writer = Writer::new();
+        let mut flv = Writer::header(true, false);
+        flv.extend(writer.write_frame(&Frame::new(
+            CodecId::H264,
+            40,
+            80,
+            annexb(&[&H264_SPS, &[0x68, 0xCE, 0x38, 0x80], &idr]),
+        )));
+        flv.extend(writer.write_frame(&Frame::new(
+            CodecId::H264,
+            80,
+            80,
+            annexb(&[&[0x41, 0x9A]]),
+        )));
+
+        // byte by byte, so the header and every tag span chunks
+        let tags = read(&flv, 1);
+        assert_eq!(tags.len(), 4);
+        let (codec, width, height, fps, record) = video_config(&tags[0]);
+        assert_eq!(
+            (codec, width, height, fps),
+            (CodecId::H264, 1280, 720, Some(30.0))
+        );
+        let (length_size, nals) = parse_avcc(&record).unwrap();
+        assert_eq!(length_size, 4);
+        assert_eq!(nals, [H264_SPS.to_vec(), vec![0x68, 0xCE, 0x38, 0x80]]);
+        assert_eq!(frame(&tags[1]).data(), annexb(&[&nals[0], &nals[1]]));
+
+        let key = frame(&tags[2]);
+        assert_eq!((key.dts(), key.pts()), (40, 80));
+        assert_eq!(key.data(), annexb(&[&idr]));
+        assert_eq!(frame(&tags[3]).data(), annexb(&[&[0x41, 0x9A]]));
+        assert_eq!(read(&flv, flv.len()).len(), 4);
+    }
+
+    #[test]
+    fn round_trips_h265_as_enhanced_rtmp() {
+        let vps = [0x40, 0x01, 0x0C, 0x01];
+        let pps = [0x44, 0x01, 0xC1, 0x72];
+        let idr = [0x26, 0x01, 0xAF, 0x06];
+        let mut writer = Writer::new();
+        let mut flv = Writer::header(true, false);
+        flv.extend(writer.write_frame(&Frame::new(
+            CodecId::H265,
+            0,
+            0,
+            annexb(&[&vps, &H265_SPS, &pps, &idr]),
+        )));
+        // enhanced RTMP: keyframe, SequenceStart, FourCC
+        assert_eq!(&flv[13 + 11..13 + 16], &[0x90, b'h', b'v', b'c', b'1']);
+
+        let tags = read(&flv, 7);
+        assert_eq!(tags.len(), 3);
+        let (codec, width, height, _, record) = video_config(&tags[0]);
+        assert_eq!((codec, width, height), (CodecId::H265, 1280, 720));
+        let (length_size, nals) = parse_hvcc(&record).unwrap();
+        assert_eq!(length_size, 4);
+        assert_eq!(nals, [vps.to_vec(), H265_SPS.to_vec(), pps.to_vec()]);
+        assert_eq!(frame(&tags[2]).data(), annexb(&[&idr]));
+    }
+
+    #[test]
+    fn reads_av1_and_vp9_sizes() {
+        // sequence header OBU: 1280x720, no timing info
+        let obu = [0x0A, 0x08, 0x00, 0x00, 0x00, 0x42, 0xAA, 0x7F, 0xAC, 0xF8];
+        let mut flv = tag(
+            TAG_VIDEO,
+            0,
+            &[b"\x90av01", &[0x81, 0x00, 0x0C, 0x00], &obu],
+        );
+        flv.extend(tag(TAG_VIDEO, 0, &[b"\x91av01", &obu]));
+        let tags = read(&flv, flv.len());
+        assert_eq!(tags.len(), 2);
+        let (codec, width, height, _, _) = video_config(&tags[0]);
+        assert_eq!((codec, width, height), (CodecId::AV1, 1280, 720));
+
+        // vpcC has no size: the config waits for the 640x360 keyframe
+        let keyframe = [0x82, 0x49, 0x83, 0x42, 0x40, 0x27, 0xF0, 0x16, 0x78];
+        let mut flv = tag(
+            TAG_VIDEO,
+            0,
+            &[b"\x90vp09", &[1, 0, 0, 0, 0, 0x80, 2, 2, 2, 0]],
+        );
+        flv.extend(tag(TAG_VIDEO, 0, &[b"\x91vp09", &keyframe]));
+        let tags = read(&flv, flv.len());
+        assert_eq!(tags.len(), 2);
+        let (codec, width, height, _, _) = video_config(&tags[0]);
+        assert_eq!((codec, width, height), (CodecId::VP9, 640, 360));
+        assert_eq!(frame(&tags[1]).data(), keyframe);
+    }
+
+    #[test]
+    fn round_trips_aac() {
+        let raw = [0x21, 0x10, 0x04, 0x60];
+        let adts = config_to_adts(2, 4, 2, &raw);
+        let (config, header_len) = adts_to_config(&adts).unwrap();
+        assert_eq!((config.as_slice(), header_len), (&[0x12, 0x10][..], 7));
+        assert_eq!(adts.len(), 7 + raw.len());
+
+        let mut writer = Writer::new();
+        let mut flv = Writer::header(false, true);
+        flv.extend(writer.write_frame(&Frame::new(CodecId::AAC, 23, 23, &adts)));
+        flv.extend(writer.write_frame(&Frame::new(CodecId::AAC, 46, 46, &adts)));
+        let tags = read(&flv, 5);
+        assert_eq!(tags.len(), 3);
+        assert!(matches!(
+            &tags[0],
+            Tag::Config(TrackConfig::Audio { codec: CodecId::AAC, sample_rate: 44100, channels: 2, config: c }) if c == &config
+        ));
+        assert_eq!(frame(&tags[1]).data(), adts);
+        assert_eq!(frame(&tags[2]).dts(), 46);
+    }
+
+    #[test]
+    fn reads_header_with_large_data_offset() {
+        // 4 bytes of padding after the 9 byte header
+        let mut flv = vec![
+            b'F', b'L', b'V', 1, 4, 0, 0, 0, 13, 0xEE, 0xEE, 0xEE, 0xEE, 0, 0, 0, 0,
+        ];
+        flv.extend(tag(TAG_AUDIO, 0, &[&[0x72, 0xD5, 0xD5]]));
+        let mut reader = Reader::new();
+        assert!(reader.push(&flv[..14]).is_empty());
+        let tags = reader.push(&flv[14..]);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(frame(&tags[0]).data(), [0xD5, 0xD5]);
+    }
+}
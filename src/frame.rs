@@ -58,6 +58,66 @@ impl Frame {
             )
         })
     }
+
+    /// Takes a new reference to a frame borrowed from a callback.
+    pub(crate) fn from_borrowed(frame: mk_frame) -> Self {
+        Self(unsafe { mk_frame_ref(frame) })
+    }
+
+    pub fn codec_id(&self) -> anyhow::Result<CodecId> {
+        CodecId::try_from(unsafe { mk_frame_codec_id(self.0) })
+    }
+
+    pub fn is_video(&self) -> bool {
+        unsafe { mk_frame_is_video(self.0) == 1 }
+    }
+
+    pub fn dts(&self) -> u64 {
+        unsafe { mk_frame_get_dts(self.0) }
+    }
+
+    pub fn pts(&self) -> u64 {
+        unsafe { mk_frame_get_pts(self.0) }
+    }
+
+    /// `MK_FRAME_FLAG_*` bits.
+    pub fn flags(&self) -> u32 {
+        unsafe { mk_frame_get_flags(self.0) }
+    }
+
+    pub fn is_key_frame(&self) -> bool {
+        self.flags() & MK_FRAME_FLAG_IS_KEY != 0
+    }
+
+    /// SPS/PPS/VPS and similar parameter sets.
+    pub fn is_config_frame(&self) -> bool {
+        self.flags() & MK_FRAME_FLAG_IS_CONFIG != 0
+    }
+
+    /// Frame payload, including the start code for H264/H265
+    /// (see [`prefix_size`](Self::prefix_size)).
+    pub fn data(&self) -> &[u8] {
+        unsafe {
+            let data = mk_frame_get_data(self.0);
+            let size = mk_frame_get_data_size(self.0);
+            if data.is_null() || size == 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts(data as *const u8, size)
+            }
+        }
+    }
+
+    /// Length of the start code in front of [`data`](Self::data).
+    pub fn prefix_size(&self) -> usize {
+        unsafe { mk_frame_get_data_prefix_size(self.0) }
+    }
+}
+
+impl Clone for Frame {
+    fn clone(&self) -> Self {
+        Self(unsafe { mk_frame_ref(self.0) })
+    }
 }
 
 impl Drop for Frame {
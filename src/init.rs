@@ -1,66 +1,145 @@
-use std::sync::Mutex;
+use std::{
+    ffi::CString,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
 
+use bitflags::bitflags;
 use once_cell::sync::Lazy;
 use rszlm_sys::*;
 
 use crate::{const_ptr_to_string, const_str_to_ptr};
 
-pub struct EnvInitBuilder(mk_config);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// 日志级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum LogLevel {
+    #[default]
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+impl From<LogLevel> for i32 {
+    fn from(level: LogLevel) -> Self {
+        level as i32
+    }
+}
+
+impl TryFrom<i32> for LogLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(LogLevel::Trace),
+            1 => Ok(LogLevel::Debug),
+            2 => Ok(LogLevel::Info),
+            3 => Ok(LogLevel::Warn),
+            4 => Ok(LogLevel::Error),
+            v => anyhow::bail!("invalid log level {}", v),
+        }
+    }
+}
+
+bitflags! {
+    /// 日志输出方式，可组合使用，例如 `LogSink::CONSOLE | LogSink::FILE`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct LogSink: i32 {
+        /// 输出到控制台
+        const CONSOLE = LOG_CONSOLE as i32;
+        /// 输出到文件，需同时设置 [`EnvInitBuilder::log_file_path`]
+        const FILE = LOG_FILE as i32;
+        /// 输出到回调方法，见 [`Event::on_log`](crate::event::Event::on_log)
+        const CALLBACK = LOG_CALLBACK as i32;
+    }
+}
+
+impl Default for LogSink {
+    fn default() -> Self {
+        LogSink::CONSOLE
+    }
+}
+
+/// SSL证书，用于https/rtsps/rtmps/webrtc等
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ssl {
+    /// 证书文件路径及其密码
+    File(String, Option<String>),
+    /// PEM格式证书内容及其密码
+    Pem(String, Option<String>),
+}
+
+#[derive(Debug, Clone)]
+enum IniSource {
+    Text(String),
+    File(String),
+}
+
+/// Configures and starts the ZLMediaKit environment.
+///
+/// ```ignore
+/// let runtime = EnvInitBuilder::new()
+///     .log_level(LogLevel::Info)
+///     .log_mask(LogSink::CONSOLE | LogSink::FILE)
+///     .log_file_path("./log")
+///     .ssl(Ssl::File("./default.pem".into(), None))
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EnvInitBuilder {
+    thread_num: i32,
+    log_level: LogLevel,
+    log_mask: LogSink,
+    log_file_path: Option<String>,
+    log_file_days: i32,
+    ini: Option<IniSource>,
+    ssl: Option<Ssl>,
+}
 
 impl EnvInitBuilder {
     pub fn new() -> Self {
-        Self(mk_config {
-            thread_num: 0,
-            log_level: 0,
-            log_mask: LOG_CONSOLE as i32,
-            log_file_path: std::ptr::null(),
-            log_file_days: 0,
-            ini_is_path: 0,
-            ini: std::ptr::null(),
-            ssl_is_path: 0,
-            ssl: std::ptr::null(),
-            ssl_pwd: std::ptr::null(),
-        })
+        Self::default()
     }
 
     pub fn thread_num(mut self, thread_num: i32) -> Self {
-        self.0.thread_num = thread_num;
+        self.thread_num = thread_num;
         self
     }
 
-    /// 设置日志级别
-    ///
-    /// - 0: Trace
-    /// - 1: Debug
-    /// - 2: Info
-    /// - 3: Warn
-    /// - 4: Error
-    ///
-    pub fn log_level(mut self, log_level: i32) -> Self {
-        self.0.log_level = log_level;
+    /// 设置日志级别，默认 [`LogLevel::Trace`]
+    pub fn log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
         self
     }
 
-    /// 设置日志输出方式
-    ///
-    /// - 0: 输出到控制台
-    /// - 2: 输出到文件
-    /// - 4: 输出到回调方法
+    /// 设置日志输出方式，默认 [`LogSink::CONSOLE`]；`LogSink::empty()` 关闭日志输出
+    pub fn log_mask(mut self, log_mask: LogSink) -> Self {
+        self.log_mask = log_mask;
+        self
+    }
+
+    /// 将ZLMediaKit日志转发到 `tracing` / `log`，并关闭控制台输出避免重复打印
     ///
-    /// example:
-    ///  三种方式全部输出，使用 0 ｜ 2 ｜ 4
+    /// 文件日志（[`LogSink::FILE`]）保持不变，见 [`crate::logging`]。
     ///
-    pub fn log_mask(mut self, log_mask: i32) -> Self {
-        self.0.log_mask = log_mask;
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    pub fn forward_log(mut self) -> Self {
+        self.log_mask = (self.log_mask & LogSink::FILE) | LogSink::CALLBACK;
+        crate::logging::install();
         self
     }
 
     /// 文件日志保存路径,路径可以不存在(内部可以创建文件夹)
-    /// 默认设置为NULL关闭日志输出至文件
+    /// 默认不设置，关闭日志输出至文件
     ///
     pub fn log_file_path(mut self, log_file_path: &str) -> Self {
-        let log_file_path = const_str_to_ptr!(log_file_path);
-        self.0.log_file_path = log_file_path.into_raw();
+        self.log_file_path = Some(log_file_path.to_string());
         self
     }
 
@@ -68,53 +147,107 @@ impl EnvInitBuilder {
     /// 默认设置为0关闭日志文件
     ///
     pub fn log_file_days(mut self, log_file_days: i32) -> Self {
-        self.0.log_file_days = log_file_days;
-        self
-    }
-
-    pub fn ini_is_path(mut self, ini_is_path: i32) -> Self {
-        self.0.ini_is_path = ini_is_path;
+        self.log_file_days = log_file_days;
         self
     }
 
+    /// ini配置内容
     pub fn ini(mut self, ini_txt: &str) -> Self {
-        let ini_txt = const_str_to_ptr!(ini_txt);
-        self.0.ini = ini_txt.into_raw();
+        self.ini = Some(IniSource::Text(ini_txt.to_string()));
         self
     }
 
+    /// ini配置文件路径
     pub fn ini_by_file(mut self, path: &str) -> Self {
-        let path = const_str_to_ptr!(path);
-        self.0.ini = path.into_raw();
-        self.0.ini_is_path = 1;
+        self.ini = Some(IniSource::File(path.to_string()));
         self
     }
 
-    pub fn ssl_is_path(mut self, ssl_is_path: i32) -> Self {
-        self.0.ssl_is_path = ssl_is_path;
+    pub fn ssl(mut self, ssl: Ssl) -> Self {
+        self.ssl = Some(ssl);
         self
     }
 
-    pub fn ssl(mut self, ssl: &str) -> Self {
-        let ssl = const_str_to_ptr!(ssl);
-        self.0.ssl = ssl.into_raw();
-        self
+    /// Initializes the environment.
+    ///
+    /// Fails if an ini or certificate file does not exist, a string contains
+    /// a NUL byte, or the environment was already initialized in this process
+    /// (ZLMediaKit can only be initialized once). Keep the returned
+    /// [`Runtime`] alive for as long as the servers should run.
+    pub fn build(self) -> anyhow::Result<Runtime> {
+        if let Some(IniSource::File(path)) = &self.ini {
+            anyhow::ensure!(Path::new(path).is_file(), "ini file not found: {}", path);
+        }
+        if let Some(Ssl::File(path, _)) = &self.ssl {
+            anyhow::ensure!(Path::new(path).is_file(), "ssl file not found: {}", path);
+        }
+
+        // the CStrings only need to outlive `mk_env_init`, which copies them
+        let log_file_path = self.log_file_path.map(CString::new).transpose()?;
+        let (ini_is_path, ini) = match self.ini {
+            Some(IniSource::Text(txt)) => (0, Some(CString::new(txt)?)),
+            Some(IniSource::File(path)) => (1, Some(CString::new(path)?)),
+            None => (0, None),
+        };
+        let (ssl_is_path, ssl, ssl_pwd) = match self.ssl {
+            Some(Ssl::File(path, pwd)) => (1, Some(CString::new(path)?), pwd),
+            Some(Ssl::Pem(pem, pwd)) => (0, Some(CString::new(pem)?), pwd),
+            None => (0, None, None),
+        };
+        let ssl_pwd = ssl_pwd.map(CString::new).transpose()?;
+
+        let as_ptr = |s: &Option<CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+        let config = mk_config {
+            thread_num: self.thread_num,
+            log_level: self.log_level.into(),
+            log_mask: self.log_mask.bits(),
+            log_file_path: as_ptr(&log_file_path),
+            log_file_days: self.log_file_days,
+            ini_is_path,
+            ini: as_ptr(&ini),
+            ssl_is_path,
+            ssl: as_ptr(&ssl),
+            ssl_pwd: as_ptr(&ssl_pwd),
+        };
+
+        if INITIALIZED.swap(true, Ordering::SeqCst) {
+            anyhow::bail!("ZLMediaKit environment is already initialized");
+        }
+        unsafe { mk_env_init(&config as *const mk_config) }
+        Ok(Runtime { _priv: () })
     }
+}
 
-    pub fn ssl_pwd(mut self, ssl_pwd: &str) -> Self {
-        let ssl_pwd = const_str_to_ptr!(ssl_pwd);
-        self.0.ssl_pwd = ssl_pwd.into_raw();
-        self
+/// Guard for an initialized ZLMediaKit environment, see [`EnvInitBuilder::build`].
+///
+/// Dropping it stops all servers. ZLMediaKit cannot be initialized a second
+/// time, so the environment is not re-creatable afterwards.
+#[must_use = "dropping the Runtime stops all servers"]
+#[derive(Debug)]
+pub struct Runtime {
+    _priv: (),
+}
+
+impl Runtime {
+    /// Whether [`EnvInitBuilder::build`] has succeeded in this process.
+    pub fn is_initialized() -> bool {
+        INITIALIZED.load(Ordering::SeqCst)
     }
 
-    pub fn build(self) {
-        unsafe { mk_env_init(&self.0 as *const mk_config) }
+    /// Errors if the environment is not initialized yet; for APIs that must
+    /// not run before [`EnvInitBuilder::build`].
+    pub fn ensure_initialized() -> anyhow::Result<()> {
+        anyhow::ensure!(
+            Self::is_initialized(),
+            "ZLMediaKit environment is not initialized, call EnvInitBuilder::build first"
+        );
+        Ok(())
     }
 }
 
-impl Default for EnvInitBuilder {
-    fn default() -> Self {
-        Self::new()
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        unsafe { mk_stop_all_server() }
     }
 }
 
@@ -156,8 +289,23 @@ impl EnvIni {
         unsafe { mk_ini_del_option(self.0, key.as_ptr()) != 0 }
     }
 
+    /// Merges ini text into this config; existing keys not in `ini_txt` are kept.
+    ///
+    /// On the global ini this makes ZLMediaKit broadcast `kBroadcastReloadConfig`,
+    /// so running servers pick up the new values.
+    pub fn load_string(&self, ini_txt: &str) {
+        let ini_txt = const_str_to_ptr!(ini_txt);
+        unsafe { mk_ini_load_string(self.0, ini_txt.as_ptr()) }
+    }
+
+    /// Same as [`load_string`](Self::load_string), reading from an ini file.
+    pub fn load_file(&self, path: &str) {
+        let path = const_str_to_ptr!(path);
+        unsafe { mk_ini_load_file(self.0, path.as_ptr()) }
+    }
+
     pub fn dump(&self) -> String {
-        unsafe { const_ptr_to_string!(mk_ini_dump_string(self.0)) }
+        dump_ini(self.0)
     }
 }
 
@@ -175,3 +323,16 @@ impl AsRef<mk_ini> for EnvIni {
 
 unsafe impl Send for EnvIni {}
 unsafe impl Sync for EnvIni {}
+
+/// Dumps any ini handle, including ones only borrowed from a callback.
+pub(crate) fn dump_ini(ini: mk_ini) -> String {
+    unsafe {
+        // the dump is heap allocated by ZLMediaKit and must be released with mk_free
+        let ptr = mk_ini_dump_string(ini);
+        let dump = const_ptr_to_string!(ptr);
+        if !ptr.is_null() {
+            mk_free(ptr as *mut _);
+        }
+        dump
+    }
+}
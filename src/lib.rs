@@ -1,12 +1,31 @@
+pub mod channel;
+pub mod config;
+pub mod demux;
 pub mod event;
+pub mod failover;
+pub mod flv;
 pub mod frame;
 pub mod init;
+#[cfg(any(feature = "tracing", feature = "log"))]
+pub mod logging;
 pub mod media;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mp4;
+pub mod mux;
+pub mod nal;
+pub mod net;
 pub mod obj;
 pub mod player;
+pub mod poller;
 pub mod pusher;
 pub mod recorder;
 pub mod server;
+pub mod snapshot;
+pub mod stats;
+pub mod timestamp;
+#[cfg(feature = "transcode")]
+pub mod transcode;
 #[cfg(feature = "webrtc")]
 pub mod webrtc;
 
@@ -0,0 +1,87 @@
+//! Routes ZLMediaKit's log output into Rust's logging facade.
+//!
+//! With the `tracing` feature every line becomes a `tracing` event, otherwise
+//! (with the `log` feature) a `log` record. The target is always
+//! [`TARGET`], so it can be filtered like any other module, e.g.
+//! `RUST_LOG=zlmediakit=warn`. `log` records carry the ZLMediaKit source
+//! file as file and module path, and the function ahead of the message.
+//!
+//! Use [`EnvInitBuilder::forward_log`](crate::init::EnvInitBuilder::forward_log)
+//! to enable it; that also turns off ZLMediaKit's own console output so lines
+//! are not printed twice.
+
+use crate::{
+    event::{LogMessage, EVENTS},
+    init::LogLevel,
+};
+
+/// Log target of forwarded ZLMediaKit messages.
+pub const TARGET: &str = "zlmediakit";
+
+/// Registers the `on_log` handler that forwards to the facade. A handler
+/// set before is still called, ahead of the forwarding.
+///
+/// ZLMediaKit only calls it when the log mask includes `LOG_CALLBACK`.
+pub fn install() {
+    let mut events = EVENTS.write().unwrap();
+    let previous = events.on_log.clone();
+    events.on_log(move |msg| {
+        if let Some(previous) = &previous {
+            previous(msg.clone());
+        }
+        forward(msg);
+    });
+}
+
+#[cfg(feature = "tracing")]
+fn forward(msg: LogMessage) {
+    let message = msg.message.trim_end();
+    macro_rules! emit {
+        ($level:expr) => {
+            tracing::event!(
+                target: TARGET,
+                $level,
+                zlm.file = %msg.file,
+                zlm.line = msg.line,
+                zlm.function = %msg.function,
+                "{}",
+                message
+            )
+        };
+    }
+
+    match msg.log_level() {
+        LogLevel::Trace => emit!(tracing::Level::TRACE),
+        LogLevel::Debug => emit!(tracing::Level::DEBUG),
+        LogLevel::Info => emit!(tracing::Level::INFO),
+        LogLevel::Warn => emit!(tracing::Level::WARN),
+        LogLevel::Error => emit!(tracing::Level::ERROR),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+fn forward(msg: LogMessage) {
+    let level = match msg.log_level() {
+        LogLevel::Trace => log::Level::Trace,
+        LogLevel::Debug => log::Level::Debug,
+        LogLevel::Info => log::Level::Info,
+        LogLevel::Warn => log::Level::Warn,
+        LogLevel::Error => log::Level::Error,
+    };
+
+    let logger = log::logger();
+    let metadata = log::Metadata::builder().level(level).target(TARGET).build();
+    if !logger.enabled(&metadata) {
+        return;
+    }
+
+    logger.log(
+        &log::Record::builder()
+            .metadata(metadata)
+            .file(Some(&msg.file))
+            .line(u32::try_from(msg.line).ok())
+            .module_path(Some(&msg.file))
+            .args(format_args!("{}: {}", msg.function, msg.message.trim_end()))
+            .build(),
+    );
+}
@@ -1,9 +1,21 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
 use rszlm_sys::*;
 
 use crate::{
-    const_str_to_ptr,
+    box_to_mut_void_ptr, const_str_to_ptr,
     frame::Frame,
+    nal::{self, NalUnit, ParameterSets},
     obj::{CodecId, Track},
+    poller::EventPoller,
+    timestamp::Normalizer,
     DEFAULT_VHOST,
 };
 
@@ -28,7 +40,57 @@ use crate::{
 /// media.init_complete();
 /// media.input_frame(&frame);
 /// ```
-pub struct Media(mk_media);
+pub struct Media {
+    inner: mk_media,
+    #[cfg_attr(not(feature = "transcode"), allow(dead_code))]
+    encoder_size: EncoderSize,
+    normalizer: Mutex<Option<Normalizer>>,
+}
+
+/// Picture size set by `Media::init_video_encoder`, `width << 32 | height`,
+/// so `Media::input_yuv` can check plane sizes.
+#[derive(Debug, Default)]
+struct EncoderSize(AtomicU64);
+
+#[cfg_attr(not(feature = "transcode"), allow(dead_code))]
+impl EncoderSize {
+    fn set(&self, width: u32, height: u32) {
+        self.0
+            .store((width as u64) << 32 | height as u64, Ordering::Relaxed)
+    }
+
+    fn get(&self) -> Option<(usize, usize)> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            size => Some(((size >> 32) as usize, (size & 0xFFFF_FFFF) as usize)),
+        }
+    }
+}
+
+/// Settings of ZLMediaKit's built-in video encoder, see [`Media::init_video_encoder`].
+#[cfg(feature = "transcode")]
+#[derive(Debug, Clone)]
+pub struct VideoEncoderSettings {
+    /// Only [`CodecId::H264`] (x264) is built in.
+    pub codec: CodecId,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    /// Bits per second.
+    pub bit_rate: i32,
+}
+
+/// Settings of ZLMediaKit's built-in audio encoder, see [`Media::init_audio_encoder`].
+#[cfg(feature = "transcode")]
+#[derive(Debug, Clone)]
+pub struct AudioEncoderSettings {
+    /// Only [`CodecId::AAC`] (faac) is built in.
+    pub codec: CodecId,
+    pub sample_rate: i32,
+    pub channels: i32,
+    /// Bits per second.
+    pub bit_rate: i32,
+}
 
 impl Media {
     /// Creates a new media source.
@@ -92,9 +154,25 @@ impl Media {
         )
     }
 
+    /// Creates a media source whose tracks are set up from the stream itself,
+    /// see [`AutoMedia`].
+    pub fn auto(
+        vhost: &str,
+        app: &str,
+        stream: &str,
+        hls_enabled: bool,
+        mp4_enabled: bool,
+        options: AutoOptions,
+    ) -> AutoMedia {
+        AutoMedia::new(
+            Media::new(vhost, app, stream, 0.0, hls_enabled, mp4_enabled),
+            options,
+        )
+    }
+
     /// Initializes a track from an existing [`Track`] (e.g. parsed from SDP or another source).
     pub fn init_track(&self, track: &Track) {
-        unsafe { mk_media_init_track(self.0, track.inner()) }
+        unsafe { mk_media_init_track(self.inner, track.inner()) }
     }
 
     /// Initializes the video track.
@@ -116,7 +194,9 @@ impl Media {
         fps: f32,
         bit_rate: i32,
     ) -> bool {
-        unsafe { mk_media_init_video(self.0, codec_id.into(), width, height, fps, bit_rate) == 1 }
+        unsafe {
+            mk_media_init_video(self.inner, codec_id.into(), width, height, fps, bit_rate) == 1
+        }
     }
 
     /// Initializes the audio track.
@@ -137,7 +217,7 @@ impl Media {
         bit_rate: i32,
     ) -> bool {
         unsafe {
-            mk_media_init_audio(self.0, codec_id.into(), sample_rate, channels, bit_rate) == 1
+            mk_media_init_audio(self.inner, codec_id.into(), sample_rate, channels, bit_rate) == 1
         }
     }
 
@@ -147,28 +227,508 @@ impl Media {
     /// `init_audio`). For single-track streams, ZLMediaKit otherwise waits ~3 seconds to see if
     /// more tracks will be added; calling this avoids that delay.
     pub fn init_complete(&self) {
-        unsafe { mk_media_init_complete(self.0) }
+        unsafe { mk_media_init_complete(self.inner) }
     }
 
     /// Feeds one encoded frame (video or audio) into the media source.
     ///
     /// Returns `true` if the frame was accepted.
     pub fn input_frame(&self, frame: &Frame) -> bool {
-        unsafe { mk_media_input_frame(self.0, frame.as_c_ptr()) == 1 }
+        unsafe { mk_media_input_frame(self.inner, frame.as_c_ptr()) == 1 }
+    }
+
+    /// Cleans up the timestamps given to [`input_data`](Self::input_data)
+    /// (and [`AutoMedia::input_data`]) from now on, see [`Normalizer`].
+    /// Frames given to [`input_frame`](Self::input_frame) are passed on
+    /// unchanged.
+    pub fn set_normalizer(&self, normalizer: Normalizer) {
+        *self.normalizer.lock().unwrap() = Some(normalizer);
+    }
+
+    /// Feeds one encoded frame with timestamps in the clock of the
+    /// [`Normalizer`] set by [`set_normalizer`](Self::set_normalizer), or in
+    /// milliseconds without one (a missing PTS then equals the DTS).
+    ///
+    /// Returns `true` if the frame was accepted.
+    pub fn input_data(
+        &self,
+        codec_id: CodecId,
+        dts: Option<u64>,
+        pts: Option<u64>,
+        data: &[u8],
+    ) -> bool {
+        self.input_frame(&self.normalized_frame(codec_id, dts, pts, data))
+    }
+
+    fn normalized_frame(
+        &self,
+        codec_id: CodecId,
+        dts: Option<u64>,
+        pts: Option<u64>,
+        data: &[u8],
+    ) -> Frame {
+        match self.normalizer.lock().unwrap().as_mut() {
+            Some(normalizer) => normalizer.frame(codec_id, dts, pts, data),
+            None => {
+                let dts = dts.or(pts).unwrap_or_default();
+                Frame::new(codec_id, dts, pts.unwrap_or(dts), data)
+            }
+        }
+    }
+
+    /// Sets up a video track fed with raw pictures through
+    /// [`input_yuv`](Self::input_yuv), encoded by ZLMediaKit.
+    ///
+    /// Fails for codecs other than H264; the keyframe interval is the
+    /// encoder's own, libmk_api does not expose it. Encoding needs a ZLMediaKit built with
+    /// `ENABLE_X264`, which the `transcode` feature's source build turns on.
+    #[cfg(feature = "transcode")]
+    pub fn init_video_encoder(&self, settings: &VideoEncoderSettings) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            settings.codec == CodecId::H264,
+            "built-in video encoder only supports H264, not {:?}",
+            settings.codec
+        );
+        anyhow::ensure!(
+            self.init_video(
+                settings.codec,
+                settings.width as i32,
+                settings.height as i32,
+                settings.fps,
+                settings.bit_rate,
+            ),
+            "failed to add video track"
+        );
+        self.encoder_size.set(settings.width, settings.height);
+        Ok(())
+    }
+
+    /// Sets up an audio track fed with raw samples through
+    /// [`input_pcm_raw`](Self::input_pcm_raw), encoded by ZLMediaKit.
+    ///
+    /// Fails for codecs other than AAC. Encoding needs a ZLMediaKit built
+    /// with `ENABLE_FAAC`.
+    #[cfg(feature = "transcode")]
+    pub fn init_audio_encoder(&self, settings: &AudioEncoderSettings) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            settings.codec == CodecId::AAC,
+            "built-in audio encoder only supports AAC, not {:?}",
+            settings.codec
+        );
+        anyhow::ensure!(
+            self.init_audio(
+                settings.codec,
+                settings.sample_rate,
+                settings.channels,
+                settings.bit_rate,
+            ),
+            "failed to add audio track"
+        );
+        Ok(())
+    }
+
+    /// Feeds one YUV420P picture to the encoder set up by
+    /// [`init_video_encoder`](Self::init_video_encoder).
+    ///
+    /// `planes` are Y, U and V; `strides` their bytes per row; `pts` is in
+    /// milliseconds.
+    #[cfg(feature = "transcode")]
+    pub fn input_yuv(
+        &self,
+        planes: [&[u8]; 3],
+        strides: [usize; 3],
+        pts: u64,
+    ) -> anyhow::Result<()> {
+        let (width, height) = self
+            .encoder_size
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("input_yuv needs init_video_encoder first"))?;
+        let chroma = (width.div_ceil(2), height.div_ceil(2));
+        for (index, (row_bytes, rows)) in [(width, height), chroma, chroma].into_iter().enumerate()
+        {
+            if strides[index] < row_bytes || planes[index].len() < strides[index] * rows {
+                anyhow::bail!(
+                    "plane {} too small: {} bytes at stride {} for {}x{}",
+                    index,
+                    planes[index].len(),
+                    strides[index],
+                    width,
+                    height
+                );
+            }
+        }
+
+        let mut yuv = planes.map(|plane| plane.as_ptr() as *const ::std::os::raw::c_char);
+        let mut linesize = strides.map(|stride| stride as i32);
+        unsafe { mk_media_input_yuv(self.inner, yuv.as_mut_ptr(), linesize.as_mut_ptr(), pts) };
+        Ok(())
+    }
+
+    /// Feeds interleaved 16 bit samples to the encoder set up by
+    /// [`init_audio_encoder`](Self::init_audio_encoder); `pts` is in milliseconds.
+    #[cfg(feature = "transcode")]
+    pub fn input_pcm_raw(&self, samples: &[i16], pts: u64) {
+        if samples.is_empty() {
+            return;
+        }
+        unsafe {
+            mk_media_input_pcm(
+                self.inner,
+                samples.as_ptr() as *mut _,
+                std::mem::size_of_val(samples) as i32,
+                pts,
+            )
+        }
+    }
+
+    /// The poller thread that owns this media source.
+    pub fn poller(&self) -> EventPoller {
+        unsafe { mk_media_get_owner_thread(self.inner) }.into()
+    }
+
+    /// Called when a player seeks, with the target position; return `true`
+    /// if the seek was handled.
+    pub fn on_seek(&self, cb: impl Fn(Duration) -> bool + Send + Sync + 'static) {
+        let cb: OnSeekFn = Box::new(cb);
+        unsafe {
+            mk_media_set_on_seek2(
+                self.inner,
+                Some(on_media_seek),
+                box_to_mut_void_ptr!(cb),
+                Some(free_user_data::<OnSeekFn>),
+            )
+        }
+    }
+
+    /// Called when a player pauses (`true`) or resumes (`false`); return
+    /// `true` if handled.
+    pub fn on_pause(&self, cb: impl Fn(bool) -> bool + Send + Sync + 'static) {
+        let cb: OnPauseFn = Box::new(cb);
+        unsafe {
+            mk_media_set_on_pause2(
+                self.inner,
+                Some(on_media_pause),
+                box_to_mut_void_ptr!(cb),
+                Some(free_user_data::<OnPauseFn>),
+            )
+        }
+    }
+
+    /// Called when a player changes the playback speed; return `true` if
+    /// handled.
+    pub fn on_speed(&self, cb: impl Fn(f32) -> bool + Send + Sync + 'static) {
+        let cb: OnSpeedFn = Box::new(cb);
+        unsafe {
+            mk_media_set_on_speed2(
+                self.inner,
+                Some(on_media_speed),
+                box_to_mut_void_ptr!(cb),
+                Some(free_user_data::<OnSpeedFn>),
+            )
+        }
+    }
+
+    /// Called when the stream is closed through the API or for lack of
+    /// readers; return `true` if the source will stop (and drop the `Media`).
+    pub fn on_close(&self, cb: impl Fn() -> bool + Send + Sync + 'static) {
+        let cb: OnCloseFn = Box::new(cb);
+        unsafe {
+            mk_media_set_on_close2(
+                self.inner,
+                Some(on_media_close),
+                box_to_mut_void_ptr!(cb),
+                Some(free_user_data::<OnCloseFn>),
+            )
+        }
     }
 }
 
+type OnSeekFn = Box<dyn Fn(Duration) -> bool + Send + Sync + 'static>;
+type OnPauseFn = Box<dyn Fn(bool) -> bool + Send + Sync + 'static>;
+type OnSpeedFn = Box<dyn Fn(f32) -> bool + Send + Sync + 'static>;
+type OnCloseFn = Box<dyn Fn() -> bool + Send + Sync + 'static>;
+
+extern "C" fn free_user_data<T>(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut T);
+            }
+        }
+    });
+}
+
+extern "C" fn on_media_seek(user_data: *mut ::std::os::raw::c_void, stamp_ms: u32) -> i32 {
+    crate::ffi_guard(|| unsafe {
+        let cb = &*(user_data as *const OnSeekFn);
+        cb(Duration::from_millis(stamp_ms as u64)) as i32
+    })
+}
+
+extern "C" fn on_media_pause(user_data: *mut ::std::os::raw::c_void, pause: i32) -> i32 {
+    crate::ffi_guard(|| unsafe {
+        let cb = &*(user_data as *const OnPauseFn);
+        cb(pause != 0) as i32
+    })
+}
+
+extern "C" fn on_media_speed(user_data: *mut ::std::os::raw::c_void, speed: f32) -> i32 {
+    crate::ffi_guard(|| unsafe {
+        let cb = &*(user_data as *const OnSpeedFn);
+        cb(speed) as i32
+    })
+}
+
+extern "C" fn on_media_close(user_data: *mut ::std::os::raw::c_void) -> i32 {
+    crate::ffi_guard(|| unsafe {
+        let cb = &*(user_data as *const OnCloseFn);
+        cb() as i32
+    })
+}
+
 impl Drop for Media {
     fn drop(&mut self) {
-        unsafe { mk_media_release(self.0) }
+        unsafe { mk_media_release(self.inner) }
     }
 }
 
 impl From<mk_media> for Media {
     fn from(sender: mk_media) -> Self {
-        Media(sender)
+        Media {
+            inner: sender,
+            encoder_size: EncoderSize::default(),
+            normalizer: Mutex::new(None),
+        }
     }
 }
 
 unsafe impl Send for Media {}
 unsafe impl Sync for Media {}
+
+/// Which tracks [`Media::auto`] waits for before calling `init_complete`.
+#[derive(Debug, Clone)]
+pub struct AutoOptions {
+    /// Expect an H264/H265 track.
+    pub video: bool,
+    /// Expect an AAC or G711 track.
+    pub audio: bool,
+    /// Completes with the tracks seen so far once this passes.
+    pub timeout: Duration,
+    /// Frames kept while waiting. Beyond this, frames are dropped up to the
+    /// next video keyframe (with its config frames), so the buffer still
+    /// starts where a decoder can; audio-only, the oldest frame is dropped.
+    pub max_buffered: usize,
+}
+
+impl Default for AutoOptions {
+    fn default() -> Self {
+        Self {
+            video: true,
+            audio: true,
+            timeout: Duration::from_secs(3),
+            max_buffered: 512,
+        }
+    }
+}
+
+/// A [`Media`] that probes its tracks from the frames fed to it.
+///
+/// Video frames are Annex-B H264/H265: the track is created from the first
+/// SPS, with its resolution and VUI frame rate (25 if absent). Audio frames
+/// are AAC with ADTS header (the track is created from the first header) or
+/// G711 at 8 kHz mono.
+/// Frames are buffered until every expected track was created, or until
+/// [`AutoOptions::timeout`] passes with at least one, then `init_complete` is
+/// called and the buffer flushed in order.
+///
+/// ```ignore
+/// let media = Media::auto(DEFAULT_VHOST, "live", "cam", false, false, AutoOptions {
+///     audio: false,
+///     ..Default::default()
+/// });
+/// media.input_frame(&Frame::new(CodecId::H264, dts, pts, annexb));
+/// ```
+pub struct AutoMedia(Arc<AutoShared>);
+
+struct AutoShared {
+    media: Media,
+    options: AutoOptions,
+    state: Mutex<AutoState>,
+}
+
+#[derive(Default)]
+struct AutoState {
+    video: Option<CodecId>,
+    audio: bool,
+    parameter_sets: ParameterSets,
+    pending: VecDeque<Frame>,
+    timed_out: bool,
+    ready: bool,
+}
+
+impl AutoMedia {
+    fn new(media: Media, options: AutoOptions) -> Self {
+        let timeout = options.timeout;
+        let poller = media.poller();
+        let shared = Arc::new(AutoShared {
+            media,
+            options,
+            state: Mutex::new(AutoState::default()),
+        });
+
+        let weak: Weak<AutoShared> = Arc::downgrade(&shared);
+        poller.spawn_delay(timeout, move || {
+            if let Some(shared) = weak.upgrade() {
+                let mut state = shared.state.lock().unwrap();
+                state.timed_out = true;
+                shared.try_complete(&mut state);
+            }
+        });
+        AutoMedia(shared)
+    }
+
+    /// Feeds one frame; it is buffered until the tracks are set up.
+    ///
+    /// Returns `false` for frames of a codec that cannot be probed or was not
+    /// expected, and for frames the media source rejected.
+    pub fn input_frame(&self, frame: &Frame) -> bool {
+        let shared = &self.0;
+        let mut state = shared.state.lock().unwrap();
+        if state.ready {
+            return shared.media.input_frame(frame);
+        }
+
+        match frame.codec_id() {
+            Ok(codec @ (CodecId::H264 | CodecId::H265)) if shared.options.video => {
+                if state.video.is_none() {
+                    shared.probe_video(&mut state, codec, frame.data());
+                }
+            }
+            Ok(CodecId::AAC) if shared.options.audio => {
+                if !state.audio {
+                    shared.probe_audio(&mut state, frame.data());
+                }
+            }
+            Ok(codec @ (CodecId::G711A | CodecId::G711U)) if shared.options.audio => {
+                if !state.audio {
+                    state.audio = shared.media.init_audio(codec, 8000, 1, 64000);
+                }
+            }
+            _ => return false,
+        }
+
+        state.pending.push_back(frame.clone());
+        if state.pending.len() > shared.options.max_buffered {
+            state.trim();
+        }
+        shared.try_complete(&mut state);
+        true
+    }
+
+    /// [`input_frame`](Self::input_frame) with timestamps cleaned up by the
+    /// [`Normalizer`] of the underlying media, as in [`Media::input_data`].
+    pub fn input_data(
+        &self,
+        codec_id: CodecId,
+        dts: Option<u64>,
+        pts: Option<u64>,
+        data: &[u8],
+    ) -> bool {
+        self.input_frame(&self.0.media.normalized_frame(codec_id, dts, pts, data))
+    }
+
+    /// Whether `init_complete` was called.
+    pub fn is_ready(&self) -> bool {
+        self.0.state.lock().unwrap().ready
+    }
+
+    /// The underlying media source, e.g. for [`Media::poller`].
+    pub fn media(&self) -> &Media {
+        &self.0.media
+    }
+}
+
+impl AutoState {
+    /// Drops one frame or more from a full buffer, keeping a config and
+    /// keyframe run at its start.
+    fn trim(&mut self) {
+        let starts =
+            |frame: &Frame| frame.is_video() && (frame.is_config_frame() || frame.is_key_frame());
+        if !self.pending.iter().any(starts) {
+            self.pending.pop_front();
+            return;
+        }
+        let next = (1..self.pending.len())
+            .find(|&i| starts(&self.pending[i]) && !starts(&self.pending[i - 1]));
+        match next {
+            Some(next) => drop(self.pending.drain(..next)),
+            // the buffered run is all there is to start from
+            None => drop(self.pending.pop_back()),
+        }
+    }
+}
+
+impl AutoShared {
+    fn probe_video(&self, state: &mut AutoState, codec: CodecId, data: &[u8]) {
+        for unit in nal::split_annexb(data) {
+            if let Ok(unit) = NalUnit::parse(codec, unit) {
+                state.parameter_sets.update(&unit);
+            }
+        }
+        let Some(info) = state.parameter_sets.sps_info() else {
+            return;
+        };
+        if self.media.init_video(
+            codec,
+            info.width as i32,
+            info.height as i32,
+            info.fps.unwrap_or(25.0),
+            0,
+        ) {
+            state.video = Some(codec);
+        }
+    }
+
+    fn probe_audio(&self, state: &mut AutoState, data: &[u8]) {
+        let Some((sample_rate, channels)) = parse_adts(data) else {
+            return;
+        };
+        state.audio = self
+            .media
+            .init_audio(CodecId::AAC, sample_rate, channels, 0);
+    }
+
+    fn try_complete(&self, state: &mut AutoState) {
+        let has_video = state.video.is_some();
+        let all = (has_video || !self.options.video) && (state.audio || !self.options.audio);
+        let any = has_video || state.audio;
+        if state.ready || !any || !(all || state.timed_out) {
+            return;
+        }
+
+        self.media.init_complete();
+        state.ready = true;
+        let is_wanted = |frame: &Frame| match frame.codec_id() {
+            Ok(codec) if codec.is_audio() => state.audio,
+            _ => has_video,
+        };
+        for frame in std::mem::take(&mut state.pending) {
+            if is_wanted(&frame) {
+                self.media.input_frame(&frame);
+            }
+        }
+    }
+}
+
+/// Sample rate and channel count from an ADTS header.
+fn parse_adts(data: &[u8]) -> Option<(i32, i32)> {
+    const SAMPLE_RATES: [i32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+    if data.len() < 7 || data[0] != 0xFF || data[1] & 0xF0 != 0xF0 {
+        return None;
+    }
+    let sample_rate = *SAMPLE_RATES.get(((data[2] >> 2) & 0x0F) as usize)?;
+    let channels = ((data[2] & 0x01) << 2 | data[3] >> 6) as i32;
+    (channels > 0).then_some((sample_rate, channels))
+}
@@ -0,0 +1,368 @@
+//! Stream and session metrics (`metrics` feature).
+//!
+//! Counters are fed from ZLMediaKit's events and published twice: through the
+//! [`metrics`](::metrics) facade, for whatever recorder the application
+//! installs, and as Prometheus text via [`render`] / [`serve`], which needs no
+//! recorder at all.
+//!
+//! | name | type | labels |
+//! |------|------|--------|
+//! | `zlm_bytes_total` | counter | app, stream, schema, direction (`in`/`out`) |
+//! | `zlm_active_readers` | gauge | app, stream |
+//! | `zlm_active_publishers` | gauge | |
+//! | `zlm_record_segments_total` | counter | app, stream, format (`mp4`/`ts`) |
+//! | `zlm_auth_denials_total` | counter | kind (`play`/`publish`/`shell`) |
+//! | `zlm_proxy_restarts_total` | counter | app, stream |
+//!
+//! Byte counts come from flow reports, which ZLMediaKit only emits for
+//! sessions above `general.flowThreshold` (KB) when they end. Play denials are
+//! only seen when an `on_media_play` callback is registered.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    sync::Mutex,
+};
+
+use once_cell::sync::Lazy;
+use rszlm_sys::*;
+
+use crate::{
+    const_str_to_ptr,
+    event::{FlowReportMessage, HttpRequestMessage, EVENTS},
+    obj::{MediaSource, RecordInfo},
+};
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+/// (app, stream)
+type StreamKey = (String, String);
+
+#[derive(Default)]
+struct Registry {
+    /// (app, stream, schema, direction)
+    bytes: BTreeMap<(String, String, String, &'static str), u64>,
+    /// Registered sources: vhost and the schemas they are muxed to.
+    sources: BTreeMap<StreamKey, (String, BTreeSet<String>)>,
+    readers: BTreeMap<StreamKey, u64>,
+    /// (app, stream, format)
+    record_segments: BTreeMap<(String, String, &'static str), u64>,
+    auth_denials: BTreeMap<&'static str, u64>,
+    proxy_restarts: BTreeMap<StreamKey, u64>,
+}
+
+/// Subscribes the events metrics are derived from and describes them to the
+/// facade. Call once after [`EnvInitBuilder::build`](crate::init::EnvInitBuilder::build).
+///
+/// Only ZLMediaKit's event slots are touched; callbacks registered on
+/// [`EVENTS`] keep working and may be registered before or after.
+pub fn install() {
+    ::metrics::describe_counter!(
+        "zlm_bytes_total",
+        ::metrics::Unit::Bytes,
+        "Bytes transferred by finished sessions."
+    );
+    ::metrics::describe_gauge!("zlm_active_readers", "Readers of a stream, all protocols.");
+    ::metrics::describe_gauge!("zlm_active_publishers", "Streams currently registered.");
+    ::metrics::describe_counter!("zlm_record_segments_total", "Record segments written.");
+    ::metrics::describe_counter!(
+        "zlm_auth_denials_total",
+        "Denied play/publish/shell logins."
+    );
+    ::metrics::describe_counter!("zlm_proxy_restarts_total", "Proxy players played again.");
+    EVENTS.write().unwrap().observe_for_metrics();
+}
+
+pub(crate) fn on_flow_report(msg: &FlowReportMessage) {
+    let app = msg.url_info.app();
+    let stream = msg.url_info.stream();
+    let schema = msg.url_info.schema();
+    let direction = if msg.is_player { "out" } else { "in" };
+    ::metrics::counter!(
+        "zlm_bytes_total",
+        "app" => app.clone(),
+        "stream" => stream.clone(),
+        "schema" => schema.clone(),
+        "direction" => direction
+    )
+    .increment(msg.total_bytes as u64);
+    *REGISTRY
+        .lock()
+        .unwrap()
+        .bytes
+        .entry((app.clone(), stream.clone(), schema, direction))
+        .or_default() += msg.total_bytes as u64;
+
+    refresh_stream((app, stream));
+}
+
+pub(crate) fn on_media_changed(regist: bool, source: &MediaSource) {
+    let key = (source.app(), source.stream());
+    let publishers = {
+        let mut registry = REGISTRY.lock().unwrap();
+        if regist {
+            let vhost = source.vhost();
+            let entry = registry
+                .sources
+                .entry(key.clone())
+                .or_insert_with(|| (vhost, BTreeSet::new()));
+            entry.1.insert(source.schema());
+        } else if let Some(entry) = registry.sources.get_mut(&key) {
+            entry.1.remove(&source.schema());
+            if entry.1.is_empty() {
+                registry.sources.remove(&key);
+                registry.readers.remove(&key);
+                ::metrics::gauge!("zlm_active_readers", "app" => key.0.clone(), "stream" => key.1.clone())
+                    .set(0.0);
+            }
+        }
+        registry.sources.len()
+    };
+    ::metrics::gauge!("zlm_active_publishers").set(publishers as f64);
+}
+
+pub(crate) fn on_media_no_reader(source: &MediaSource) {
+    refresh_stream((source.app(), source.stream()));
+}
+
+pub(crate) fn on_record(info: &RecordInfo, format: &'static str) {
+    let (app, stream) = (info.app(), info.stream());
+    ::metrics::counter!(
+        "zlm_record_segments_total",
+        "app" => app.clone(),
+        "stream" => stream.clone(),
+        "format" => format
+    )
+    .increment(1);
+    *REGISTRY
+        .lock()
+        .unwrap()
+        .record_segments
+        .entry((app, stream, format))
+        .or_default() += 1;
+}
+
+pub(crate) fn on_auth_denied(kind: &'static str) {
+    ::metrics::counter!("zlm_auth_denials_total", "kind" => kind).increment(1);
+    *REGISTRY
+        .lock()
+        .unwrap()
+        .auth_denials
+        .entry(kind)
+        .or_default() += 1;
+}
+
+pub(crate) fn on_proxy_restart(app: &str, stream: &str) {
+    ::metrics::counter!(
+        "zlm_proxy_restarts_total",
+        "app" => app.to_string(),
+        "stream" => stream.to_string()
+    )
+    .increment(1);
+    *REGISTRY
+        .lock()
+        .unwrap()
+        .proxy_restarts
+        .entry((app.to_string(), stream.to_string()))
+        .or_default() += 1;
+}
+
+/// Re-reads the reader count of every registered stream.
+///
+/// Readers come and go without an event, so call this periodically when
+/// only the facade is scraped; [`render`] does it itself.
+pub fn refresh() {
+    let keys: Vec<StreamKey> = REGISTRY.lock().unwrap().sources.keys().cloned().collect();
+    for key in keys {
+        refresh_stream(key);
+    }
+}
+
+fn refresh_stream(key: StreamKey) {
+    let Some((vhost, schema)) = REGISTRY
+        .lock()
+        .unwrap()
+        .sources
+        .get(&key)
+        .and_then(|(vhost, schemas)| Some((vhost.clone(), schemas.first()?.clone())))
+    else {
+        return;
+    };
+
+    // looked up without holding the registry lock: ZLMediaKit may emit
+    // on_media_changed from under its own source lock
+    let readers = total_reader_count(&schema, &vhost, &key.0, &key.1);
+    ::metrics::gauge!("zlm_active_readers", "app" => key.0.clone(), "stream" => key.1.clone())
+        .set(readers as f64);
+    let mut registry = REGISTRY.lock().unwrap();
+    if registry.sources.contains_key(&key) {
+        registry.readers.insert(key, readers);
+    }
+}
+
+fn total_reader_count(schema: &str, vhost: &str, app: &str, stream: &str) -> u64 {
+    let mut count = 0u64;
+    let (schema, vhost, app, stream) = (
+        const_str_to_ptr!(schema),
+        const_str_to_ptr!(vhost),
+        const_str_to_ptr!(app),
+        const_str_to_ptr!(stream),
+    );
+    unsafe {
+        // the callback runs synchronously while the source is kept alive
+        mk_media_source_find(
+            schema.as_ptr(),
+            vhost.as_ptr(),
+            app.as_ptr(),
+            stream.as_ptr(),
+            0,
+            &mut count as *mut u64 as *mut _,
+            Some(on_find_source),
+        )
+    };
+    count
+}
+
+extern "C" fn on_find_source(user_data: *mut ::std::os::raw::c_void, source: mk_media_source) {
+    crate::ffi_guard(|| {
+        if !source.is_null() {
+            let count = unsafe { &mut *(user_data as *mut u64) };
+            *count = unsafe { mk_media_source_get_total_reader_count(source) }.max(0) as u64;
+        }
+    });
+}
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    refresh();
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "zlm_bytes_total",
+        "counter",
+        "Bytes transferred by finished sessions.",
+    );
+    for ((app, stream, schema, direction), bytes) in &registry.bytes {
+        let _ = writeln!(
+            out,
+            "zlm_bytes_total{{app=\"{}\",stream=\"{}\",schema=\"{}\",direction=\"{}\"}} {}",
+            escape(app),
+            escape(stream),
+            escape(schema),
+            direction,
+            bytes
+        );
+    }
+
+    header(
+        &mut out,
+        "zlm_active_readers",
+        "gauge",
+        "Readers of a stream, all protocols.",
+    );
+    for ((app, stream), readers) in &registry.readers {
+        let _ = writeln!(
+            out,
+            "zlm_active_readers{{app=\"{}\",stream=\"{}\"}} {}",
+            escape(app),
+            escape(stream),
+            readers
+        );
+    }
+
+    header(
+        &mut out,
+        "zlm_active_publishers",
+        "gauge",
+        "Streams currently registered.",
+    );
+    let _ = writeln!(out, "zlm_active_publishers {}", registry.sources.len());
+
+    header(
+        &mut out,
+        "zlm_record_segments_total",
+        "counter",
+        "Record segments written.",
+    );
+    for ((app, stream, format), count) in &registry.record_segments {
+        let _ = writeln!(
+            out,
+            "zlm_record_segments_total{{app=\"{}\",stream=\"{}\",format=\"{}\"}} {}",
+            escape(app),
+            escape(stream),
+            format,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "zlm_auth_denials_total",
+        "counter",
+        "Denied play/publish/shell logins.",
+    );
+    for (kind, count) in &registry.auth_denials {
+        let _ = writeln!(out, "zlm_auth_denials_total{{kind=\"{}\"}} {}", kind, count);
+    }
+
+    header(
+        &mut out,
+        "zlm_proxy_restarts_total",
+        "counter",
+        "Proxy players played again.",
+    );
+    for ((app, stream), count) in &registry.proxy_restarts {
+        let _ = writeln!(
+            out,
+            "zlm_proxy_restarts_total{{app=\"{}\",stream=\"{}\"}} {}",
+            escape(app),
+            escape(stream),
+            count
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers a `GET` of `path` with [`render`]; returns whether the request was
+/// consumed. For use inside an existing `on_http_request` callback.
+pub fn handle_http(msg: &HttpRequestMessage, path: &str) -> bool {
+    if msg.parser.url() != path {
+        return false;
+    }
+    msg.invoker.invoke(
+        200,
+        vec![
+            "Content-Type".to_string(),
+            "text/plain; version=0.0.4".to_string(),
+        ],
+        &render(),
+    );
+    true
+}
+
+/// Serves [`render`] at `path` on ZLMediaKit's http server.
+///
+/// This takes the `on_http_request` slot; if the application needs it too, call
+/// [`handle_http`] from its own callback instead.
+pub fn serve(path: &str) {
+    let path = path.to_string();
+    EVENTS
+        .write()
+        .unwrap()
+        .on_http_request(move |msg| handle_http(&msg, &path));
+}
@@ -0,0 +1,1164 @@
+//! MP4 files played by Rust instead of `mk_load_mp4_file`
+//! ([`Mp4ProxyPlayer`](crate::player::Mp4ProxyPlayer)): [`Mp4File`] demuxes
+//! a progressive MP4 with keyframe seeking, and [`Mp4Source`] publishes a
+//! playlist of clips with in/out points and looping as one stream with
+//! continuous timestamps, honouring players' seek, pause and speed requests.
+//!
+//! ```ignore
+//! let source = Mp4Source::builder(DEFAULT_VHOST, "vod", "show")
+//!     .clip(Clip::new("intro.mp4"))
+//!     .clip(Clip::new("main.mp4").start(Duration::from_secs(30)).end(Duration::from_secs(90)))
+//!     .looping(true)
+//!     .start()?;
+//! // ... publishing until `source` is dropped
+//! ```
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    flv::{config_to_adts, length_prefixed_to_annexb, parse_avcc, parse_hvcc, TrackConfig},
+    frame::Frame,
+    media::Media,
+    obj::CodecId,
+    timestamp::Splicer,
+};
+
+const AAC_SAMPLE_RATES: [i32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+#[derive(Debug, Clone, Copy)]
+struct SampleEntry {
+    offset: u64,
+    size: u32,
+    /// In the track timescale.
+    dts: u64,
+    cts: i32,
+    keyframe: bool,
+}
+
+#[derive(Debug)]
+struct Mp4Track {
+    config: TrackConfig,
+    timescale: u32,
+    /// NAL length size for H264/H265.
+    nal_length_size: usize,
+    /// Annex-B parameter sets put in front of keyframes.
+    parameter_sets: Vec<u8>,
+    samples: Vec<SampleEntry>,
+    /// Next sample to read.
+    cursor: usize,
+}
+
+impl Mp4Track {
+    fn to_ms(&self, value: i64) -> i64 {
+        value * 1000 / self.timescale as i64
+    }
+
+    fn next_dts_ms(&self) -> Option<i64> {
+        self.samples
+            .get(self.cursor)
+            .map(|sample| self.to_ms(sample.dts as i64))
+    }
+}
+
+/// One sample read from an [`Mp4File`], with timestamps in milliseconds.
+#[derive(Debug, Clone)]
+pub struct Mp4Sample {
+    pub codec: CodecId,
+    pub dts: u64,
+    pub pts: u64,
+    pub keyframe: bool,
+    /// Annex-B (with parameter sets on keyframes) or ADTS AAC.
+    pub data: Vec<u8>,
+}
+
+impl Mp4Sample {
+    pub fn to_frame(&self) -> Frame {
+        Frame::new(self.codec, self.dts, self.pts, &self.data)
+    }
+}
+
+/// A progressive (non-fragmented) MP4 file with H264, H265 or AAC tracks;
+/// samples are read in decode order across tracks. Edit lists are ignored.
+#[derive(Debug)]
+pub struct Mp4File {
+    file: File,
+    tracks: Vec<Mp4Track>,
+    duration: Duration,
+}
+
+impl Mp4File {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        // find moov without reading mdat
+        let mut pos = 0;
+        let moov = loop {
+            if pos + 8 > len {
+                anyhow::bail!("{}: no moov box", path.display());
+            }
+            file.seek(SeekFrom::Start(pos))?;
+            let mut header = [0u8; 16];
+            file.read_exact(&mut header[..8])?;
+            let mut size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+            let mut header_len = 8;
+            if size == 1 {
+                file.read_exact(&mut header[8..])?;
+                size = u64::from_be_bytes(header[8..].try_into().unwrap());
+                header_len = 16;
+            } else if size == 0 {
+                size = len - pos;
+            }
+            if size < header_len || size > len - pos {
+                anyhow::bail!("{}: bad box size at {}", path.display(), pos);
+            }
+            if &header[4..8] == b"moov" {
+                let mut moov = vec![0; (size - header_len) as usize];
+                file.read_exact(&mut moov)?;
+                break moov;
+            }
+            pos += size;
+        };
+
+        let tracks: Vec<Mp4Track> = boxes(&moov)
+            .filter(|(kind, _)| kind == b"trak")
+            .filter_map(|(_, trak)| parse_trak(trak, len))
+            .collect();
+        if tracks.is_empty() {
+            anyhow::bail!("{}: no H264, H265 or AAC track", path.display());
+        }
+        let duration = tracks
+            .iter()
+            .filter_map(|track| {
+                let last = track.samples.last()?;
+                // plus an average sample duration
+                let average = last.dts as i64 / (track.samples.len() as i64 - 1).max(1);
+                let end = last.dts as i64 + average;
+                Some(track.to_ms(end).max(0) as u64)
+            })
+            .max()
+            .unwrap_or_default();
+
+        Ok(Self {
+            file,
+            tracks,
+            duration: Duration::from_millis(duration),
+        })
+    }
+
+    /// Codec setup of each track, e.g. for [`TrackConfig::init`].
+    pub fn track_configs(&self) -> Vec<TrackConfig> {
+        self.tracks
+            .iter()
+            .map(|track| track.config.clone())
+            .collect()
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Moves to the last video keyframe at or before `position` (audio
+    /// follows from there); returns the keyframe's time.
+    pub fn seek(&mut self, position: Duration) -> Duration {
+        let target = position.as_millis() as i64;
+        let keyframe = self
+            .tracks
+            .iter()
+            .filter(|track| matches!(track.config, TrackConfig::Video { .. }))
+            .filter_map(|track| {
+                track
+                    .samples
+                    .iter()
+                    .filter(|sample| sample.keyframe)
+                    .map(|sample| track.to_ms(sample.dts as i64))
+                    .take_while(|dts| *dts <= target)
+                    .last()
+            })
+            .min()
+            .unwrap_or(target);
+
+        for track in &mut self.tracks {
+            let timescale = track.timescale as i64;
+            track.cursor = track
+                .samples
+                .partition_point(|sample| (sample.dts as i64) * 1000 / timescale < keyframe);
+        }
+        Duration::from_millis(keyframe.max(0) as u64)
+    }
+
+    /// The next sample in decode order, `None` at the end.
+    pub fn next_sample(&mut self) -> anyhow::Result<Option<Mp4Sample>> {
+        let Some(track) = self
+            .tracks
+            .iter_mut()
+            .filter(|track| track.cursor < track.samples.len())
+            .min_by_key(|track| track.next_dts_ms())
+        else {
+            return Ok(None);
+        };
+        let sample = track.samples[track.cursor];
+        track.cursor += 1;
+
+        let mut raw = vec![0; sample.size as usize];
+        self.file.seek(SeekFrom::Start(sample.offset))?;
+        self.file.read_exact(&mut raw)?;
+
+        let codec = track.config.codec();
+        let data = match &track.config {
+            TrackConfig::Audio { config, .. } => {
+                let object_type = config.first().map_or(2, |byte| byte >> 3);
+                let rate_index = config.first().map_or(4, |byte| (byte & 0x07) << 1)
+                    | config.get(1).map_or(0, |byte| byte >> 7);
+                let channels = config.get(1).map_or(2, |byte| (byte >> 3) & 0x0F);
+                config_to_adts(object_type, rate_index, channels, &raw)
+            }
+            TrackConfig::Video { .. } => {
+                let mut data = Vec::with_capacity(raw.len() + track.parameter_sets.len() + 16);
+                if sample.keyframe {
+                    data.extend_from_slice(&track.parameter_sets);
+                }
+                data.extend(length_prefixed_to_annexb(&raw, track.nal_length_size));
+                data
+            }
+        };
+
+        let dts = track.to_ms(sample.dts as i64).max(0);
+        let pts = track.to_ms(sample.dts as i64 + sample.cts as i64).max(dts);
+        Ok(Some(Mp4Sample {
+            codec,
+            dts: dts as u64,
+            pts: pts as u64,
+            keyframe: sample.keyframe,
+            data,
+        }))
+    }
+}
+
+/// Iterates `(type, content)` of the boxes in `data`.
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let kind: [u8; 4] = data[4..8].try_into().unwrap();
+        let (size, header) = match u32::from_be_bytes(data[..4].try_into().unwrap()) {
+            0 => (data.len(), 8),
+            1 => (
+                u64::from_be_bytes(data.get(8..16)?.try_into().unwrap()) as usize,
+                16,
+            ),
+            size => (size as usize, 8),
+        };
+        if size < header || size > data.len() {
+            return None;
+        }
+        let content = &data[header..size];
+        data = &data[size..];
+        Some((kind, content))
+    })
+}
+
+fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let content = boxes(data).find(|(kind, _)| kind == *first)?.1;
+    if rest.is_empty() {
+        Some(content)
+    } else {
+        child(content, rest)
+    }
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Entries of a full box table: `(count, entries)` after the version/flags.
+fn table(data: &[u8], entry_size: usize) -> Option<Vec<&[u8]>> {
+    let count = be_u32(data, 4)? as usize;
+    let entries = data.get(8..8 + count.checked_mul(entry_size)?)?;
+    Some(entries.chunks_exact(entry_size).collect())
+}
+
+/// `file_len` bounds the sample table.
+fn parse_trak(trak: &[u8], file_len: u64) -> Option<Mp4Track> {
+    let mdhd = child(trak, &[b"mdia", b"mdhd"])?;
+    let timescale = if *mdhd.first()? == 1 {
+        be_u32(mdhd, 20)?
+    } else {
+        be_u32(mdhd, 12)?
+    };
+    let stbl = child(trak, &[b"mdia", b"minf", b"stbl"])?;
+    let stsd = child(stbl, &[b"stsd"])?;
+    let (entry_kind, entry) = boxes(stsd.get(8..)?).next()?;
+
+    let mut nal_length_size = 4;
+    let mut parameter_sets = Vec::new();
+    let config = match &entry_kind {
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" => {
+            let (codec, record_kind) = if entry_kind.starts_with(b"avc") {
+                (CodecId::H264, b"avcC")
+            } else {
+                (CodecId::H265, b"hvcC")
+            };
+            let record = child(entry.get(78..)?, &[record_kind])?;
+            let (length_size, nals) = match codec {
+                CodecId::H264 => parse_avcc(record)?,
+                _ => parse_hvcc(record)?,
+            };
+            nal_length_size = length_size;
+            for nal in nals {
+                parameter_sets.extend_from_slice(&[0, 0, 0, 1]);
+                parameter_sets.extend_from_slice(&nal);
+            }
+            TrackConfig::Video {
+                codec,
+                width: be_u16(entry, 24)? as i32,
+                height: be_u16(entry, 26)? as i32,
+                fps: None,
+                record: record.to_vec(),
+            }
+        }
+        b"mp4a" => {
+            let children = match be_u16(entry, 8)? {
+                1 => entry.get(44..)?,
+                2 => entry.get(64..)?,
+                _ => entry.get(28..)?,
+            };
+            let config = decoder_specific_info(child(children, &[b"esds"])?.get(4..)?)?;
+            let rate_index = ((config.first()? & 0x07) << 1 | config.get(1)? >> 7) as usize;
+            TrackConfig::Audio {
+                codec: CodecId::AAC,
+                sample_rate: *AAC_SAMPLE_RATES.get(rate_index)?,
+                channels: ((config[1] >> 3) & 0x0F) as i32,
+                config: config.to_vec(),
+            }
+        }
+        _ => return None,
+    };
+
+    let samples = sample_table(stbl, file_len)?;
+    let config = match config {
+        TrackConfig::Video {
+            codec,
+            width,
+            height,
+            record,
+            ..
+        } => {
+            let span = samples.last().map_or(0, |last| last.dts) as f32 / timescale as f32;
+            let fps = (span > 0.0).then(|| (samples.len() - 1) as f32 / span);
+            TrackConfig::Video {
+                codec,
+                width,
+                height,
+                fps,
+                record,
+            }
+        }
+        audio => audio,
+    };
+
+    Some(Mp4Track {
+        config,
+        timescale: timescale.max(1),
+        nal_length_size,
+        parameter_sets,
+        samples,
+        cursor: 0,
+    })
+}
+
+/// The AudioSpecificConfig inside an `esds` ES_Descriptor.
+fn decoder_specific_info(mut data: &[u8]) -> Option<&[u8]> {
+    loop {
+        let (&tag, rest) = data.split_first()?;
+        // expandable size, up to 4 bytes
+        let mut len = 0usize;
+        let mut used = 0;
+        for &byte in rest.iter().take(4) {
+            len = len << 7 | (byte & 0x7F) as usize;
+            used += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let body = rest.get(used..used + len)?;
+        data = match tag {
+            // ES_ID, flags (no dependency/URL/OCR streams expected)
+            0x03 => body.get(3..)?,
+            // object type, stream type, buffer size, bitrates
+            0x04 => body.get(13..)?,
+            0x05 => return Some(body),
+            _ => return None,
+        };
+    }
+}
+
+fn sample_table(stbl: &[u8], file_len: u64) -> Option<Vec<SampleEntry>> {
+    let stsz = child(stbl, &[b"stsz"])?;
+    let fixed_size = be_u32(stsz, 4)?;
+    let count = be_u32(stsz, 8)? as usize;
+    // the count is checked before anything is sized by it
+    let sizes: Vec<u32> = if fixed_size != 0 {
+        if (count as u64).checked_mul(fixed_size as u64)? > file_len {
+            return None;
+        }
+        vec![fixed_size; count]
+    } else {
+        stsz.get(12..12 + count.checked_mul(4)?)?
+            .chunks_exact(4)
+            .map(|entry| be_u32(entry, 0))
+            .collect::<Option<_>>()?
+    };
+
+    let chunk_offsets: Vec<u64> = match child(stbl, &[b"stco"]) {
+        Some(stco) => table(stco, 4)?
+            .into_iter()
+            .map(|entry| be_u32(entry, 0).unwrap() as u64)
+            .collect(),
+        None => table(child(stbl, &[b"co64"])?, 8)?
+            .into_iter()
+            .map(|entry| be_u64(entry, 0).unwrap())
+            .collect(),
+    };
+
+    // (first chunk, samples per chunk)
+    let stsc: Vec<(u32, u32)> = table(child(stbl, &[b"stsc"])?, 12)?
+        .into_iter()
+        .map(|entry| (be_u32(entry, 0).unwrap(), be_u32(entry, 4).unwrap()))
+        .collect();
+    let mut offsets = Vec::with_capacity(count);
+    let mut sample = 0;
+    for (index, &(first, per_chunk)) in stsc.iter().enumerate() {
+        let last = match stsc.get(index + 1) {
+            Some(next) => next.0.checked_sub(1)?,
+            None => chunk_offsets.len() as u32,
+        };
+        for chunk in first..=last {
+            let mut offset = *chunk_offsets.get((chunk as usize).checked_sub(1)?)?;
+            for _ in 0..per_chunk {
+                if sample >= count {
+                    break;
+                }
+                // every sample is read in one piece, so it must be in the file
+                let end = offset.checked_add(sizes[sample] as u64)?;
+                if end > file_len {
+                    return None;
+                }
+                offsets.push(offset);
+                offset = end;
+                sample += 1;
+            }
+        }
+    }
+    if offsets.len() < count {
+        return None;
+    }
+
+    let mut dts = Vec::with_capacity(count);
+    let mut time = 0u64;
+    for entry in table(child(stbl, &[b"stts"])?, 8)? {
+        for _ in 0..be_u32(entry, 0)?.min((count - dts.len()) as u32) {
+            dts.push(time);
+            time += be_u32(entry, 4)? as u64;
+        }
+    }
+    dts.resize(count, time);
+
+    let mut cts = Vec::with_capacity(count);
+    if let Some(ctts) = child(stbl, &[b"ctts"]) {
+        for entry in table(ctts, 8)? {
+            // signed in version 1, and in practice in version 0 as well
+            cts.extend(std::iter::repeat_n(
+                be_u32(entry, 4)? as i32,
+                (be_u32(entry, 0)? as usize).min(count - cts.len()),
+            ));
+        }
+    }
+    cts.resize(count, 0);
+
+    let mut keyframes = vec![child(stbl, &[b"stss"]).is_none(); count];
+    if let Some(stss) = child(stbl, &[b"stss"]) {
+        for entry in table(stss, 4)? {
+            if let Some(keyframe) = keyframes.get_mut((be_u32(entry, 0)? as usize).checked_sub(1)?)
+            {
+                *keyframe = true;
+            }
+        }
+    }
+
+    Some(
+        (0..count)
+            .map(|index| SampleEntry {
+                offset: offsets[index],
+                size: sizes[index],
+                dts: dts[index],
+                cts: cts[index],
+                keyframe: keyframes[index],
+            })
+            .collect(),
+    )
+}
+
+/// One playlist entry of an [`Mp4Source`].
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub path: PathBuf,
+    /// In point; playback starts at the keyframe at or before it.
+    pub start: Option<Duration>,
+    /// Out point; samples from here on are skipped.
+    pub end: Option<Duration>,
+}
+
+impl Clip {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            start: None,
+            end: None,
+        }
+    }
+
+    pub fn start(mut self, start: Duration) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn end(mut self, end: Duration) -> Self {
+        self.end = Some(end);
+        self
+    }
+}
+
+/// What a [`PacedFile`] has next.
+pub(crate) enum Due {
+    /// A sample that is due now.
+    Sample(Mp4Sample),
+    /// Nothing is due for this long.
+    Wait(Duration),
+    /// A repeating file started over at its in point; its time line restarts.
+    Looped,
+    /// Past the out point.
+    End,
+    Failed(anyhow::Error),
+}
+
+/// A clip read in real time: samples come out when they are due on the wall
+/// clock, counted from the first one read.
+pub(crate) struct PacedFile {
+    file: Mp4File,
+    /// In point snapped to a keyframe, file time in milliseconds.
+    start: u64,
+    end: u64,
+    /// Starts over at the out point.
+    repeat: bool,
+    speed: f32,
+    pending: Option<Mp4Sample>,
+    /// (wall clock, file time) pacing starts from.
+    anchor: Option<(Instant, u64)>,
+    /// Started over without a sample since, so an empty clip does not loop
+    /// forever.
+    looped: bool,
+}
+
+impl PacedFile {
+    pub(crate) fn open(clip: &Clip, repeat: bool) -> anyhow::Result<Self> {
+        let mut file = Mp4File::open(&clip.path)?;
+        let start = file.seek(clip.start.unwrap_or_default()).as_millis() as u64;
+        let end = clip
+            .end
+            .map_or(file.duration(), |end| end.min(file.duration()))
+            .as_millis() as u64;
+        Ok(Self {
+            file,
+            start,
+            end,
+            repeat,
+            speed: 1.0,
+            pending: None,
+            anchor: None,
+            looped: false,
+        })
+    }
+
+    pub(crate) fn file(&self) -> &Mp4File {
+        &self.file
+    }
+
+    pub(crate) fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub(crate) fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Pacing starts over from the next sample, e.g. after a pause.
+    pub(crate) fn reanchor(&mut self) {
+        self.anchor = None;
+    }
+
+    pub(crate) fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        self.anchor = None;
+    }
+
+    /// Back to the in point.
+    pub(crate) fn rewind(&mut self) {
+        self.seek(Duration::from_millis(self.start));
+    }
+
+    /// Jumps to the keyframe at or before `position` (file time) and returns
+    /// its time.
+    pub(crate) fn seek(&mut self, position: Duration) -> Duration {
+        self.pending = None;
+        self.anchor = None;
+        self.file.seek(position)
+    }
+
+    pub(crate) fn next_due(&mut self) -> Due {
+        if self.pending.is_none() {
+            match self.file.next_sample() {
+                Ok(Some(sample)) if sample.dts < self.end => {
+                    self.pending = Some(sample);
+                    self.looped = false;
+                }
+                Ok(_) if self.looped => {
+                    return Due::Failed(anyhow::anyhow!("no samples between the in and out point"))
+                }
+                Ok(_) if self.repeat => {
+                    self.rewind();
+                    self.looped = true;
+                    return Due::Looped;
+                }
+                Ok(_) => return Due::End,
+                Err(err) => return Due::Failed(err),
+            }
+        }
+
+        let dts = self.pending.as_ref().map_or(0, |sample| sample.dts);
+        let (since, from) = *self.anchor.get_or_insert((Instant::now(), dts));
+        let due = since
+            + Duration::from_secs_f64(dts.saturating_sub(from) as f64 / 1000.0 / self.speed as f64);
+        let now = Instant::now();
+        match self.pending.take() {
+            Some(sample) if due <= now => Due::Sample(sample),
+            pending => {
+                self.pending = pending;
+                Due::Wait(due - now)
+            }
+        }
+    }
+}
+
+pub struct Mp4SourceBuilder {
+    vhost: String,
+    app: String,
+    stream: String,
+    clips: Vec<Clip>,
+    looping: bool,
+    hls_enabled: bool,
+    mp4_enabled: bool,
+}
+
+impl Mp4SourceBuilder {
+    pub fn clip(mut self, clip: Clip) -> Self {
+        self.clips.push(clip);
+        self
+    }
+
+    /// Restarts the playlist at its end, with timestamps carrying on.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn hls_enabled(mut self, enabled: bool) -> Self {
+        self.hls_enabled = enabled;
+        self
+    }
+
+    pub fn mp4_enabled(mut self, enabled: bool) -> Self {
+        self.mp4_enabled = enabled;
+        self
+    }
+
+    /// Opens every clip and starts publishing. The tracks are set up from
+    /// the first clip; fails if another clip has different codecs.
+    pub fn start(self) -> anyhow::Result<Mp4Source> {
+        if self.clips.is_empty() {
+            anyhow::bail!("playlist is empty");
+        }
+        let codecs = |file: &Mp4File| {
+            let mut codecs: Vec<CodecId> = file
+                .track_configs()
+                .iter()
+                .map(TrackConfig::codec)
+                .collect();
+            codecs.sort();
+            codecs
+        };
+        let mut clips: Vec<OpenClip> = Vec::with_capacity(self.clips.len());
+        let mut position = 0;
+        for clip in self.clips {
+            let file = PacedFile::open(&clip, false)?;
+            if let Some(first) = clips.first() {
+                let (expected, found) = (codecs(first.file.file()), codecs(file.file()));
+                if expected != found {
+                    anyhow::bail!(
+                        "{}: codecs {:?} differ from the first clip's {:?}",
+                        clip.path.display(),
+                        found,
+                        expected
+                    );
+                }
+            }
+            let length = file.end().saturating_sub(file.start());
+            clips.push(OpenClip { file, position });
+            position += length;
+        }
+
+        let duration = if self.looping {
+            0.0
+        } else {
+            position as f32 / 1000.0
+        };
+        let media = Media::new(
+            &self.vhost,
+            &self.app,
+            &self.stream,
+            duration,
+            self.hls_enabled,
+            self.mp4_enabled,
+        );
+        for config in clips[0].file.file().track_configs() {
+            config.init(&media);
+        }
+        media.init_complete();
+
+        let (commands, receiver) = mpsc::channel();
+        let sender = commands.clone();
+        media.on_seek(move |position| sender.send(Command::Seek(position)).is_ok());
+        let sender = commands.clone();
+        media.on_pause(move |pause| sender.send(Command::Pause(pause)).is_ok());
+        let sender = commands.clone();
+        media.on_speed(move |speed| sender.send(Command::Speed(speed)).is_ok());
+        let sender = commands.clone();
+        media.on_close(move || sender.send(Command::Stop).is_ok());
+
+        let video = clips[0]
+            .file
+            .file()
+            .track_configs()
+            .iter()
+            .any(|config| matches!(config, TrackConfig::Video { .. }));
+        let player = Player {
+            media,
+            splicer: Splicer::new(video),
+            clips,
+            total: position,
+            looping: self.looping,
+            commands: receiver,
+        };
+        let thread = std::thread::Builder::new()
+            .name(format!("mp4-{}", self.stream))
+            .spawn(move || player.run())?;
+        Ok(Mp4Source {
+            commands,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A playlist of MP4 clips published as one stream, paced in real time on
+/// its own thread; stops when dropped, at the end of a non-looping playlist,
+/// or when ZLMediaKit closes the stream.
+pub struct Mp4Source {
+    commands: mpsc::Sender<Command>,
+    thread: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Mp4Source {
+    pub fn builder(vhost: &str, app: &str, stream: &str) -> Mp4SourceBuilder {
+        Mp4SourceBuilder {
+            vhost: vhost.to_string(),
+            app: app.to_string(),
+            stream: stream.to_string(),
+            clips: Vec::new(),
+            looping: false,
+            hls_enabled: false,
+            mp4_enabled: false,
+        }
+    }
+
+    /// Jumps to `position` on the playlist time line (snapped to a keyframe).
+    pub fn seek(&self, position: Duration) {
+        let _ = self.commands.send(Command::Seek(position));
+    }
+
+    pub fn pause(&self, pause: bool) {
+        let _ = self.commands.send(Command::Pause(pause));
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        let _ = self.commands.send(Command::Speed(speed));
+    }
+
+    /// Waits until publishing stops (end of a non-looping playlist, or the
+    /// stream was closed); returns the read error that stopped it, if any.
+    pub fn join(mut self) -> anyhow::Result<()> {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => anyhow::bail!("mp4 source thread panicked"),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the playlist is still being published.
+    pub fn is_playing(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for Mp4Source {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Seek(Duration),
+    Pause(bool),
+    Speed(f32),
+    Stop,
+}
+
+struct OpenClip {
+    file: PacedFile,
+    /// Where the clip starts on the playlist time line.
+    position: u64,
+}
+
+struct Player {
+    media: Media,
+    /// Joins clips and loops into one time line.
+    splicer: Splicer,
+    clips: Vec<OpenClip>,
+    /// Playlist length in milliseconds.
+    total: u64,
+    looping: bool,
+    commands: mpsc::Receiver<Command>,
+}
+
+impl Player {
+    fn run(mut self) -> anyhow::Result<()> {
+        let mut current = 0;
+        let mut paused = false;
+
+        loop {
+            let wait = if paused {
+                None
+            } else {
+                match self.clips[current].file.next_due() {
+                    Due::Sample(sample) => {
+                        if let Some(ts) = self.splicer.splice(
+                            sample.codec,
+                            sample.dts,
+                            sample.pts,
+                            sample.keyframe,
+                        ) {
+                            self.media.input_frame(&Frame::new(
+                                sample.codec,
+                                ts.dts,
+                                ts.pts,
+                                &sample.data,
+                            ));
+                        }
+                        continue;
+                    }
+                    Due::Wait(wait) => Some(wait),
+                    // clips do not repeat on their own
+                    Due::Looped => continue,
+                    Due::End => {
+                        current += 1;
+                        if current == self.clips.len() {
+                            if !self.looping || self.total == 0 {
+                                break;
+                            }
+                            current = 0;
+                        }
+                        // clips start on a keyframe, so no need to wait for one
+                        self.splicer.rebase();
+                        self.clips[current].file.rewind();
+                        continue;
+                    }
+                    Due::Failed(err) => return Err(err),
+                }
+            };
+
+            let command = match wait {
+                None => match self.commands.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                },
+                Some(wait) => match self.commands.recv_timeout(wait) {
+                    Ok(command) => command,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                },
+            };
+
+            match command {
+                Command::Stop => break,
+                Command::Pause(pause) => {
+                    paused = pause;
+                    self.clips[current].file.reanchor();
+                }
+                Command::Speed(speed) if speed > 0.0 => {
+                    for clip in &mut self.clips {
+                        clip.file.set_speed(speed);
+                    }
+                }
+                Command::Speed(_) => {}
+                Command::Seek(position) => {
+                    let target = position.as_millis() as u64 % self.total.max(1);
+                    current = self
+                        .clips
+                        .iter()
+                        .rposition(|clip| clip.position <= target)
+                        .unwrap_or(0);
+                    let clip = &mut self.clips[current];
+                    let start = clip.file.start();
+                    let keyframe = clip
+                        .file
+                        .seek(Duration::from_millis(start + target - clip.position));
+                    // output time follows the playlist position seeked to
+                    let at = clip.position + (keyframe.as_millis() as u64).saturating_sub(start);
+                    self.splicer.jump(at);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mux::fmp4::{full_box, mp4_box};
+
+    const SPS: [u8; 4] = [0x67, 0x42, 0x00, 0x1F];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x38, 0x80];
+    const IDR: [u8; 6] = [0, 0, 0, 2, 0x65, 0x88];
+    const NON_IDR: [u8; 6] = [0, 0, 0, 2, 0x41, 0x9A];
+    const AAC: [u8; 4] = [0x21, 0x10, 0x04, 0x60];
+
+    /// A full box table of `u32` fields: entry count, then the entries.
+    fn table_box(kind: &[u8; 4], entries: &[&[u32]]) -> Vec<u8> {
+        let mut body = (entries.len() as u32).to_be_bytes().to_vec();
+        for value in entries.iter().flat_map(|entry| entry.iter()) {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        full_box(kind, 0, 0, &[&body])
+    }
+
+    fn trak(timescale: u32, sample_entry: &[u8], tables: &[Vec<u8>]) -> Vec<u8> {
+        let mdhd = full_box(b"mdhd", 0, 0, &[&[0; 8], &timescale.to_be_bytes(), &[0; 8]]);
+        let stsd = full_box(b"stsd", 0, 0, &[&1u32.to_be_bytes(), sample_entry]);
+        let mut stbl: Vec<&[u8]> = vec![&stsd];
+        stbl.extend(tables.iter().map(Vec::as_slice));
+        let minf = mp4_box(b"minf", &[&mp4_box(b"stbl", &stbl)]);
+        mp4_box(b"trak", &[&mp4_box(b"mdia", &[&mdhd, &minf])])
+    }
+
+    fn video_trak() -> Vec<u8> {
+        let record = [
+            &[1, 0x42, 0x00, 0x1F, 0xFF, 0xE1, 0, SPS.len() as u8][..],
+            &SPS,
+            &[1, 0, PPS.len() as u8],
+            &PPS,
+        ]
+        .concat();
+        let avc1 = mp4_box(
+            b"avc1",
+            &[
+                &[0; 24],
+                &640u16.to_be_bytes(),
+                &360u16.to_be_bytes(),
+                &[0; 50],
+                &mp4_box(b"avcC", &[&record]),
+            ],
+        );
+        trak(
+            1000,
+            &avc1,
+            &[
+                table_box(b"stts", &[&[3, 40]]),
+                table_box(b"ctts", &[&[1, 0], &[1, 80], &[1, 0]]),
+                table_box(b"stss", &[&[1], &[3]]),
+                // two samples in the first chunk, one in the second
+                table_box(b"stsc", &[&[1, 2, 1], &[2, 1, 1]]),
+                full_box(
+                    b"stsz",
+                    0,
+                    0,
+                    &[&[0, 0, 0, 0, 0, 0, 0, 3], &[0, 0, 0, 6].repeat(3)],
+                ),
+                table_box(b"stco", &[&[8], &[28]]),
+            ],
+        )
+    }
+
+    fn audio_trak() -> Vec<u8> {
+        // AAC LC, 48 kHz, stereo
+        let specific = [0x05, 2, 0x11, 0x90];
+        let decoder = [&[0x04, 13 + 4, 0x40, 0x15][..], &[0; 11], &specific].concat();
+        let es = [
+            &[0x03, 3 + 2 + 17 + 3, 0, 0, 0][..],
+            &decoder,
+            &[0x06, 1, 2],
+        ]
+        .concat();
+        let mp4a = mp4_box(
+            b"mp4a",
+            &[&[0; 8], &[0; 20], &full_box(b"esds", 0, 0, &[&es])],
+        );
+        trak(
+            48000,
+            &mp4a,
+            &[
+                table_box(b"stts", &[&[2, 1024]]),
+                table_box(b"stsc", &[&[1, 2, 1]]),
+                // fixed sample size
+                full_box(b"stsz", 0, 0, &[&4u32.to_be_bytes(), &2u32.to_be_bytes()]),
+                table_box(b"stco", &[&[20]]),
+            ],
+        )
+    }
+
+    /// mdat with video 0, 1, audio 0, 1, video 2, then moov.
+    fn file() -> Vec<u8> {
+        let mdat = mp4_box(b"mdat", &[&IDR, &NON_IDR, &AAC, &AAC, &IDR]);
+        let moov = mp4_box(b"moov", &[&video_trak(), &audio_trak()]);
+        [mdat, moov].concat()
+    }
+
+    fn open(name: &str, data: &[u8]) -> anyhow::Result<Mp4File> {
+        let path = std::env::temp_dir().join(format!("rszlm-{}-{}.mp4", name, std::process::id()));
+        std::fs::write(&path, data)?;
+        let file = Mp4File::open(&path);
+        let _ = std::fs::remove_file(&path);
+        file
+    }
+
+    #[test]
+    fn reads_tracks_and_samples() {
+        let mut file = open("samples", &file()).unwrap();
+        let configs = file.track_configs();
+        assert!(matches!(
+            configs[0],
+            TrackConfig::Video { codec: CodecId::H264, width: 640, height: 360, fps: Some(fps), .. }
+                if fps == 25.0
+        ));
+        assert!(matches!(
+            &configs[1],
+            TrackConfig::Audio { codec: CodecId::AAC, sample_rate: 48000, channels: 2, config }
+                if config == &[0x11, 0x90]
+        ));
+        // last video DTS plus one frame
+        assert_eq!(file.duration(), Duration::from_millis(120));
+
+        let mut samples = Vec::new();
+        while let Some(sample) = file.next_sample().unwrap() {
+            samples.push(sample);
+        }
+        let order: Vec<(CodecId, u64, u64, bool)> = samples
+            .iter()
+            .map(|sample| (sample.codec, sample.dts, sample.pts, sample.keyframe))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (CodecId::H264, 0, 0, true),
+                (CodecId::AAC, 0, 0, true),
+                (CodecId::AAC, 21, 21, true),
+                (CodecId::H264, 40, 120, false),
+                (CodecId::H264, 80, 80, true),
+            ]
+        );
+        let start_code = [0, 0, 0, 1];
+        assert_eq!(
+            samples[0].data,
+            [
+                &start_code[..],
+                &SPS,
+                &start_code,
+                &PPS,
+                &start_code,
+                &IDR[4..]
+            ]
+            .concat()
+        );
+        assert_eq!(samples[3].data, [&start_code[..], &NON_IDR[4..]].concat());
+        assert_eq!(samples[1].data, config_to_adts(2, 3, 2, &AAC));
+    }
+
+    #[test]
+    fn seeks_to_keyframe() {
+        let mut file = open("seek", &file()).unwrap();
+        assert_eq!(file.seek(Duration::from_millis(70)), Duration::ZERO);
+        assert_eq!(file.next_sample().unwrap().unwrap().dts, 0);
+
+        assert_eq!(
+            file.seek(Duration::from_millis(90)),
+            Duration::from_millis(80)
+        );
+        let sample = file.next_sample().unwrap().unwrap();
+        assert_eq!((sample.codec, sample.dts), (CodecId::H264, 80));
+        assert!(file.next_sample().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_samples_past_end_of_file() {
+        let file = file();
+        let moov = child(&file[34..], &[b"moov"]).unwrap();
+        let trak = boxes(moov).next().unwrap().1;
+        let stbl = child(trak, &[b"mdia", b"minf", b"stbl"]).unwrap();
+        assert_eq!(sample_table(stbl, file.len() as u64).unwrap().len(), 3);
+        // the last sample ends at 34
+        assert!(sample_table(stbl, 33).is_none());
+
+        // a video sample size pointing far past the file drops the track
+        let mut broken = file.clone();
+        let at = broken.windows(4).position(|kind| kind == b"stsz").unwrap() + 20;
+        broken[at..at + 4].copy_from_slice(&0xFFFF_FF00u32.to_be_bytes());
+        let configs = open("broken", &broken).unwrap().track_configs();
+        assert_eq!(configs.len(), 1);
+        assert!(matches!(configs[0], TrackConfig::Audio { .. }));
+    }
+}
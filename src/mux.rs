@@ -0,0 +1,92 @@
+//! Muxers in Rust, fed from [`Frame`](crate::frame::Frame)s of a track
+//! delegate or any other source.
+
+pub mod fmp4;
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{
+    frame::Frame,
+    obj::{MediaSource, Track, TrackDelegate},
+};
+
+/// Track delegates feeding a muxer from a live source; removes them on drop.
+pub struct Tap {
+    delegates: Vec<(Track, TrackDelegate)>,
+}
+
+impl Tap {
+    pub(crate) fn new(delegates: Vec<(Track, TrackDelegate)>) -> Self {
+        Self { delegates }
+    }
+
+    /// Feeds every track of a registered stream to `f`; `None` while the
+    /// stream is not registered or has no tracks yet.
+    pub(crate) fn find(
+        vhost: &str,
+        app: &str,
+        stream: &str,
+        f: impl FnMut(&Frame) + Clone + Send + 'static,
+    ) -> Option<Self> {
+        MediaSource::find_any(vhost, app, stream, |source| {
+            let delegates: Vec<_> = (0..source.track_count())
+                .filter_map(|index| source.get_track(index))
+                .map(|track| {
+                    let delegate = track.add_delegate(f.clone());
+                    (track, delegate)
+                })
+                .collect();
+            (!delegates.is_empty()).then(|| Self::new(delegates))
+        })
+        .flatten()
+    }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        for (track, delegate) in self.delegates.drain(..) {
+            track.del_delegate(delegate);
+        }
+    }
+}
+
+/// Hands what a muxer produced to a user sink in order, without holding the
+/// muxer's lock while the sink runs and without overlapping sink calls.
+pub(crate) struct SinkQueue<M, T> {
+    muxer: Mutex<(M, VecDeque<T>)>,
+    sink: Mutex<Box<dyn FnMut(T) + Send>>,
+}
+
+impl<M, T> SinkQueue<M, T> {
+    pub(crate) fn new(muxer: M, sink: impl FnMut(T) + Send + 'static) -> Self {
+        Self {
+            muxer: Mutex::new((muxer, VecDeque::new())),
+            sink: Mutex::new(Box::new(sink)),
+        }
+    }
+
+    /// Runs `write` on the muxer and passes its output to the sink, unless
+    /// another thread is in the sink already; that one passes it on then.
+    pub(crate) fn write(&self, write: impl FnOnce(&mut M) -> Vec<T>) {
+        {
+            let (muxer, queue) = &mut *self.muxer.lock().unwrap();
+            queue.extend(write(muxer));
+        }
+        let Ok(mut sink) = self.sink.try_lock() else {
+            return;
+        };
+        loop {
+            let mut muxer = self.muxer.lock().unwrap();
+            if muxer.1.is_empty() {
+                // released under the muxer lock, so no write goes unseen
+                drop(sink);
+                return;
+            }
+            let items = std::mem::take(&mut muxer.1);
+            drop(muxer);
+            for item in items {
+                sink(item);
+            }
+        }
+    }
+}
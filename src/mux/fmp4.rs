@@ -0,0 +1,765 @@
+//! Fragmented MP4 (CMAF) muxer: an init segment (`ftyp` + `moov`), then
+//! `moof` + `mdat` fragments starting on video keyframes.
+//!
+//! H264, H265 and AAC (ADTS input) are supported; the codec configuration is
+//! taken from the frames, so the init segment comes with the first keyframe
+//! once every track's configuration was seen. Timestamps use a 1000 Hz
+//! timescale, as [`Frame`]s are in milliseconds.
+//!
+//! ```ignore
+//! let tap = fmp4::tap(&source, Duration::from_millis(500), move |segment| match segment {
+//!     Segment::Init(init) => ws.send(init),
+//!     Segment::Fragment(fragment) => ws.send(fragment.data),
+//! })?;
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use super::{SinkQueue, Tap};
+use crate::{
+    flv::adts_to_config,
+    frame::Frame,
+    nal::{self, NalKind, NalUnit, ParameterSets},
+    obj::{CodecId, MediaSource, Track},
+};
+
+const TIMESCALE: u32 = 1000;
+
+/// Muxer output.
+#[derive(Debug, Clone)]
+pub enum Segment {
+    /// `ftyp` + `moov`, once before the first fragment.
+    Init(Vec<u8>),
+    Fragment(Fragment),
+}
+
+/// One `moof` + `mdat`.
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub data: Vec<u8>,
+    /// `mfhd` sequence number, from 1.
+    pub sequence: u32,
+    /// Decode time of the first sample, milliseconds.
+    pub start: u64,
+    pub duration: u64,
+    /// Starts with a video keyframe (or is audio only).
+    pub keyframe: bool,
+}
+
+#[derive(Debug)]
+struct Sample {
+    dts: u64,
+    cts: i32,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct MuxTrack {
+    id: u32,
+    codec: CodecId,
+    parameter_sets: ParameterSets,
+    /// `avcC`/`hvcC` or AudioSpecificConfig.
+    config: Option<Vec<u8>>,
+    width: u32,
+    height: u32,
+    sample_rate: u32,
+    channels: u32,
+    samples: Vec<Sample>,
+    /// Duration of the last sample of the previous fragment, for tracks
+    /// whose next sample is not known yet.
+    last_duration: u32,
+}
+
+impl MuxTrack {
+    fn is_video(&self) -> bool {
+        self.codec != CodecId::AAC
+    }
+}
+
+/// Builds fMP4 from frames of up to one video and one audio track.
+#[derive(Debug)]
+pub struct Muxer {
+    fragment_duration: Duration,
+    tracks: Vec<MuxTrack>,
+    init_sent: bool,
+    sequence: u32,
+}
+
+impl Muxer {
+    /// Fragments are cut on the first video keyframe (or audio frame for
+    /// audio only streams) after `fragment_duration`.
+    pub fn new(fragment_duration: Duration) -> Self {
+        Self {
+            fragment_duration,
+            tracks: Vec::new(),
+            init_sent: false,
+            sequence: 0,
+        }
+    }
+
+    /// Adds a track of this codec; `H264`, `H265` and `AAC` are supported.
+    pub fn add_codec(&mut self, codec: CodecId) -> anyhow::Result<()> {
+        if !matches!(codec, CodecId::H264 | CodecId::H265 | CodecId::AAC) {
+            anyhow::bail!("fmp4 muxer does not support {}", codec);
+        }
+        if self.init_sent {
+            anyhow::bail!("tracks must be added before the first frame");
+        }
+        if self
+            .tracks
+            .iter()
+            .any(|track| track.is_video() == (codec != CodecId::AAC))
+        {
+            anyhow::bail!("fmp4 muxer takes one video and one audio track");
+        }
+        self.tracks.push(MuxTrack {
+            id: self.tracks.len() as u32 + 1,
+            codec,
+            parameter_sets: ParameterSets::default(),
+            config: None,
+            width: 0,
+            height: 0,
+            sample_rate: 0,
+            channels: 0,
+            samples: Vec::new(),
+            last_duration: 0,
+        });
+        Ok(())
+    }
+
+    /// Adds a track of a media source, see [`add_codec`](Self::add_codec).
+    pub fn add_track(&mut self, track: &Track) -> anyhow::Result<()> {
+        self.add_codec(track.codec_id()?)
+    }
+
+    /// Feeds one frame; returns the init segment and fragments it completes.
+    /// A video frame with the DTS of the previous one (another slice of the
+    /// same picture) is added to its sample.
+    pub fn write_frame(&mut self, frame: &Frame) -> Vec<Segment> {
+        let mut out = Vec::new();
+        let Ok(codec) = frame.codec_id() else {
+            return out;
+        };
+        let Some(index) = self.tracks.iter().position(|track| track.codec == codec) else {
+            return out;
+        };
+        let Some(sample) = Self::sample(&mut self.tracks[index], frame) else {
+            return out;
+        };
+        // slices of one picture fed as separate frames make one sample
+        let track = &mut self.tracks[index];
+        if let Some(last) = track
+            .samples
+            .last_mut()
+            .filter(|last| last.dts == sample.dts && track.codec != CodecId::AAC)
+        {
+            last.keyframe |= sample.keyframe;
+            last.data.extend_from_slice(&sample.data);
+            return out;
+        }
+
+        let has_video = self.tracks.iter().any(MuxTrack::is_video);
+        let starts_fragment = if has_video {
+            sample.keyframe && self.tracks[index].is_video()
+        } else {
+            true
+        };
+
+        if !self.init_sent {
+            // wait for a keyframe with every configuration known
+            if !starts_fragment || self.tracks.iter().any(|track| track.config.is_none()) {
+                return out;
+            }
+            out.push(Segment::Init(self.init_segment()));
+            self.init_sent = true;
+        } else if starts_fragment {
+            let start = self.fragment_start();
+            if start.is_some_and(|start| {
+                sample.dts.saturating_sub(start) >= self.fragment_duration.as_millis() as u64
+            }) {
+                out.extend(self.fragment(Some(sample.dts)).map(Segment::Fragment));
+            }
+        }
+
+        self.tracks[index].samples.push(sample);
+        out
+    }
+
+    /// Emits what is buffered as a last fragment, e.g. at end of stream.
+    pub fn flush(&mut self) -> Option<Segment> {
+        self.fragment(None).map(Segment::Fragment)
+    }
+
+    /// Turns a frame into a sample, learning the codec configuration.
+    fn sample(track: &mut MuxTrack, frame: &Frame) -> Option<Sample> {
+        let mut data = Vec::new();
+        let mut keyframe = frame.is_key_frame();
+        if track.codec == CodecId::AAC {
+            let (config, header_len) = adts_to_config(frame.data())?;
+            let sample_rate_index = ((frame.data()[2] >> 2) & 0x0F) as usize;
+            track.sample_rate = [
+                96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000,
+                7350,
+            ]
+            .get(sample_rate_index)
+            .copied()
+            .unwrap_or(44100);
+            track.channels = ((config[1] >> 3) & 0x0F) as u32;
+            track.config = Some(config);
+            data.extend_from_slice(frame.data().get(header_len..)?);
+            keyframe = true;
+        } else {
+            for unit in nal::split_annexb(frame.data()) {
+                let Ok(nal) = NalUnit::parse(track.codec, unit) else {
+                    continue;
+                };
+                if track.parameter_sets.update(&nal) {
+                    let record = match track.codec {
+                        CodecId::H265 => track.parameter_sets.hevc_config_record(),
+                        _ => track.parameter_sets.avc_config_record(),
+                    };
+                    if let (Some(record), Some(info)) = (record, track.parameter_sets.sps_info()) {
+                        track.width = info.width;
+                        track.height = info.height;
+                        track.config = Some(record);
+                    }
+                    continue;
+                }
+                if nal.kind() == NalKind::Aud {
+                    continue;
+                }
+                keyframe |= nal.is_keyframe();
+                data.extend_from_slice(&(unit.len() as u32).to_be_bytes());
+                data.extend_from_slice(unit);
+            }
+        }
+        (!data.is_empty()).then(|| Sample {
+            dts: frame.dts(),
+            cts: (frame.pts() as i64 - frame.dts() as i64) as i32,
+            keyframe,
+            data,
+        })
+    }
+
+    fn fragment_start(&self) -> Option<u64> {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.samples.first().map(|sample| sample.dts))
+            .min()
+    }
+
+    /// `moof` + `mdat` of everything buffered; `next_dts` ends the last
+    /// video sample.
+    fn fragment(&mut self, next_dts: Option<u64>) -> Option<Fragment> {
+        let start = self.fragment_start()?;
+        self.sequence += 1;
+
+        let mut end = start;
+        let mut trafs = Vec::new();
+        let mut payloads = Vec::new();
+        let mut keyframe = true;
+        for track in &mut self.tracks {
+            let samples = std::mem::take(&mut track.samples);
+            if samples.is_empty() {
+                continue;
+            }
+            if track.is_video() {
+                keyframe = samples[0].keyframe;
+            }
+            let mut durations: Vec<u32> = samples
+                .windows(2)
+                .map(|pair| pair[1].dts.saturating_sub(pair[0].dts) as u32)
+                .collect();
+            let last = match next_dts.filter(|_| track.is_video()) {
+                Some(next) => next.saturating_sub(samples[samples.len() - 1].dts) as u32,
+                None => durations.last().copied().unwrap_or(track.last_duration),
+            };
+            durations.push(last);
+            track.last_duration = last;
+            end = end.max(samples[samples.len() - 1].dts + last as u64);
+            trafs.push((track.id, samples[0].dts, samples, durations));
+        }
+
+        // the data offsets depend on the moof size, which does not
+        let moof_size = moof(self.sequence, &trafs, &vec![0; trafs.len()]).len();
+        let mut offsets = Vec::new();
+        let mut offset = moof_size as u32 + 8;
+        for (_, _, samples, _) in &trafs {
+            offsets.push(offset);
+            for sample in samples {
+                offset += sample.data.len() as u32;
+                payloads.push(&sample.data);
+            }
+        }
+        let mut data = moof(self.sequence, &trafs, &offsets);
+        let mdat_size: usize = payloads.iter().map(|payload| payload.len()).sum();
+        data.extend_from_slice(&(mdat_size as u32 + 8).to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        for payload in payloads {
+            data.extend_from_slice(payload);
+        }
+
+        Some(Fragment {
+            data,
+            sequence: self.sequence,
+            start,
+            duration: end - start,
+            keyframe,
+        })
+    }
+
+    fn init_segment(&self) -> Vec<u8> {
+        let mut out = mp4_box(b"ftyp", &[b"iso6", &[0, 0, 2, 0], b"iso6cmfcmp41"]);
+        let mut moov = vec![mvhd(self.tracks.len() as u32 + 1)];
+        for track in &self.tracks {
+            moov.push(trak(track));
+        }
+        let trexs: Vec<Vec<u8>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                full_box(
+                    b"trex",
+                    0,
+                    0,
+                    &[&track.id.to_be_bytes(), &1u32.to_be_bytes(), &[0; 12]],
+                )
+            })
+            .collect();
+        moov.push(mp4_box(b"mvex", &as_parts(&trexs)));
+        out.extend(mp4_box(b"moov", &as_parts(&moov)));
+        out
+    }
+}
+
+fn as_parts(boxes: &[Vec<u8>]) -> Vec<&[u8]> {
+    boxes.iter().map(Vec::as_slice).collect()
+}
+
+pub(crate) fn mp4_box(kind: &[u8; 4], parts: &[&[u8]]) -> Vec<u8> {
+    let size: usize = 8 + parts.iter().map(|part| part.len()).sum::<usize>();
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(&(size as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    for part in parts {
+        out.extend_from_slice(part);
+    }
+    out
+}
+
+pub(crate) fn full_box(kind: &[u8; 4], version: u8, flags: u32, parts: &[&[u8]]) -> Vec<u8> {
+    let header = (version as u32) << 24 | flags;
+    let mut all: Vec<&[u8]> = Vec::with_capacity(parts.len() + 1);
+    let header = header.to_be_bytes();
+    all.push(&header);
+    all.extend_from_slice(parts);
+    mp4_box(kind, &all)
+}
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+fn matrix() -> Vec<u8> {
+    MATRIX
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+fn mvhd(next_track_id: u32) -> Vec<u8> {
+    full_box(
+        b"mvhd",
+        0,
+        0,
+        &[
+            &[0; 8], // creation, modification time
+            &TIMESCALE.to_be_bytes(),
+            &[0; 4], // duration
+            &0x0001_0000u32.to_be_bytes(),
+            &0x0100u16.to_be_bytes(),
+            &[0; 10],
+            &matrix(),
+            &[0; 24],
+            &next_track_id.to_be_bytes(),
+        ],
+    )
+}
+
+fn trak(track: &MuxTrack) -> Vec<u8> {
+    let video = track.is_video();
+    let tkhd = full_box(
+        b"tkhd",
+        0,
+        0x3, // enabled, in movie
+        &[
+            &[0; 8],
+            &track.id.to_be_bytes(),
+            &[0; 4],
+            &[0; 4], // duration
+            &[0; 8],
+            &[0; 4], // layer, alternate group
+            &(if video { 0u16 } else { 0x0100 }).to_be_bytes(),
+            &[0; 2],
+            &matrix(),
+            &(track.width << 16).to_be_bytes(),
+            &(track.height << 16).to_be_bytes(),
+        ],
+    );
+    let mdhd = full_box(
+        b"mdhd",
+        0,
+        0,
+        &[
+            &[0; 8],
+            &TIMESCALE.to_be_bytes(),
+            &[0; 4],
+            &0x55C4u16.to_be_bytes(), // "und"
+            &[0; 2],
+        ],
+    );
+    let (handler, name): (&[u8; 4], &[u8]) = if video {
+        (b"vide", b"VideoHandler\0")
+    } else {
+        (b"soun", b"SoundHandler\0")
+    };
+    let hdlr = full_box(b"hdlr", 0, 0, &[&[0; 4], handler, &[0; 12], name]);
+    let media_header = if video {
+        full_box(b"vmhd", 0, 1, &[&[0; 8]])
+    } else {
+        full_box(b"smhd", 0, 0, &[&[0; 4]])
+    };
+    let dinf = mp4_box(
+        b"dinf",
+        &[&full_box(
+            b"dref",
+            0,
+            0,
+            &[&1u32.to_be_bytes(), &full_box(b"url ", 0, 1, &[])],
+        )],
+    );
+    let stbl = mp4_box(
+        b"stbl",
+        &[
+            &full_box(b"stsd", 0, 0, &[&1u32.to_be_bytes(), &sample_entry(track)]),
+            &full_box(b"stts", 0, 0, &[&[0; 4]]),
+            &full_box(b"stsc", 0, 0, &[&[0; 4]]),
+            &full_box(b"stsz", 0, 0, &[&[0; 8]]),
+            &full_box(b"stco", 0, 0, &[&[0; 4]]),
+        ],
+    );
+    let minf = mp4_box(b"minf", &[&media_header, &dinf, &stbl]);
+    let mdia = mp4_box(b"mdia", &[&mdhd, &hdlr, &minf]);
+    mp4_box(b"trak", &[&tkhd, &mdia])
+}
+
+fn sample_entry(track: &MuxTrack) -> Vec<u8> {
+    let config = track.config.as_deref().unwrap_or_default();
+    match track.codec {
+        CodecId::AAC => {
+            // ES_Descriptor > DecoderConfigDescriptor > DecoderSpecificInfo
+            let specific = [&[0x05, config.len() as u8][..], config].concat();
+            let decoder = [
+                &[0x04, (13 + specific.len()) as u8, 0x40, 0x15][..],
+                &[0; 11],
+                &specific,
+            ]
+            .concat();
+            let es = [
+                &[0x03, (3 + decoder.len() + 3) as u8, 0, 0, 0][..],
+                &decoder,
+                &[0x06, 0x01, 0x02],
+            ]
+            .concat();
+            mp4_box(
+                b"mp4a",
+                &[
+                    &[0; 6],
+                    &1u16.to_be_bytes(),
+                    &[0; 8],
+                    &(track.channels as u16).to_be_bytes(),
+                    &16u16.to_be_bytes(),
+                    &[0; 4],
+                    // 16.16; rates that do not fit are left to the esds
+                    &(if track.sample_rate > 0xFFFF {
+                        0
+                    } else {
+                        track.sample_rate << 16
+                    })
+                    .to_be_bytes(),
+                    &full_box(b"esds", 0, 0, &[&es]),
+                ],
+            )
+        }
+        codec => {
+            let (entry, record): (&[u8; 4], &[u8; 4]) = match codec {
+                CodecId::H265 => (b"hvc1", b"hvcC"),
+                _ => (b"avc1", b"avcC"),
+            };
+            mp4_box(
+                entry,
+                &[
+                    &[0; 6],
+                    &1u16.to_be_bytes(),
+                    &[0; 16],
+                    &(track.width as u16).to_be_bytes(),
+                    &(track.height as u16).to_be_bytes(),
+                    &0x0048_0000u32.to_be_bytes(),
+                    &0x0048_0000u32.to_be_bytes(),
+                    &[0; 4],
+                    &1u16.to_be_bytes(),
+                    &[0; 32],
+                    &0x0018u16.to_be_bytes(),
+                    &0xFFFFu16.to_be_bytes(),
+                    &mp4_box(record, &[config]),
+                ],
+            )
+        }
+    }
+}
+
+type Traf = (u32, u64, Vec<Sample>, Vec<u32>);
+
+fn moof(sequence: u32, trafs: &[Traf], offsets: &[u32]) -> Vec<u8> {
+    let mut boxes = vec![full_box(b"mfhd", 0, 0, &[&sequence.to_be_bytes()])];
+    for ((id, start, samples, durations), offset) in trafs.iter().zip(offsets) {
+        // default-base-is-moof
+        let tfhd = full_box(b"tfhd", 0, 0x02_0000, &[&id.to_be_bytes()]);
+        let tfdt = full_box(b"tfdt", 1, 0, &[&start.to_be_bytes()]);
+        let mut entries = Vec::with_capacity(samples.len() * 16);
+        for (sample, duration) in samples.iter().zip(durations) {
+            let flags: u32 = if sample.keyframe {
+                0x0200_0000
+            } else {
+                0x0101_0000
+            };
+            entries.extend_from_slice(&duration.to_be_bytes());
+            entries.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+            entries.extend_from_slice(&flags.to_be_bytes());
+            entries.extend_from_slice(&sample.cts.to_be_bytes());
+        }
+        // data offset, duration, size, flags, composition offset (signed)
+        let trun = full_box(
+            b"trun",
+            1,
+            0x0F01,
+            &[
+                &(samples.len() as u32).to_be_bytes(),
+                &offset.to_be_bytes(),
+                &entries,
+            ],
+        );
+        boxes.push(mp4_box(b"traf", &[&tfhd, &tfdt, &trun]));
+    }
+    mp4_box(b"moof", &as_parts(&boxes))
+}
+
+/// Muxes `source`'s tracks to fMP4 segments passed to `sink`, e.g. frames
+/// of a WebSocket or parts of an archive object.
+pub fn tap(
+    source: &MediaSource,
+    fragment_duration: Duration,
+    sink: impl FnMut(Segment) + Send + 'static,
+) -> anyhow::Result<Tap> {
+    let mut muxer = Muxer::new(fragment_duration);
+    let mut tracks = Vec::new();
+    for track in (0..source.track_count()).filter_map(|index| source.get_track(index)) {
+        if muxer.add_track(&track).is_ok() {
+            tracks.push(track);
+        }
+    }
+    if tracks.is_empty() {
+        anyhow::bail!("no track of {} can be muxed to fmp4", source.stream());
+    }
+
+    let shared = Arc::new(SinkQueue::new(muxer, sink));
+    let delegates = tracks
+        .into_iter()
+        .map(|track| {
+            let shared = shared.clone();
+            let delegate = track.add_delegate(move |frame| {
+                shared.write(|muxer| muxer.write_frame(frame));
+            });
+            (track, delegate)
+        })
+        .collect();
+    Ok(Tap { delegates })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flv::config_to_adts;
+
+    const SPS: [u8; 19] = [
+        0x67, 0x42, 0x00, 0x1F, 0xF4, 0x02, 0x80, 0x2D, 0xD0, 0x80, 0x00, 0x00, 0x03, 0x00, 0x80,
+        0x00, 0x00, 0x1E, 0x40,
+    ];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x38, 0x80];
+
+    fn annexb(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+            .collect()
+    }
+
+    fn length_prefixed(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&(nal.len() as u32).to_be_bytes()[..], nal].concat())
+            .collect()
+    }
+
+    /// Child boxes: type and payload.
+    fn children(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut out = Vec::new();
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            out.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+        assert!(data.is_empty(), "trailing bytes");
+        out
+    }
+
+    fn kinds<'a>(boxes: &[(&'a [u8], &[u8])]) -> Vec<&'a [u8]> {
+        boxes.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    fn be32(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn fragment(segment: &Segment) -> &Fragment {
+        match segment {
+            Segment::Fragment(fragment) => fragment,
+            Segment::Init(_) => panic!("not a fragment"),
+        }
+    }
+
+    /// `(track id, data offset, sample sizes)` of every `traf`, checking the
+    /// `mfhd` sequence number.
+    fn runs(fragment: &Fragment) -> Vec<(u32, usize, Vec<usize>)> {
+        let top = children(&fragment.data);
+        assert_eq!(kinds(&top), [&b"moof"[..], b"mdat"]);
+        let moof = children(top[0].1);
+        assert_eq!(moof[0].0, b"mfhd");
+        assert_eq!(be32(moof[0].1, 4), fragment.sequence);
+
+        moof[1..]
+            .iter()
+            .map(|(kind, traf)| {
+                assert_eq!(*kind, b"traf");
+                let traf = children(traf);
+                assert_eq!(kinds(&traf), [&b"tfhd"[..], b"tfdt", b"trun"]);
+                let trun = traf[2].1;
+                let count = be32(trun, 4) as usize;
+                let sizes = (0..count).map(|i| be32(trun, 12 + i * 16 + 4) as usize);
+                (be32(traf[0].1, 4), be32(trun, 8) as usize, sizes.collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn muxes_h264_and_aac() {
+        let mut muxer = Muxer::new(Duration::from_millis(100));
+        muxer.add_codec(CodecId::H264).unwrap();
+        muxer.add_codec(CodecId::AAC).unwrap();
+        assert!(muxer.add_codec(CodecId::H265).is_err());
+
+        let raw_aac = [0x21, 0x10, 0x04, 0x60, 0x8C, 0x1C];
+        let aac = config_to_adts(2, 4, 2, &raw_aac);
+        let idr = [0x65, 0x88, 0x84, 0x00, 0x33];
+        let slice = [0x41, 0x9A, 0x02];
+        let second_slice = [0x41, 0x1A, 0x04, 0x05];
+
+        let mut segments = Vec::new();
+        // the audio configuration is learnt before the first keyframe
+        segments.extend(muxer.write_frame(&Frame::new(CodecId::AAC, 0, 0, &aac)));
+        assert!(segments.is_empty());
+        let frames = [
+            Frame::new(CodecId::H264, 0, 0, annexb(&[&SPS, &PPS, &idr])),
+            Frame::new(CodecId::AAC, 10, 10, &aac),
+            Frame::new(CodecId::H264, 40, 40, annexb(&[&slice])),
+            Frame::new(CodecId::H264, 40, 40, annexb(&[&second_slice])),
+            Frame::new(CodecId::AAC, 33, 33, &aac),
+            Frame::new(CodecId::H264, 80, 80, annexb(&[&slice])),
+            Frame::new(CodecId::H264, 120, 120, annexb(&[&SPS, &PPS, &idr])),
+            Frame::new(CodecId::AAC, 126, 126, &aac),
+        ];
+        for frame in &frames {
+            segments.extend(muxer.write_frame(frame));
+        }
+        segments.extend(muxer.flush());
+        assert_eq!(segments.len(), 3);
+
+        let Segment::Init(init) = &segments[0] else {
+            panic!("no init segment first");
+        };
+        let top = children(init);
+        assert_eq!(kinds(&top), [&b"ftyp"[..], b"moov"]);
+        let moov = children(top[1].1);
+        assert_eq!(kinds(&moov), [&b"mvhd"[..], b"trak", b"trak", b"mvex"]);
+        for (trak, id) in [(&moov[1], 1), (&moov[2], 2)] {
+            let trak = children(trak.1);
+            assert_eq!(kinds(&trak), [&b"tkhd"[..], b"mdia"]);
+            assert_eq!(be32(trak[0].1, 12), id);
+        }
+        let video_tkhd = children(moov[1].1)[0].1;
+        assert_eq!(be32(video_tkhd, 76) >> 16, 1280);
+        assert_eq!(be32(video_tkhd, 80) >> 16, 720);
+        let mvex = children(moov[3].1);
+        assert_eq!(kinds(&mvex), [&b"trex"[..], b"trex"]);
+
+        let first = fragment(&segments[1]);
+        assert_eq!((first.sequence, first.start, first.keyframe), (1, 0, true));
+        let first_runs = runs(first);
+        assert_eq!(first_runs.len(), 2);
+        let (video_id, video_offset, video_sizes) = &first_runs[0];
+        let (audio_id, audio_offset, audio_sizes) = &first_runs[1];
+        assert_eq!((*video_id, *audio_id), (1, 2));
+        // the two slices at 40 ms are one sample
+        let merged = length_prefixed(&[&slice, &second_slice]);
+        assert_eq!(video_sizes, &[idr.len() + 4, merged.len(), slice.len() + 4]);
+        assert_eq!(audio_sizes, &[raw_aac.len(), raw_aac.len()]);
+
+        // data offsets point into the mdat payload, video then audio
+        let moof_size = be32(&first.data, 0) as usize;
+        assert_eq!(&first.data[moof_size + 4..moof_size + 8], b"mdat");
+        assert_eq!(*video_offset, moof_size + 8);
+        let video: usize = video_sizes.iter().sum();
+        assert_eq!(*audio_offset, video_offset + video);
+        assert_eq!(
+            &first.data[*video_offset..*audio_offset],
+            [length_prefixed(&[&idr]), merged, length_prefixed(&[&slice])].concat()
+        );
+        assert_eq!(&first.data[*audio_offset..][..raw_aac.len()], raw_aac);
+        assert_eq!(audio_offset + 2 * raw_aac.len(), first.data.len());
+
+        let second = fragment(&segments[2]);
+        assert_eq!(
+            (second.sequence, second.start, second.keyframe),
+            (2, 120, true)
+        );
+        let second_runs = runs(second);
+        assert_eq!(second_runs[0].2, [idr.len() + 4]);
+        assert_eq!(
+            &second.data[second_runs[0].1..][..4],
+            (idr.len() as u32).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn leaves_high_sample_rates_to_esds() {
+        let mut muxer = Muxer::new(Duration::from_millis(100));
+        muxer.add_codec(CodecId::AAC).unwrap();
+        // rate index 0: 96 kHz
+        let aac = config_to_adts(2, 0, 2, &[0x21, 0x10]);
+        let segments = muxer.write_frame(&Frame::new(CodecId::AAC, 0, 0, &aac));
+        let Some(Segment::Init(init)) = segments.first() else {
+            panic!("no init segment");
+        };
+        let position = init.windows(4).position(|w| w == b"mp4a").unwrap();
+        // reserved, data reference, version etc., channels, sample size, pre-defined
+        assert_eq!(be32(init, position + 4 + 24), 0);
+        assert_eq!(&init[position + 4 + 16..position + 4 + 18], &[0, 2]);
+    }
+}
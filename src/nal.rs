@@ -0,0 +1,1013 @@
+//! H264/H265 bitstream parsing in Rust: NAL units, parameter sets and
+//! access units.
+//!
+//! ```ignore
+//! let mut units = AccessUnitSplitter::new(CodecId::H264)?;
+//! let splitter = H264Splitter::new(Box::new(move |nal| {
+//!     if let Some(au) = units.push(nal) {
+//!         publish(au.to_annexb(), au.keyframe);
+//!     }
+//! }), false);
+//! // `units.parameter_sets().sps_info()` has the resolution once the SPS was seen
+//! ```
+
+use crate::obj::CodecId;
+
+/// Strips a leading `00 00 01` / `00 00 00 01` start code.
+pub fn strip_start_code(data: &[u8]) -> &[u8] {
+    if data.starts_with(&[0, 0, 0, 1]) {
+        &data[4..]
+    } else if data.starts_with(&[0, 0, 1]) {
+        &data[3..]
+    } else {
+        data
+    }
+}
+
+/// Splits an Annex-B byte stream into NAL units, without start codes.
+pub fn split_annexb(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|&next| {
+            // the zero of a 4 byte start code belongs to it, not to the NAL
+            let mut end = next - 3;
+            while end > 0 && data[end - 1] == 0 {
+                end -= 1;
+            }
+            end
+        })
+        .chain(std::iter::once(data.len()))
+        .collect();
+
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(move |(start, end)| &data[start..end.max(start)])
+        .filter(|nal| !nal.is_empty())
+}
+
+/// Removes emulation prevention bytes (`00 00 03` -> `00 00`).
+pub fn to_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// What a NAL unit carries, across H264 and H265.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NalKind {
+    /// Non-keyframe slice.
+    Slice,
+    /// IDR (H264) or IRAP (H265) slice.
+    Keyframe,
+    Vps,
+    Sps,
+    Pps,
+    /// Prefix or (H265) suffix SEI.
+    Sei,
+    Aud,
+    Other,
+}
+
+/// A NAL unit, without start code.
+#[derive(Debug, Clone, Copy)]
+pub struct NalUnit<'a> {
+    codec: CodecId,
+    nal_type: u8,
+    data: &'a [u8],
+}
+
+impl<'a> NalUnit<'a> {
+    /// Parses one NAL unit of an H264 or H265 stream; a leading start code is
+    /// skipped, e.g. for [`H264Splitter`](crate::frame::H264Splitter) output.
+    pub fn parse(codec: CodecId, data: &'a [u8]) -> anyhow::Result<Self> {
+        let data = strip_start_code(data);
+        let nal_type = match (codec, data.first()) {
+            (_, None) => anyhow::bail!("empty nal unit"),
+            (CodecId::H264, Some(header)) => header & 0x1F,
+            (CodecId::H265, Some(header)) if data.len() >= 2 => (header >> 1) & 0x3F,
+            (CodecId::H265, Some(_)) => anyhow::bail!("truncated h265 nal header"),
+            (codec, _) => anyhow::bail!("{} has no nal units", codec),
+        };
+        Ok(NalUnit {
+            codec,
+            nal_type,
+            data,
+        })
+    }
+
+    pub fn codec(&self) -> CodecId {
+        self.codec
+    }
+
+    /// `nal_unit_type` from the header.
+    pub fn nal_type(&self) -> u8 {
+        self.nal_type
+    }
+
+    /// The whole unit, header included.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn header_len(&self) -> usize {
+        match self.codec {
+            CodecId::H265 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Payload with emulation prevention bytes removed.
+    pub fn rbsp(&self) -> Vec<u8> {
+        to_rbsp(&self.data[self.header_len()..])
+    }
+
+    pub fn kind(&self) -> NalKind {
+        match (self.codec, self.nal_type) {
+            (CodecId::H265, 16..=23) => NalKind::Keyframe,
+            (CodecId::H265, 0..=15) => NalKind::Slice,
+            (CodecId::H265, 32) => NalKind::Vps,
+            (CodecId::H265, 33) => NalKind::Sps,
+            (CodecId::H265, 34) => NalKind::Pps,
+            (CodecId::H265, 35) => NalKind::Aud,
+            (CodecId::H265, 39 | 40) => NalKind::Sei,
+            (CodecId::H265, _) => NalKind::Other,
+            (_, 5) => NalKind::Keyframe,
+            (_, 1..=4) => NalKind::Slice,
+            (_, 6) => NalKind::Sei,
+            (_, 7) => NalKind::Sps,
+            (_, 8) => NalKind::Pps,
+            (_, 9) => NalKind::Aud,
+            _ => NalKind::Other,
+        }
+    }
+
+    /// Coded picture data (a slice).
+    pub fn is_vcl(&self) -> bool {
+        matches!(self.kind(), NalKind::Slice | NalKind::Keyframe)
+    }
+
+    /// IDR for H264, IRAP (IDR, CRA, BLA) for H265.
+    pub fn is_keyframe(&self) -> bool {
+        self.kind() == NalKind::Keyframe
+    }
+
+    pub fn is_parameter_set(&self) -> bool {
+        matches!(self.kind(), NalKind::Vps | NalKind::Sps | NalKind::Pps)
+    }
+
+    pub fn is_sei(&self) -> bool {
+        self.kind() == NalKind::Sei
+    }
+
+    /// H265 suffix SEI, which follows the slices of its picture.
+    pub fn is_suffix_sei(&self) -> bool {
+        self.codec == CodecId::H265 && self.nal_type == 40
+    }
+
+    pub fn is_aud(&self) -> bool {
+        self.kind() == NalKind::Aud
+    }
+
+    /// For slices: whether this is the first slice of its picture
+    /// (`first_mb_in_slice == 0` / `first_slice_segment_in_pic_flag`).
+    pub fn is_first_slice(&self) -> bool {
+        self.is_vcl()
+            && self
+                .data
+                .get(self.header_len())
+                .is_some_and(|byte| byte & 0x80 != 0)
+    }
+
+    /// Decodes the SPS; `None` if this is not an SPS.
+    pub fn sps_info(&self) -> Option<anyhow::Result<SpsInfo>> {
+        (self.kind() == NalKind::Sps).then(|| match self.codec {
+            CodecId::H265 => parse_h265_sps(&self.rbsp()),
+            _ => parse_h264_sps(&self.rbsp()),
+        })
+    }
+}
+
+/// Fields of a sequence parameter set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpsInfo {
+    pub codec: CodecId,
+    /// `profile_idc` / `general_profile_idc`, e.g. 66, 77, 100 for H264
+    /// baseline, main and high; 1, 2 for H265 main and main 10.
+    pub profile_idc: u8,
+    /// `level_idc` / `general_level_idc`: level * 10 for H264, level * 30 for H265.
+    pub level_idc: u8,
+    pub chroma_format_idc: u32,
+    /// Luma bit depth.
+    pub bit_depth: u32,
+    pub bit_depth_chroma: u32,
+    /// Picture size after cropping.
+    pub width: u32,
+    pub height: u32,
+    /// From the VUI timing info, if present.
+    pub fps: Option<f32>,
+}
+
+/// The latest VPS/SPS/PPS of a stream.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterSets {
+    pub vps: Option<Vec<u8>>,
+    pub sps: Option<Vec<u8>>,
+    pub pps: Option<Vec<u8>>,
+    info: Option<SpsInfo>,
+}
+
+impl ParameterSets {
+    /// Records `nal` if it is a parameter set; returns whether it was one.
+    pub fn update(&mut self, nal: &NalUnit) -> bool {
+        match nal.kind() {
+            NalKind::Vps => self.vps = Some(nal.data().to_vec()),
+            NalKind::Sps => {
+                if let Some(Ok(info)) = nal.sps_info() {
+                    self.info = Some(info);
+                }
+                self.sps = Some(nal.data().to_vec())
+            }
+            NalKind::Pps => self.pps = Some(nal.data().to_vec()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Decoded fields of the latest SPS that parsed.
+    pub fn sps_info(&self) -> Option<&SpsInfo> {
+        self.info.as_ref()
+    }
+
+    /// Whether everything a decoder needs was seen (VPS only for H265).
+    pub fn is_complete(&self, codec: CodecId) -> bool {
+        self.sps.is_some() && self.pps.is_some() && (codec != CodecId::H265 || self.vps.is_some())
+    }
+
+    /// `AVCDecoderConfigurationRecord` (`avcC`) of the H264 SPS/PPS.
+    pub fn avc_config_record(&self) -> Option<Vec<u8>> {
+        let sps = self.sps.as_ref().filter(|sps| sps.len() >= 4)?;
+        let pps = self.pps.as_ref()?;
+        let mut record = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+        record.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        record.extend_from_slice(sps);
+        record.push(1);
+        record.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        record.extend_from_slice(pps);
+        Some(record)
+    }
+
+    /// `HEVCDecoderConfigurationRecord` (`hvcC`) of the H265 VPS/SPS/PPS.
+    pub fn hevc_config_record(&self) -> Option<Vec<u8>> {
+        let sps = self.sps.as_ref()?;
+        let info = self.sps_info()?;
+        // sps header byte then the 12 general profile_tier_level bytes
+        let rbsp = to_rbsp(sps.get(2..)?);
+        let ptl = rbsp.get(1..13)?;
+        let sub_layers = (rbsp[0] >> 1) & 0x07;
+        let nesting = rbsp[0] & 0x01;
+
+        let mut record = vec![1];
+        record.extend_from_slice(ptl);
+        record.extend_from_slice(&[
+            0xF0,
+            0x00,
+            0xFC,
+            0xFC | info.chroma_format_idc as u8,
+            0xF8 | (info.bit_depth - 8) as u8,
+            0xF8 | (info.bit_depth_chroma - 8) as u8,
+            0,
+            0,
+            (sub_layers + 1) << 3 | nesting << 2 | 0x03,
+            3,
+        ]);
+        for (kind, nal) in [(32u8, &self.vps), (33, &self.sps), (34, &self.pps)] {
+            let nal = nal.as_ref()?;
+            record.extend_from_slice(&[0x80 | kind, 0, 1]);
+            record.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            record.extend_from_slice(nal);
+        }
+        Some(record)
+    }
+
+    /// VPS, SPS and PPS as Annex-B.
+    pub fn to_annexb(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nal in [&self.vps, &self.sps, &self.pps].into_iter().flatten() {
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(nal);
+        }
+        out
+    }
+}
+
+/// The NAL units of one picture.
+#[derive(Debug, Clone, Default)]
+pub struct AccessUnit {
+    /// Units without start codes.
+    pub nals: Vec<Vec<u8>>,
+    /// Contains an IDR/IRAP slice.
+    pub keyframe: bool,
+}
+
+impl AccessUnit {
+    pub fn to_annexb(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.nals.iter().map(|nal| nal.len() + 4).sum());
+        for nal in &self.nals {
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(nal);
+        }
+        out
+    }
+}
+
+/// Groups NAL units into access units, following the first-slice flag and
+/// the units (AUD, SEI, parameter sets) that may only start a new picture.
+#[derive(Debug)]
+pub struct AccessUnitSplitter {
+    codec: CodecId,
+    current: AccessUnit,
+    has_vcl: bool,
+    parameter_sets: ParameterSets,
+}
+
+impl AccessUnitSplitter {
+    pub fn new(codec: CodecId) -> anyhow::Result<Self> {
+        if !matches!(codec, CodecId::H264 | CodecId::H265) {
+            anyhow::bail!("{} has no nal units", codec);
+        }
+        Ok(Self {
+            codec,
+            current: AccessUnit::default(),
+            has_vcl: false,
+            parameter_sets: ParameterSets::default(),
+        })
+    }
+
+    /// Adds one NAL unit (start code optional); returns the previous access
+    /// unit once this one starts a new picture. Malformed units are dropped.
+    pub fn push(&mut self, data: &[u8]) -> Option<AccessUnit> {
+        let nal = NalUnit::parse(self.codec, data).ok()?;
+        self.parameter_sets.update(&nal);
+
+        let starts_picture = match nal.kind() {
+            NalKind::Slice | NalKind::Keyframe => nal.is_first_slice(),
+            NalKind::Sei => !nal.is_suffix_sei(),
+            NalKind::Aud | NalKind::Vps | NalKind::Sps | NalKind::Pps => true,
+            NalKind::Other => false,
+        };
+        let done = if self.has_vcl && starts_picture {
+            self.has_vcl = false;
+            Some(std::mem::take(&mut self.current))
+        } else {
+            None
+        };
+
+        self.has_vcl |= nal.is_vcl();
+        self.current.keyframe |= nal.is_keyframe();
+        self.current.nals.push(nal.data().to_vec());
+        done
+    }
+
+    /// Pushes every unit of an Annex-B buffer.
+    pub fn push_annexb(&mut self, data: &[u8]) -> Vec<AccessUnit> {
+        split_annexb(data)
+            .filter_map(|nal| self.push(nal))
+            .collect()
+    }
+
+    /// Returns the pending access unit, e.g. at end of stream.
+    pub fn flush(&mut self) -> Option<AccessUnit> {
+        self.has_vcl = false;
+        let current = std::mem::take(&mut self.current);
+        (!current.nals.is_empty()).then_some(current)
+    }
+
+    pub fn parameter_sets(&self) -> &ParameterSets {
+        &self.parameter_sets
+    }
+}
+
+/// Largest picture dimension accepted from an SPS, in luma samples.
+const MAX_SIZE: u32 = 16384;
+
+/// Largest bit depth accepted from an SPS.
+const MAX_BIT_DEPTH: u32 = 16;
+
+/// Exp-Golomb capable MSB-first bit reader over an RBSP (or any other
+/// bitstream header).
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> anyhow::Result<u32> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or_else(|| anyhow::anyhow!("bitstream truncated"))?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    pub(crate) fn flag(&mut self) -> anyhow::Result<bool> {
+        Ok(self.bit()? == 1)
+    }
+
+    pub(crate) fn bits(&mut self, n: u32) -> anyhow::Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn skip(&mut self, n: usize) -> anyhow::Result<()> {
+        if self.pos + n > self.data.len() * 8 {
+            anyhow::bail!("bitstream truncated");
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    pub(crate) fn ue(&mut self) -> anyhow::Result<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                anyhow::bail!("invalid exp-golomb code");
+            }
+        }
+        Ok(((1u64 << zeros) - 1 + self.bits(zeros)? as u64) as u32)
+    }
+
+    /// [`ue`](Self::ue) that fails above `max`, for values used in arithmetic.
+    fn ue_max(&mut self, max: u32) -> anyhow::Result<u32> {
+        let value = self.ue()?;
+        anyhow::ensure!(value <= max, "sps value {} out of range", value);
+        Ok(value)
+    }
+
+    fn se(&mut self) -> anyhow::Result<i32> {
+        let value = self.ue()? as i64;
+        Ok(if value % 2 == 1 {
+            ((value + 1) / 2) as i32
+        } else {
+            -(value / 2) as i32
+        })
+    }
+}
+
+fn parse_h264_sps(rbsp: &[u8]) -> anyhow::Result<SpsInfo> {
+    let mut r = BitReader::new(rbsp);
+    let profile_idc = r.bits(8)? as u8;
+    r.skip(8)?; // constraint flags
+    let level_idc = r.bits(8)? as u8;
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    let (mut bit_depth, mut bit_depth_chroma) = (8, 8);
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue_max(3)?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.flag()?;
+        }
+        bit_depth = r.ue_max(MAX_BIT_DEPTH - 8)? + 8;
+        bit_depth_chroma = r.ue_max(MAX_BIT_DEPTH - 8)? + 8;
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.flag()? {
+                    skip_h264_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue_max(255)? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_mbs = r.ue_max(MAX_SIZE / 16 - 1)? + 1;
+    let height_map_units = r.ue_max(MAX_SIZE / 16 - 1)? + 1;
+    let frame_mbs_only = r.flag()?;
+    if !frame_mbs_only {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+
+    let (mut crop_x, mut crop_y) = (0, 0);
+    if r.flag()? {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let chroma = if separate_colour_plane {
+            0
+        } else {
+            chroma_format_idc
+        };
+        let (unit_x, unit_y) = match chroma {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let unit_y = unit_y * (2 - frame_mbs_only as u32);
+        crop_x = crop(left, right, unit_x)?;
+        crop_y = crop(top, bottom, unit_y)?;
+    }
+
+    // bounded above, so these cannot overflow
+    let width = (width_mbs * 16).saturating_sub(crop_x);
+    let height = ((2 - frame_mbs_only as u32) * height_map_units * 16).saturating_sub(crop_y);
+    let fps = if r.flag()? {
+        // a broken VUI leaves the picture size valid
+        parse_h264_vui_fps(&mut r).ok().flatten()
+    } else {
+        None
+    };
+
+    Ok(SpsInfo {
+        codec: CodecId::H264,
+        profile_idc,
+        level_idc,
+        chroma_format_idc,
+        bit_depth,
+        bit_depth_chroma,
+        width,
+        height,
+        fps,
+    })
+}
+
+/// Cropped samples of a conformance window edge pair.
+fn crop(first: u32, second: u32, unit: u32) -> anyhow::Result<u32> {
+    first
+        .checked_add(second)
+        .and_then(|sum| sum.checked_mul(unit))
+        .ok_or_else(|| anyhow::anyhow!("invalid cropping"))
+}
+
+fn skip_h264_scaling_list(r: &mut BitReader, size: usize) -> anyhow::Result<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Ok(())
+}
+
+/// Skips the VUI fields in front of the timing info shared by H264 and H265:
+/// aspect ratio, overscan, video signal type and chroma location.
+fn skip_vui_head(r: &mut BitReader) -> anyhow::Result<()> {
+    if r.flag()? && r.bits(8)? == 255 {
+        r.skip(32)?; // sar_width, sar_height
+    }
+    if r.flag()? {
+        r.skip(1)?; // overscan_appropriate_flag
+    }
+    if r.flag()? {
+        r.skip(4)?; // video_format, video_full_range_flag
+        if r.flag()? {
+            r.skip(24)?; // colour primaries, transfer, matrix
+        }
+    }
+    if r.flag()? {
+        r.ue()?;
+        r.ue()?;
+    }
+    Ok(())
+}
+
+fn parse_h264_vui_fps(r: &mut BitReader) -> anyhow::Result<Option<f32>> {
+    skip_vui_head(r)?;
+    if !r.flag()? {
+        return Ok(None);
+    }
+    let num_units_in_tick = r.bits(32)?;
+    let time_scale = r.bits(32)?;
+    // two fields per frame
+    Ok((num_units_in_tick > 0).then(|| time_scale as f32 / (2.0 * num_units_in_tick as f32)))
+}
+
+fn parse_h265_sps(rbsp: &[u8]) -> anyhow::Result<SpsInfo> {
+    let mut r = BitReader::new(rbsp);
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.bits(3)?;
+    r.skip(1)?; // sps_temporal_id_nesting_flag
+
+    // profile_tier_level(1, max_sub_layers_minus1)
+    r.skip(3)?; // general_profile_space, general_tier_flag
+    let profile_idc = r.bits(5)? as u8;
+    r.skip(32 + 48)?; // compatibility and constraint flags
+    let level_idc = r.bits(8)? as u8;
+    let mut sub_layers = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.flag()?, r.flag()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1 as usize))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+
+    r.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.ue_max(3)?;
+    let separate_colour_plane = chroma_format_idc == 3 && r.flag()?;
+    let mut width = r.ue_max(MAX_SIZE)?;
+    let mut height = r.ue_max(MAX_SIZE)?;
+    if r.flag()? {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let chroma = if separate_colour_plane {
+            0
+        } else {
+            chroma_format_idc
+        };
+        let (unit_x, unit_y) = match chroma {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        width = width.saturating_sub(crop(left, right, unit_x)?);
+        height = height.saturating_sub(crop(top, bottom, unit_y)?);
+    }
+    let bit_depth = r.ue_max(MAX_BIT_DEPTH - 8)? + 8;
+    let bit_depth_chroma = r.ue_max(MAX_BIT_DEPTH - 8)? + 8;
+
+    // a broken tail leaves the picture size valid
+    let fps = parse_h265_sps_tail_fps(&mut r, max_sub_layers_minus1)
+        .ok()
+        .flatten();
+
+    Ok(SpsInfo {
+        codec: CodecId::H265,
+        profile_idc,
+        level_idc,
+        chroma_format_idc,
+        bit_depth,
+        bit_depth_chroma,
+        width,
+        height,
+        fps,
+    })
+}
+
+/// Walks the rest of an H265 SPS up to the VUI timing info.
+fn parse_h265_sps_tail_fps(
+    r: &mut BitReader,
+    max_sub_layers_minus1: u32,
+) -> anyhow::Result<Option<f32>> {
+    let log2_max_poc_lsb = r.ue_max(12)? + 4;
+    let ordering_info_for_all = r.flag()?;
+    let first = if ordering_info_for_all {
+        0
+    } else {
+        max_sub_layers_minus1
+    };
+    for _ in first..=max_sub_layers_minus1 {
+        r.ue()?;
+        r.ue()?;
+        r.ue()?;
+    }
+    for _ in 0..6 {
+        r.ue()?; // coding/transform block sizes and hierarchy depths
+    }
+    if r.flag()? && r.flag()? {
+        skip_h265_scaling_list_data(r)?;
+    }
+    r.skip(2)?; // amp_enabled_flag, sample_adaptive_offset_enabled_flag
+    if r.flag()? {
+        r.skip(8)?; // pcm sample bit depths
+        r.ue()?;
+        r.ue()?;
+        r.skip(1)?; // pcm_loop_filter_disabled_flag
+    }
+
+    let num_sets = r.ue()?;
+    if num_sets > 64 {
+        anyhow::bail!("invalid num_short_term_ref_pic_sets");
+    }
+    let mut delta_pocs: Vec<u32> = Vec::with_capacity(num_sets as usize);
+    for idx in 0..num_sets as usize {
+        let inter = idx != 0 && r.flag()?;
+        if inter {
+            r.skip(1)?; // delta_rps_sign
+            r.ue()?; // abs_delta_rps_minus1
+            let mut count = 0;
+            for _ in 0..=delta_pocs[idx - 1] {
+                let used = r.flag()?;
+                if used || r.flag()? {
+                    count += 1;
+                }
+            }
+            delta_pocs.push(count);
+        } else {
+            let (negative, positive) = (r.ue()?, r.ue()?);
+            if negative > 16 || positive > 16 {
+                anyhow::bail!("invalid short term ref pic set");
+            }
+            for _ in 0..negative + positive {
+                r.ue()?;
+                r.skip(1)?;
+            }
+            delta_pocs.push(negative + positive);
+        }
+    }
+
+    if r.flag()? {
+        for _ in 0..r.ue_max(32)? {
+            r.skip(log2_max_poc_lsb as usize + 1)?;
+        }
+    }
+    r.skip(2)?; // sps_temporal_mvp_enabled_flag, strong_intra_smoothing_enabled_flag
+    if !r.flag()? {
+        return Ok(None);
+    }
+
+    skip_vui_head(r)?;
+    r.skip(3)?; // neutral_chroma, field_seq, frame_field_info
+    if r.flag()? {
+        r.ue()?;
+        r.ue()?;
+        r.ue()?;
+        r.ue()?; // default display window
+    }
+    if !r.flag()? {
+        return Ok(None);
+    }
+    let num_units_in_tick = r.bits(32)?;
+    let time_scale = r.bits(32)?;
+    Ok((num_units_in_tick > 0).then(|| time_scale as f32 / num_units_in_tick as f32))
+}
+
+fn skip_h265_scaling_list_data(r: &mut BitReader) -> anyhow::Result<()> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0..6).step_by(step) {
+            if !r.flag()? {
+                r.ue()?; // scaling_list_pred_matrix_id_delta
+            } else {
+                let coefs = 64.min(1 << (4 + (size_id << 1)));
+                if size_id > 1 {
+                    r.se()?; // scaling_list_dc_coef_minus8
+                }
+                for _ in 0..coefs {
+                    r.se()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MSB-first writer for building parameter sets.
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, n: u32, value: u64) -> &mut Self {
+            for i in (0..n).rev() {
+                self.bits.push((value >> i) & 1 == 1);
+            }
+            self
+        }
+
+        fn ue(&mut self, value: u64) -> &mut Self {
+            let len = 64 - (value + 1).leading_zeros();
+            self.bits(len - 1, 0).bits(len, value + 1)
+        }
+
+        /// Ends with `rbsp_trailing_bits` and adds emulation prevention.
+        fn nal(&mut self, header: &[u8]) -> Vec<u8> {
+            self.bits.push(true);
+            self.bits.resize(self.bits.len().div_ceil(8) * 8, false);
+            let mut out = header.to_vec();
+            let mut zeros = 0;
+            for byte in self.bits.chunks(8) {
+                let byte = byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8);
+                if zeros >= 2 && byte <= 3 {
+                    out.push(3);
+                    zeros = 0;
+                }
+                zeros = if byte == 0 { zeros + 1 } else { 0 };
+                out.push(byte);
+            }
+            out
+        }
+    }
+
+    fn h264_sps(width_mbs_minus1: u64, crop_right: u64) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(8, 66).bits(8, 0).bits(8, 31); // baseline, level 3.1
+        w.ue(0).ue(0).ue(0).ue(0).ue(1).bits(1, 0);
+        w.ue(width_mbs_minus1).ue(44); // 45 map units
+        w.bits(1, 1).bits(1, 1); // frame_mbs_only, direct_8x8_inference
+        if crop_right > 0 {
+            w.bits(1, 1).ue(0).ue(crop_right).ue(0).ue(0);
+        } else {
+            w.bits(1, 0);
+        }
+        w.bits(1, 1).bits(4, 0); // vui, no head fields
+        w.bits(1, 1).bits(32, 1).bits(32, 60); // timing: 60 fields/s
+        w.nal(&[0x67])
+    }
+
+    fn h265_sps(bit_depth_chroma_minus8: u64) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(4, 0).bits(3, 0).bits(1, 1);
+        w.bits(3, 0)
+            .bits(5, 1)
+            .bits(32, 0x6000_0000)
+            .bits(48, 0)
+            .bits(8, 93);
+        w.ue(0).ue(1).ue(1280).ue(720).bits(1, 0);
+        w.ue(0).ue(bit_depth_chroma_minus8);
+        w.ue(4).bits(1, 1).ue(1).ue(0).ue(0);
+        w.ue(0).ue(1).ue(0).ue(2).ue(0).ue(0);
+        w.bits(1, 0).bits(2, 0).bits(1, 0); // scaling list, amp/sao, pcm
+        w.ue(0).bits(1, 0).bits(2, 0); // ref pic sets, long term, mvp/smoothing
+        w.bits(1, 1).bits(4, 0).bits(3, 0).bits(1, 0); // vui, no head fields
+        w.bits(1, 1).bits(32, 1).bits(32, 25);
+        w.nal(&[0x42, 0x01])
+    }
+
+    #[test]
+    fn parses_h264_sps() {
+        let sps = h264_sps(79, 0);
+        let nal = NalUnit::parse(CodecId::H264, &sps).unwrap();
+        assert_eq!(nal.kind(), NalKind::Sps);
+        let info = nal.sps_info().unwrap().unwrap();
+        assert_eq!((info.profile_idc, info.level_idc), (66, 31));
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!((info.bit_depth, info.bit_depth_chroma), (8, 8));
+        assert_eq!(info.fps, Some(30.0));
+
+        let cropped = h264_sps(79, 4);
+        let info = NalUnit::parse(CodecId::H264, &cropped)
+            .unwrap()
+            .sps_info()
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.width, 1272);
+    }
+
+    #[test]
+    fn parses_h265_sps() {
+        let sps = h265_sps(2);
+        let info = NalUnit::parse(CodecId::H265, &sps)
+            .unwrap()
+            .sps_info()
+            .unwrap()
+            .unwrap();
+        assert_eq!((info.profile_idc, info.level_idc), (1, 93));
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!((info.bit_depth, info.bit_depth_chroma), (8, 10));
+        assert_eq!(info.fps, Some(25.0));
+    }
+
+    #[test]
+    fn rejects_out_of_range_sps() {
+        for sps in [
+            h264_sps(u32::MAX as u64 - 1, 0),
+            h264_sps(79, u32::MAX as u64 - 1),
+        ] {
+            let nal = NalUnit::parse(CodecId::H264, &sps).unwrap();
+            assert!(nal.sps_info().unwrap().is_err());
+        }
+        let truncated = &h264_sps(79, 0)[..4];
+        let nal = NalUnit::parse(CodecId::H264, truncated).unwrap();
+        assert!(nal.sps_info().unwrap().is_err());
+    }
+
+    #[test]
+    fn removes_emulation_prevention() {
+        assert_eq!(to_rbsp(&[0, 0, 3, 1, 0, 0, 3, 0, 3]), [0, 0, 1, 0, 0, 0, 3]);
+        let nal = NalUnit::parse(CodecId::H265, &[0x42, 0x01, 0, 0, 3, 2]).unwrap();
+        assert_eq!(nal.rbsp(), [0, 0, 2]);
+    }
+
+    #[test]
+    fn splits_annexb() {
+        let data = [0, 0, 0, 1, 0x67, 1, 0, 0, 1, 0x68, 2, 0, 0, 0, 1, 0x65, 3];
+        let nals: Vec<_> = split_annexb(&data).map(strip_start_code).collect();
+        assert_eq!(nals, [&[0x67, 1][..], &[0x68, 2], &[0x65, 3]]);
+        assert_eq!(strip_start_code(&[0, 0, 1, 9]), [9]);
+    }
+
+    #[test]
+    fn keeps_suffix_sei_in_access_unit() {
+        let mut splitter = AccessUnitSplitter::new(CodecId::H265).unwrap();
+        assert!(splitter.push(&[0x4E, 0x01, 0x05]).is_none()); // prefix SEI
+        assert!(splitter.push(&[0x26, 0x01, 0x80]).is_none()); // IDR, first slice
+        assert!(splitter.push(&[0x50, 0x01, 0x05]).is_none()); // suffix SEI
+        let au = splitter.push(&[0x02, 0x01, 0x80]).unwrap(); // next picture
+        assert!(au.keyframe);
+        assert_eq!(au.nals.len(), 3);
+        assert_eq!(au.nals[2][0] >> 1, 40);
+
+        let next = splitter.flush().unwrap();
+        assert!(!next.keyframe);
+        assert_eq!(next.nals.len(), 1);
+    }
+
+    #[test]
+    fn splits_h264_access_units() {
+        let mut splitter = AccessUnitSplitter::new(CodecId::H264).unwrap();
+        let mut data = Vec::new();
+        for nal in [
+            h264_sps(79, 0),
+            vec![0x68, 0xCE],
+            vec![0x65, 0x88],
+            vec![0x65, 0x08],
+            vec![0x41, 0x9A],
+        ] {
+            data.extend_from_slice(&[0, 0, 0, 1]);
+            data.extend_from_slice(&nal);
+        }
+        let aus = splitter.push_annexb(&data);
+        // the second IDR slice is not a first slice
+        assert_eq!(aus.len(), 1);
+        assert_eq!(aus[0].nals.len(), 4);
+        assert!(aus[0].keyframe);
+        assert!(splitter.parameter_sets().is_complete(CodecId::H264));
+        assert_eq!(splitter.parameter_sets().sps_info().unwrap().width, 1280);
+
+        let record = splitter.parameter_sets().avc_config_record().unwrap();
+        assert_eq!(&record[..6], &[1, 66, 0, 31, 0xFF, 0xE1]);
+        assert!(AccessUnitSplitter::new(CodecId::AAC).is_err());
+    }
+
+    #[test]
+    fn writes_hevc_config_record() {
+        let mut sets = ParameterSets::default();
+        for data in [vec![0x40, 0x01, 0x0C], h265_sps(2), vec![0x44, 0x01, 0xC1]] {
+            assert!(sets.update(&NalUnit::parse(CodecId::H265, &data).unwrap()));
+        }
+        assert!(sets.is_complete(CodecId::H265));
+        let record = sets.hevc_config_record().unwrap();
+        assert_eq!(record[1], 0x01); // general profile
+        assert_eq!(record[12], 93); // general level
+        assert_eq!(record[16], 0xFC | 1); // chroma format
+        assert_eq!(record[17], 0xF8); // luma bit depth 8
+        assert_eq!(record[18], 0xFA); // chroma bit depth 10
+        assert_eq!(record[22], 3); // vps, sps, pps arrays
+        assert_eq!(record[23], 0x80 | 32);
+    }
+}
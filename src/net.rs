@@ -0,0 +1,616 @@
+//! Custom protocols on ZLMediaKit's network stack.
+//!
+//! Sessions and clients live on the same poller threads as media, so
+//! handlers run there and may call other ZLMediaKit APIs directly.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use rszlm_sys::*;
+
+use crate::{
+    box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr, obj::SockInfo, poller::EventPoller,
+};
+
+/// Transport of a [`TcpServer`] or [`TcpClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TcpType {
+    #[default]
+    Tcp,
+    Ssl,
+    Ws,
+    Wss,
+}
+
+impl From<TcpType> for mk_tcp_type {
+    fn from(ty: TcpType) -> Self {
+        match ty {
+            TcpType::Tcp => mk_tcp_type_mk_type_tcp,
+            TcpType::Ssl => mk_tcp_type_mk_type_ssl,
+            TcpType::Ws => mk_tcp_type_mk_type_ws,
+            TcpType::Wss => mk_tcp_type_mk_type_wss,
+        }
+    }
+}
+
+/// Callbacks of a [`TcpServer`], all run on the session's poller thread.
+///
+/// A plain `Fn(&TcpSession, &[u8])` closure is a handler that only reads data.
+pub trait TcpHandler: Send + Sync + 'static {
+    fn on_connect(&self, _session: &TcpSession) {}
+
+    fn on_data(&self, session: &TcpSession, data: &[u8]);
+
+    /// Called about every 2 seconds per session, e.g. for idle timeouts.
+    fn on_manager(&self, _session: &TcpSession) {}
+
+    fn on_disconnect(&self, _session: &TcpSession, _code: i32, _msg: &str) {}
+}
+
+impl<F> TcpHandler for F
+where
+    F: Fn(&TcpSession, &[u8]) + Send + Sync + 'static,
+{
+    fn on_data(&self, session: &TcpSession, data: &[u8]) {
+        self(session, data)
+    }
+}
+
+/// Handlers by local port. ZLMediaKit has a single set of tcp server events,
+/// each callback carries the port of the server the session belongs to.
+static TCP_HANDLERS: Lazy<RwLock<HashMap<u16, Arc<dyn TcpHandler>>>> = Lazy::new(Default::default);
+
+/// A tcp server; stopped when dropped.
+pub struct TcpServer {
+    port: u16,
+}
+
+impl TcpServer {
+    /// Starts a plain tcp server, `port` 0 picks a free port.
+    pub fn start(port: u16, handler: impl TcpHandler) -> anyhow::Result<Self> {
+        Self::start_with(port, TcpType::Tcp, handler)
+    }
+
+    pub fn start_with(port: u16, ty: TcpType, handler: impl TcpHandler) -> anyhow::Result<Self> {
+        static EVENTS: mk_tcp_session_events = mk_tcp_session_events {
+            on_mk_tcp_session_create: Some(on_mk_tcp_session_create),
+            on_mk_tcp_session_data: Some(on_mk_tcp_session_data),
+            on_mk_tcp_session_manager: Some(on_mk_tcp_session_manager),
+            on_mk_tcp_session_disconnect: Some(on_mk_tcp_session_disconnect),
+        };
+
+        crate::init::Runtime::ensure_initialized()?;
+        let mut handlers = TCP_HANDLERS.write().unwrap();
+        if port != 0 && handlers.contains_key(&port) {
+            anyhow::bail!("tcp server already running on port {}", port);
+        }
+        unsafe { mk_tcp_server_events_listen(&EVENTS) };
+        let bound = unsafe { mk_tcp_server_start(port, ty.into()) };
+        if bound == 0 {
+            anyhow::bail!("failed to start tcp server on port {}", port);
+        }
+        handlers.insert(bound, Arc::new(handler));
+        Ok(TcpServer { port: bound })
+    }
+
+    /// The bound port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for TcpServer {
+    fn drop(&mut self) {
+        unsafe { mk_tcp_server_server_stop(self.port) };
+        TCP_HANDLERS.write().unwrap().remove(&self.port);
+    }
+}
+
+fn tcp_handler(port: u16) -> Option<Arc<dyn TcpHandler>> {
+    TCP_HANDLERS.read().unwrap().get(&port).cloned()
+}
+
+extern "C" fn on_mk_tcp_session_create(port: u16, session: mk_tcp_session) {
+    crate::ffi_guard(|| {
+        if let Some(handler) = tcp_handler(port) {
+            handler.on_connect(&TcpSession(session));
+        }
+    });
+}
+
+extern "C" fn on_mk_tcp_session_data(port: u16, session: mk_tcp_session, buffer: mk_buffer) {
+    crate::ffi_guard(|| {
+        if let Some(handler) = tcp_handler(port) {
+            handler.on_data(&TcpSession(session), unsafe { buffer_as_slice(buffer) });
+        }
+    });
+}
+
+extern "C" fn on_mk_tcp_session_manager(port: u16, session: mk_tcp_session) {
+    crate::ffi_guard(|| {
+        if let Some(handler) = tcp_handler(port) {
+            handler.on_manager(&TcpSession(session));
+        }
+    });
+}
+
+extern "C" fn on_mk_tcp_session_disconnect(
+    port: u16,
+    session: mk_tcp_session,
+    code: ::std::os::raw::c_int,
+    msg: *const ::std::os::raw::c_char,
+) {
+    crate::ffi_guard(|| {
+        if let Some(handler) = tcp_handler(port) {
+            let msg = unsafe { const_ptr_to_string!(msg) };
+            handler.on_disconnect(&TcpSession(session), code, &msg);
+        }
+    });
+}
+
+unsafe fn buffer_as_slice<'a>(buffer: mk_buffer) -> &'a [u8] {
+    let data = mk_buffer_get_data(buffer);
+    let size = mk_buffer_get_size(buffer);
+    if data.is_null() || size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data as *const u8, size)
+    }
+}
+
+/// A server side connection, only valid inside [`TcpHandler`] callbacks.
+/// Keep a [`TcpSessionRef`] to reach it later.
+pub struct TcpSession(mk_tcp_session);
+
+impl TcpSession {
+    /// Sends on the session's poller thread, where handlers run.
+    pub fn send(&self, data: &[u8]) {
+        unsafe { mk_tcp_session_send(self.0, data.as_ptr() as *const _, data.len()) }
+    }
+
+    /// Closes the session; `on_disconnect` follows with `code` and `msg`.
+    pub fn shutdown(&self, code: i32, msg: &str) {
+        let msg = const_str_to_ptr!(msg);
+        unsafe { mk_tcp_session_shutdown(self.0, code, msg.as_ptr()) }
+    }
+
+    pub fn sock_info(&self) -> SockInfo {
+        unsafe { mk_tcp_session_get_sock_info(self.0) }.into()
+    }
+
+    pub fn peer_ip(&self) -> String {
+        self.sock_info().peer_ip()
+    }
+
+    pub fn peer_port(&self) -> u16 {
+        self.sock_info().peer_port()
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.sock_info().local_port()
+    }
+
+    pub fn poller(&self) -> EventPoller {
+        unsafe { EventPoller::from_tcp_session(self.0) }
+    }
+
+    /// A weak, thread-safe handle to this session.
+    pub fn to_ref(&self) -> TcpSessionRef {
+        TcpSessionRef(Arc::new(SessionRef {
+            inner: unsafe { mk_tcp_session_ref_from(self.0) },
+            poller: self.poller(),
+        }))
+    }
+}
+
+/// Weak handle to a [`TcpSession`], usable from any thread. Operations on a
+/// closed session are ignored.
+#[derive(Clone)]
+pub struct TcpSessionRef(Arc<SessionRef>);
+
+struct SessionRef {
+    inner: mk_tcp_session_ref,
+    poller: EventPoller,
+}
+
+unsafe impl Send for SessionRef {}
+unsafe impl Sync for SessionRef {}
+
+impl Drop for SessionRef {
+    fn drop(&mut self) {
+        unsafe { mk_tcp_session_ref_release(self.inner) }
+    }
+}
+
+impl TcpSessionRef {
+    pub fn is_alive(&self) -> bool {
+        !unsafe { mk_tcp_session_from_ref(self.0.inner) }.is_null()
+    }
+
+    pub fn send(&self, data: &[u8]) {
+        let session = unsafe { mk_tcp_session_from_ref(self.0.inner) };
+        if !session.is_null() {
+            unsafe { mk_tcp_session_send_safe(session, data.as_ptr() as *const _, data.len()) }
+        }
+    }
+
+    /// Closes the session on its poller thread.
+    pub fn shutdown(&self, code: i32, msg: &str) {
+        let this = self.0.clone();
+        let msg = msg.to_string();
+        self.0.poller.spawn(move || {
+            let session = unsafe { mk_tcp_session_from_ref(this.inner) };
+            if !session.is_null() {
+                TcpSession(session).shutdown(code, &msg);
+            }
+        });
+    }
+
+    pub fn poller(&self) -> EventPoller {
+        self.0.poller
+    }
+}
+
+type OnConnectFn = Box<dyn FnMut(&TcpClient, anyhow::Result<()>) + Send + 'static>;
+type OnDataFn = Box<dyn FnMut(&TcpClient, &[u8]) + Send + 'static>;
+type OnErrFn = Box<dyn FnMut(&TcpClient, i32, String) + Send + 'static>;
+
+#[derive(Default)]
+struct ClientCallbacks {
+    on_connect: Option<OnConnectFn>,
+    on_data: Option<OnDataFn>,
+    on_err: Option<OnErrFn>,
+}
+
+/// A tcp client; the connection is closed when dropped.
+///
+/// Callbacks receive a borrowed `&TcpClient` they can [`send`](Self::send) on.
+pub struct TcpClient(mk_tcp_client, bool);
+
+unsafe impl Send for TcpClient {}
+unsafe impl Sync for TcpClient {}
+
+impl TcpClient {
+    pub fn builder() -> TcpClientBuilder {
+        TcpClientBuilder::default()
+    }
+
+    /// Thread-safe send.
+    pub fn send(&self, data: &[u8]) {
+        unsafe { mk_tcp_client_send_safe(self.0, data.as_ptr() as *const _, data.len() as i32) }
+    }
+
+    pub fn poller(&self) -> EventPoller {
+        unsafe { mk_thread_from_tcp_client(self.0) }.into()
+    }
+}
+
+impl Drop for TcpClient {
+    fn drop(&mut self) {
+        if self.1 {
+            unsafe { mk_tcp_client_release(self.0) }
+        }
+    }
+}
+
+pub struct TcpClientBuilder {
+    ty: TcpType,
+    timeout: Duration,
+    callbacks: ClientCallbacks,
+}
+
+impl Default for TcpClientBuilder {
+    fn default() -> Self {
+        Self {
+            ty: TcpType::Tcp,
+            timeout: Duration::from_secs(5),
+            callbacks: ClientCallbacks::default(),
+        }
+    }
+}
+
+impl TcpClientBuilder {
+    pub fn tcp_type(mut self, ty: TcpType) -> Self {
+        self.ty = ty;
+        self
+    }
+
+    /// Connect timeout, 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Connect result.
+    pub fn on_connect(
+        mut self,
+        cb: impl FnMut(&TcpClient, anyhow::Result<()>) + Send + 'static,
+    ) -> Self {
+        self.callbacks.on_connect = Some(Box::new(cb));
+        self
+    }
+
+    pub fn on_data(mut self, cb: impl FnMut(&TcpClient, &[u8]) + Send + 'static) -> Self {
+        self.callbacks.on_data = Some(Box::new(cb));
+        self
+    }
+
+    /// Connection lost after it was established.
+    pub fn on_err(mut self, cb: impl FnMut(&TcpClient, i32, String) + Send + 'static) -> Self {
+        self.callbacks.on_err = Some(Box::new(cb));
+        self
+    }
+
+    /// Starts connecting; the result is reported to `on_connect`.
+    pub fn connect(self, host: &str, port: u16) -> anyhow::Result<TcpClient> {
+        crate::init::Runtime::ensure_initialized()?;
+        let mut events = mk_tcp_client_events {
+            on_mk_tcp_client_connect: Some(on_mk_tcp_client_connect),
+            on_mk_tcp_client_data: Some(on_mk_tcp_client_data),
+            on_mk_tcp_client_disconnect: Some(on_mk_tcp_client_disconnect),
+            on_mk_tcp_client_manager: None,
+        };
+        let client = TcpClient(
+            unsafe { mk_tcp_client_create(&mut events, self.ty.into()) },
+            true,
+        );
+        unsafe {
+            // freed together with the client, after its last callback
+            mk_tcp_client_set_user_data2(
+                client.0,
+                box_to_mut_void_ptr!(self.callbacks),
+                Some(free_client_callbacks),
+            );
+            let host = const_str_to_ptr!(host);
+            mk_tcp_client_connect(client.0, host.as_ptr(), port, self.timeout.as_secs_f32());
+        }
+        Ok(client)
+    }
+}
+
+extern "C" fn free_client_callbacks(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut ClientCallbacks);
+            }
+        }
+    });
+}
+
+fn with_callbacks(client: mk_tcp_client, f: impl FnOnce(&mut ClientCallbacks, &TcpClient)) {
+    let user_data = unsafe { mk_tcp_client_get_user_data(client) };
+    if !user_data.is_null() {
+        let callbacks = unsafe { &mut *(user_data as *mut ClientCallbacks) };
+        f(callbacks, &TcpClient(client, false));
+    }
+}
+
+extern "C" fn on_mk_tcp_client_connect(
+    client: mk_tcp_client,
+    code: ::std::os::raw::c_int,
+    msg: *const ::std::os::raw::c_char,
+) {
+    crate::ffi_guard(|| {
+        with_callbacks(client, |cbs, client| {
+            if let Some(cb) = cbs.on_connect.as_mut() {
+                let result = match code {
+                    0 => Ok(()),
+                    _ => Err(anyhow::anyhow!(
+                        "connect failed: {} ({})",
+                        unsafe { const_ptr_to_string!(msg) },
+                        code
+                    )),
+                };
+                cb(client, result);
+            }
+        })
+    });
+}
+
+extern "C" fn on_mk_tcp_client_data(client: mk_tcp_client, buffer: mk_buffer) {
+    crate::ffi_guard(|| {
+        with_callbacks(client, |cbs, client| {
+            if let Some(cb) = cbs.on_data.as_mut() {
+                cb(client, unsafe { buffer_as_slice(buffer) });
+            }
+        })
+    });
+}
+
+extern "C" fn on_mk_tcp_client_disconnect(
+    client: mk_tcp_client,
+    code: ::std::os::raw::c_int,
+    msg: *const ::std::os::raw::c_char,
+) {
+    crate::ffi_guard(|| {
+        with_callbacks(client, |cbs, client| {
+            if let Some(cb) = cbs.on_err.as_mut() {
+                cb(client, code, unsafe { const_ptr_to_string!(msg) });
+            }
+        })
+    });
+}
+
+type OnRecvFn = Arc<dyn Fn(&[u8], SocketAddr) + Send + Sync + 'static>;
+type OnUdpErrorFn = Arc<dyn Fn(io::Error) + Send + Sync + 'static>;
+
+/// A udp socket whose datagrams are handed to a ZLMediaKit poller thread.
+///
+/// Unlike the tcp types, this socket is **not** on ZLMediaKit's event loop:
+/// the C API has no udp socket, so it is a std socket read by a dedicated
+/// helper thread, and every datagram is copied and posted to
+/// [`poller`](Self::poller) with [`EventPoller::spawn`]. Callbacks still run
+/// on the poller, but each datagram costs an allocation and a thread hop.
+/// Receiving stops when the socket is dropped or on a fatal error.
+pub struct UdpSocket {
+    socket: Arc<std::net::UdpSocket>,
+    poller: EventPoller,
+    on_recv: Arc<RwLock<Option<OnRecvFn>>>,
+    on_error: Arc<RwLock<Option<OnUdpErrorFn>>>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl UdpSocket {
+    /// Binds `addr` and delivers to a poller picked from the pool.
+    pub fn bind(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        Self::bind_on(addr, EventPoller::from_pool())
+    }
+
+    /// Binds `addr` and delivers to `poller`, e.g. the one owning a session
+    /// or media source the datagrams relate to.
+    pub fn bind_on(addr: impl ToSocketAddrs, poller: EventPoller) -> anyhow::Result<Self> {
+        let socket = Arc::new(std::net::UdpSocket::bind(addr)?);
+        // bounded so the reader notices `stop`
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let on_recv: Arc<RwLock<Option<OnRecvFn>>> = Default::default();
+        let on_error: Arc<RwLock<Option<OnUdpErrorFn>>> = Default::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let reader = std::thread::Builder::new()
+            .name(format!("udp-{}", socket.local_addr()?.port()))
+            .spawn({
+                let (socket, on_recv, on_error, stop) = (
+                    socket.clone(),
+                    on_recv.clone(),
+                    on_error.clone(),
+                    stop.clone(),
+                );
+                move || {
+                    let mut buf = vec![0u8; 64 * 1024];
+                    while !stop.load(Ordering::Relaxed) {
+                        let (len, peer) = match socket.recv_from(&mut buf) {
+                            Ok(received) => received,
+                            // timeouts, and ICMP errors of earlier sends
+                            // (reported as resets or refusals on some systems)
+                            Err(e)
+                                if matches!(
+                                    e.kind(),
+                                    io::ErrorKind::WouldBlock
+                                        | io::ErrorKind::TimedOut
+                                        | io::ErrorKind::Interrupted
+                                        | io::ErrorKind::ConnectionReset
+                                        | io::ErrorKind::ConnectionRefused
+                                ) =>
+                            {
+                                continue
+                            }
+                            Err(e) => {
+                                let cb = on_error.read().unwrap().clone();
+                                if let Some(cb) = cb {
+                                    poller.spawn(move || cb(e));
+                                }
+                                break;
+                            }
+                        };
+                        if stop.load(Ordering::Relaxed) {
+                            // the wake-up datagram of drop
+                            break;
+                        }
+                        let cb = on_recv.read().unwrap().clone();
+                        if let Some(cb) = cb {
+                            let data = buf[..len].to_vec();
+                            poller.spawn(move || cb(&data, peer));
+                        }
+                    }
+                }
+            })?;
+
+        Ok(UdpSocket {
+            socket,
+            poller,
+            on_recv,
+            on_error,
+            stop,
+            reader: Some(reader),
+        })
+    }
+
+    /// Sets the receive callback; datagrams arriving before it is set are dropped.
+    pub fn on_recv(&self, cb: impl Fn(&[u8], SocketAddr) + Send + Sync + 'static) {
+        *self.on_recv.write().unwrap() = Some(Arc::new(cb));
+    }
+
+    /// Called on the poller when receiving stopped on a fatal error.
+    pub fn on_error(&self, cb: impl Fn(io::Error) + Send + Sync + 'static) {
+        *self.on_error.write().unwrap() = Some(Arc::new(cb));
+    }
+
+    pub fn send_to(&self, data: &[u8], addr: impl ToSocketAddrs) -> io::Result<usize> {
+        self.socket.send_to(data, addr)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// The poller receive callbacks run on.
+    pub fn poller(&self) -> EventPoller {
+        self.poller
+    }
+
+    /// Joins multicast `group` on the default interface.
+    pub fn join_multicast(&self, group: IpAddr) -> io::Result<()> {
+        match group {
+            IpAddr::V4(group) => self
+                .socket
+                .join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(group) => self.socket.join_multicast_v6(&group, 0),
+        }
+    }
+
+    pub fn leave_multicast(&self, group: IpAddr) -> io::Result<()> {
+        match group {
+            IpAddr::V4(group) => self
+                .socket
+                .leave_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(group) => self.socket.leave_multicast_v6(&group, 0),
+        }
+    }
+
+    pub fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        self.socket.set_broadcast(broadcast)
+    }
+
+    /// TTL of outgoing IPv4 multicast; fails with `Unsupported` on IPv6
+    /// sockets, where std offers no hop limit setting.
+    pub fn set_multicast_ttl(&self, ttl: u32) -> io::Result<()> {
+        if self.socket.local_addr()?.is_ipv6() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "multicast ttl is IPv4 only",
+            ));
+        }
+        self.socket.set_multicast_ttl_v4(ttl)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // wake the reader rather than waiting for its read timeout
+        if let Ok(mut addr) = self.socket.local_addr() {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = self.socket.send_to(&[], addr);
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
@@ -7,7 +7,9 @@ use std::{
 
 use rszlm_sys::*;
 
-use crate::{box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr};
+use crate::{
+    box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr, frame::Frame, poller::EventPoller,
+};
 
 #[derive(Debug)]
 pub struct SockInfo(mk_sock_info);
@@ -86,6 +88,10 @@ impl From<mk_media_info> for MediaInfo {
 pub struct MediaSource(mk_media_source);
 
 impl MediaSource {
+    pub fn vhost(&self) -> String {
+        unsafe { const_ptr_to_string!(mk_media_source_get_vhost(self.0)) }
+    }
+
     pub fn schema(&self) -> String {
         unsafe { const_ptr_to_string!(mk_media_source_get_schema(self.0)) }
     }
@@ -121,6 +127,76 @@ impl MediaSource {
         }
     }
 
+    /// The first video track, if the source has one.
+    pub fn video_track(&self) -> Option<Track> {
+        (0..self.track_count())
+            .filter_map(|index| self.get_track(index))
+            .find(|track| track.is_video())
+    }
+
+    /// Looks up a registered source and runs `f` on it while ZLMediaKit keeps
+    /// it alive. `None` if there is no such source.
+    pub fn find<R, F>(schema: &str, vhost: &str, app: &str, stream: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&MediaSource) -> R,
+    {
+        struct Find<F, R>(Option<F>, Option<R>);
+
+        extern "C" fn on_find<F: FnOnce(&MediaSource) -> R, R>(
+            user_data: *mut ::std::os::raw::c_void,
+            source: mk_media_source,
+        ) {
+            crate::ffi_guard(|| {
+                if !source.is_null() {
+                    let find = unsafe { &mut *(user_data as *mut Find<F, R>) };
+                    if let Some(f) = find.0.take() {
+                        find.1 = Some(f(&MediaSource(source)));
+                    }
+                }
+            });
+        }
+
+        let mut find = Find(Some(f), None);
+        let (schema, vhost, app, stream) = (
+            const_str_to_ptr!(schema),
+            const_str_to_ptr!(vhost),
+            const_str_to_ptr!(app),
+            const_str_to_ptr!(stream),
+        );
+        unsafe {
+            // the callback runs synchronously, before mk_media_source_find returns
+            mk_media_source_find(
+                schema.as_ptr(),
+                vhost.as_ptr(),
+                app.as_ptr(),
+                stream.as_ptr(),
+                0,
+                &mut find as *mut Find<F, R> as *mut _,
+                Some(on_find::<F, R>),
+            )
+        };
+        find.1
+    }
+
+    /// [`find`](Self::find) over the rtsp, rtmp, ts and fmp4 muxers, in that order.
+    pub fn find_any<R>(
+        vhost: &str,
+        app: &str,
+        stream: &str,
+        f: impl FnOnce(&MediaSource) -> R,
+    ) -> Option<R> {
+        let mut f = Some(f);
+        for schema in ["rtsp", "rtmp", "ts", "fmp4"] {
+            let found = Self::find(schema, vhost, app, stream, |source| {
+                f.take().map(|f| f(source))
+            });
+            if let Some(result) = found {
+                return result;
+            }
+        }
+        None
+    }
+
     pub fn close(&self, force: bool) -> bool {
         match unsafe { mk_media_source_close(self.0, force as i32) } {
             1 => true,
@@ -128,6 +204,11 @@ impl MediaSource {
         }
     }
 
+    /// The poller thread that owns this source.
+    pub fn poller(&self) -> EventPoller {
+        unsafe { mk_media_source_get_owner_thread(self.0) }.into()
+    }
+
     pub(crate) fn inner(&self) -> mk_media_source {
         self.0
     }
@@ -172,6 +253,10 @@ impl Track {
         unsafe { mk_track_codec_id(self.0) }
     }
 
+    pub fn codec_id(&self) -> anyhow::Result<CodecId> {
+        CodecId::try_from(self.get_codec_id())
+    }
+
     pub fn get_codec_name(&self) -> String {
         unsafe { const_ptr_to_string!(mk_track_codec_name(self.0)) }
     }
@@ -208,11 +293,64 @@ impl Track {
         unsafe { mk_track_audio_sample_bit(self.0) }
     }
 
+    /// Calls `cb` with every frame passing through the track, on the thread
+    /// that feeds it, until [`del_delegate`](Self::del_delegate).
+    pub fn add_delegate(&self, cb: impl FnMut(&Frame) + Send + 'static) -> TrackDelegate {
+        let cb: OnTrackFrameFn = Box::new(cb);
+        TrackDelegate(unsafe {
+            mk_track_add_delegate2(
+                self.0,
+                Some(on_track_frame_out),
+                box_to_mut_void_ptr!(cb),
+                Some(free_track_frame_cb),
+            )
+        })
+    }
+
+    pub fn del_delegate(&self, delegate: TrackDelegate) {
+        unsafe { mk_track_del_delegate(self.0, delegate.0) }
+    }
+
     pub(crate) fn inner(&self) -> mk_track {
         self.0
     }
 }
 
+impl Clone for Track {
+    fn clone(&self) -> Self {
+        Self(unsafe { mk_track_ref(self.0) })
+    }
+}
+
+unsafe impl Send for Track {}
+unsafe impl Sync for Track {}
+
+/// Tag of a frame delegate added by [`Track::add_delegate`].
+#[derive(Debug)]
+pub struct TrackDelegate(*mut ::std::os::raw::c_void);
+
+unsafe impl Send for TrackDelegate {}
+unsafe impl Sync for TrackDelegate {}
+
+type OnTrackFrameFn = Box<dyn FnMut(&Frame) + Send + 'static>;
+
+extern "C" fn free_track_frame_cb(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut OnTrackFrameFn);
+            }
+        }
+    });
+}
+
+extern "C" fn on_track_frame_out(user_data: *mut ::std::os::raw::c_void, frame: mk_frame) {
+    crate::ffi_guard(|| {
+        let cb = unsafe { &mut *(user_data as *mut OnTrackFrameFn) };
+        cb(&Frame::from_borrowed(frame));
+    });
+}
+
 impl Drop for Track {
     fn drop(&mut self) {
         unsafe { mk_track_unref(self.0) }
@@ -357,71 +495,120 @@ impl From<mk_parser> for Parser {
     }
 }
 
-pub enum CodecId {
-    // 0
-    H264,
-    // 1
-    H265,
-    // 2
-    AAC,
-    // 3
-    G711A,
-    // 4
-    G711U,
-    // 5
-    Opus,
-    // 6
-    L16,
-    // 7
-    VP8,
-    // 8
-    VP9,
-    // 9
-    AV1,
-    // 10
-    JPEG,
-}
-
-/// i do not why
-/// in msvc, 0 is H264, 1 is H265
-/// unsafe { MKCodecH264 } will build error with:
-///     error LNK2019: unresolved external symbol MKCodecH264
-///     error LNK2019: unresolved external symbol MKCodecH265
-#[cfg(target_env = "msvc")]
-impl Into<i32> for CodecId {
-    fn into(self) -> i32 {
-        match self {
-            CodecId::H264 => 0,
-            CodecId::H265 => 1,
-            CodecId::AAC => 2,
-            CodecId::G711A => 3,
-            CodecId::G711U => 4,
-            CodecId::Opus => 5,
-            CodecId::L16 => 6,
-            CodecId::VP8 => 7,
-            CodecId::VP9 => 8,
-            CodecId::AV1 => 9,
-            CodecId::JPEG => 10,
+macro_rules! codec_ids {
+    ($($variant:ident = $value:literal, $name:literal, $kind:ident;)*) => {
+        /// ZLMediaKit's `CodecId`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum CodecId {
+            $(
+                #[doc = concat!("`", $name, "`, ", stringify!($value))]
+                $variant,
+            )*
+        }
+
+        impl CodecId {
+            pub const ALL: &'static [CodecId] = &[$(CodecId::$variant),*];
+
+            /// Name as returned by `mk_track_codec_name`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(CodecId::$variant => $name,)*
+                }
+            }
+
+            fn kind(&self) -> CodecKind {
+                match self {
+                    $(CodecId::$variant => CodecKind::$kind,)*
+                }
+            }
         }
+
+        /// The values of ZLMediaKit's `CodecId` enum (the `MKCodec*` exports)
+        /// are stable, so they are used directly; reading the exports also
+        /// fails to link on MSVC.
+        impl From<CodecId> for i32 {
+            fn from(value: CodecId) -> Self {
+                match value {
+                    $(CodecId::$variant => $value,)*
+                }
+            }
+        }
+
+        impl TryFrom<i32> for CodecId {
+            type Error = anyhow::Error;
+
+            fn try_from(value: i32) -> anyhow::Result<Self> {
+                match value {
+                    $($value => Ok(CodecId::$variant),)*
+                    _ => anyhow::bail!("unknown codec id {}", value),
+                }
+            }
+        }
+    };
+}
+
+enum CodecKind {
+    Video,
+    Audio,
+    /// Muxed streams passed through as a whole.
+    Container,
+}
+
+codec_ids! {
+    H264 = 0, "H264", Video;
+    H265 = 1, "H265", Video;
+    AAC = 2, "mpeg4-generic", Audio;
+    G711A = 3, "PCMA", Audio;
+    G711U = 4, "PCMU", Audio;
+    Opus = 5, "opus", Audio;
+    L16 = 6, "L16", Audio;
+    VP8 = 7, "VP8", Video;
+    VP9 = 8, "VP9", Video;
+    AV1 = 9, "AV1", Video;
+    JPEG = 10, "JPEG", Video;
+    H266 = 11, "H266", Video;
+    TS = 12, "MP2T", Container;
+    PS = 13, "MPEG", Container;
+    MP3 = 14, "MP3", Audio;
+    ADPCM = 15, "ADPCM", Audio;
+    SVACV = 16, "SVACV", Video;
+    SVACA = 17, "SVACA", Audio;
+    G722 = 18, "G722", Audio;
+    G723 = 19, "G723", Audio;
+    G728 = 20, "G728", Audio;
+    G729 = 21, "G729", Audio;
+}
+
+impl CodecId {
+    pub fn is_video(&self) -> bool {
+        matches!(self.kind(), CodecKind::Video)
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self.kind(), CodecKind::Audio)
     }
 }
 
-#[cfg(not(target_env = "msvc"))]
-impl Into<i32> for CodecId {
-    fn into(self) -> i32 {
-        match self {
-            CodecId::H264 => unsafe { MKCodecH264 },
-            CodecId::H265 => unsafe { MKCodecH265 },
-            CodecId::VP8 => unsafe { MKCodecVP8 },
-            CodecId::VP9 => unsafe { MKCodecVP9 },
-            CodecId::AV1 => unsafe { MKCodecAV1 },
-            CodecId::JPEG => unsafe { MKCodecJPEG },
-            CodecId::AAC => unsafe { MKCodecAAC },
-            CodecId::G711A => unsafe { MKCodecG711A },
-            CodecId::G711U => unsafe { MKCodecG711U },
-            CodecId::Opus => unsafe { MKCodecOpus },
-            CodecId::L16 => unsafe { MKCodecL16 },
-        }
+impl std::fmt::Display for CodecId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Accepts the [`name`](CodecId::name) and the variant name, ignoring case
+/// (`"mpeg4-generic"` and `"aac"` both give [`CodecId::AAC`]).
+impl std::str::FromStr for CodecId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        CodecId::ALL
+            .iter()
+            .find(|codec| {
+                codec.name().eq_ignore_ascii_case(s)
+                    || format!("{:?}", codec).eq_ignore_ascii_case(s)
+            })
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unknown codec {}", s))
     }
 }
 
@@ -472,7 +659,28 @@ impl RecordInfo {
     }
 }
 
-pub struct RtcTransport(mk_rtc_transport);
+/// Stable identity of a WebRTC transport.
+///
+/// ZLMediaKit hands the same `mk_rtc_transport` pointer to every SCTP event of
+/// one session, so its address identifies the session from `Connecting` until
+/// `Closed`. Ids may be reused by a later session once the transport is freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RtcTransportId(usize);
+
+impl std::fmt::Display for RtcTransportId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// Borrowed handle to a WebRTC transport, lent to an SCTP event handler for
+/// the duration of the call. Keep a [`DataChannel`](crate::webrtc::DataChannel)
+/// to send later.
+///
+/// ZLMediaKit's C API does not tell which app/stream a transport serves; see
+/// [`DataChannel::bind`](crate::webrtc::DataChannel::bind).
+#[derive(Debug)]
+pub struct RtcTransport(pub(crate) mk_rtc_transport);
 
 impl From<mk_rtc_transport> for RtcTransport {
     fn from(value: mk_rtc_transport) -> Self {
@@ -481,6 +689,10 @@ impl From<mk_rtc_transport> for RtcTransport {
 }
 
 impl RtcTransport {
+    pub fn id(&self) -> RtcTransportId {
+        RtcTransportId(self.0 as usize)
+    }
+
     pub fn send_datachannel(&self, data: &[u8], sid: u16, ppid: u32) {
         unsafe {
             mk_rtc_send_datachannel(
@@ -1,14 +1,29 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
 use rszlm_sys::*;
 
-use crate::{box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr, init::EnvIni};
+use crate::{
+    box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr, frame::Frame, init::EnvIni,
+    mux::Tap,
+};
 
-pub struct ProxyPlayer(mk_proxy_player);
+pub struct ProxyPlayer(mk_proxy_player, ProxySource);
+
+/// What a proxy player publishes, and how often it was played.
+#[derive(Debug, Default)]
+struct ProxySource {
+    app: String,
+    stream: String,
+    plays: AtomicU32,
+}
 
 impl From<mk_proxy_player> for ProxyPlayer {
     fn from(sender: mk_proxy_player) -> Self {
-        ProxyPlayer(sender)
+        ProxyPlayer(sender, ProxySource::default())
     }
 }
 
@@ -22,6 +37,24 @@ impl ProxyPlayer {
     pub fn play(&self, url: &str) {
         let url = const_str_to_ptr!(url);
         unsafe { mk_proxy_player_play(self.0, url.as_ptr()) };
+        if self.1.plays.fetch_add(1, Ordering::Relaxed) > 0 {
+            #[cfg(feature = "metrics")]
+            crate::metrics::on_proxy_restart(&self.1.app, &self.1.stream);
+        }
+    }
+
+    pub fn app(&self) -> &str {
+        &self.1.app
+    }
+
+    pub fn stream(&self) -> &str {
+        &self.1.stream
+    }
+
+    /// How often [`play`](Self::play) was called again after the first time,
+    /// e.g. from an `on_close` handler restarting the pull.
+    pub fn restarts(&self) -> u32 {
+        self.1.plays.load(Ordering::Relaxed).saturating_sub(1)
     }
 
     pub fn total_reader_count(&self) -> i32 {
@@ -118,18 +151,23 @@ impl ProxyPlayerBuilder {
     }
 
     pub fn build(self) -> ProxyPlayer {
-        let tmp = ProxyPlayer(unsafe {
-            let vhost = const_str_to_ptr!(self.vhost);
-            let app = const_str_to_ptr!(self.app);
-            let stream = const_str_to_ptr!(self.stream);
-            mk_proxy_player_create(
-                vhost.as_ptr(),
-                app.as_ptr(),
-                stream.as_ptr(),
-                self.hls_enabled as i32,
-                self.mp4_enabled as i32,
-            )
-        });
+        let mut tmp = ProxyPlayer(
+            unsafe {
+                let vhost = const_str_to_ptr!(self.vhost);
+                let app = const_str_to_ptr!(self.app);
+                let stream = const_str_to_ptr!(self.stream);
+                mk_proxy_player_create(
+                    vhost.as_ptr(),
+                    app.as_ptr(),
+                    stream.as_ptr(),
+                    self.hls_enabled as i32,
+                    self.mp4_enabled as i32,
+                )
+            },
+            ProxySource::default(),
+        );
+        tmp.1.app = self.app;
+        tmp.1.stream = self.stream;
 
         if !self.options.is_empty() {
             for (key, val) in self.options {
@@ -202,3 +240,63 @@ impl Mp4ProxyPlayer {
         }
     }
 }
+
+/// App of the helper streams [`HelperPull`]s play into.
+const INPUT_APP: &str = "__input";
+
+/// Helper streams started so far, for unique names.
+static HELPERS: AtomicU64 = AtomicU64::new(0);
+
+/// A URL pulled into a helper stream and tapped from there, for inputs
+/// whose frames are re-stamped before they are published.
+pub(crate) struct HelperPull {
+    // dropped before the player
+    tap: Option<Tap>,
+    _player: ProxyPlayer,
+    vhost: String,
+    stream: String,
+    started: Instant,
+}
+
+impl HelperPull {
+    /// Pulls `url` into a new helper stream named after `name`; `on_close`
+    /// runs on a ZLMediaKit thread when the pull ends.
+    pub(crate) fn start(
+        vhost: &str,
+        name: &str,
+        url: &str,
+        on_close: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        let stream = format!("{}-{}", name, HELPERS.fetch_add(1, Ordering::Relaxed) + 1);
+        let player = ProxyPlayerBuilder::new()
+            .vhost(vhost)
+            .app(INPUT_APP)
+            .stream(&stream)
+            .build();
+        player.on_close(move |_, _, _| on_close());
+        player.play(url);
+        Self {
+            tap: None,
+            _player: player,
+            vhost: vhost.to_string(),
+            stream,
+            started: Instant::now(),
+        }
+    }
+
+    /// Taps the helper stream if it is registered by now.
+    pub(crate) fn attach(&mut self, f: impl FnMut(&Frame) + Clone + Send + 'static) {
+        if self.tap.is_none() {
+            self.tap = Tap::find(&self.vhost, INPUT_APP, &self.stream, f);
+        }
+    }
+
+    pub(crate) fn is_attached(&self) -> bool {
+        self.tap.is_some()
+    }
+
+    /// Time since the pull started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
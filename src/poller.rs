@@ -0,0 +1,215 @@
+//! ZLMediaKit's event loops (`EventPoller`) and timers.
+//!
+//! Every ZLMediaKit object belongs to one poller thread and most `mk_*` calls
+//! on it must be made from that thread. Event callbacks already run there;
+//! anything else should hop over with [`EventPoller::spawn`] or
+//! [`EventPoller::run_sync`].
+
+use std::time::Duration;
+
+use rszlm_sys::*;
+
+use crate::box_to_mut_void_ptr;
+
+/// Handle to a ZLMediaKit poller thread. Pollers live as long as the process,
+/// so the handle is freely copyable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventPoller(mk_thread);
+
+unsafe impl Send for EventPoller {}
+unsafe impl Sync for EventPoller {}
+
+impl From<mk_thread> for EventPoller {
+    fn from(inner: mk_thread) -> Self {
+        EventPoller(inner)
+    }
+}
+
+impl EventPoller {
+    /// Picks the least loaded network poller.
+    pub fn from_pool() -> Self {
+        EventPoller(unsafe { mk_thread_from_pool() })
+    }
+
+    /// Picks a poller of the background work pool, for blocking or CPU heavy jobs.
+    pub fn from_pool_work() -> Self {
+        EventPoller(unsafe { mk_thread_from_pool_work() })
+    }
+
+    /// The poller that owns `session`.
+    ///
+    /// # Safety
+    ///
+    /// `session` must be a live session handle, e.g. one passed to a tcp
+    /// server callback.
+    pub unsafe fn from_tcp_session(session: mk_tcp_session) -> Self {
+        EventPoller(mk_thread_from_tcp_session(session))
+    }
+
+    pub(crate) fn as_raw(&self) -> mk_thread {
+        self.0
+    }
+
+    /// Runs `f` on the poller thread, without waiting for it.
+    pub fn spawn(&self, f: impl FnOnce() + Send + 'static) {
+        let task: OnTaskFn = Some(Box::new(f));
+        unsafe {
+            mk_async_do2(
+                self.0,
+                Some(on_mk_task),
+                box_to_mut_void_ptr!(task),
+                Some(free_task),
+            )
+        }
+    }
+
+    /// Runs `f` on the poller thread after `delay`.
+    pub fn spawn_delay(&self, delay: Duration, f: impl FnOnce() + Send + 'static) {
+        let task: OnTaskFn = Some(Box::new(f));
+        unsafe {
+            mk_async_do_delay2(
+                self.0,
+                delay.as_millis() as usize,
+                Some(on_mk_task),
+                box_to_mut_void_ptr!(task),
+                Some(free_task),
+            )
+        }
+    }
+
+    /// Runs `f` on the poller thread and waits for its result. Called from
+    /// the poller thread itself, `f` runs inline.
+    ///
+    /// A panic in `f` is resumed on the calling thread.
+    pub fn run_sync<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        let mut task = SyncTask {
+            f: Some(f),
+            result: None,
+        };
+        unsafe {
+            mk_sync_do(
+                self.0,
+                Some(on_mk_sync_task::<T, F>),
+                &mut task as *mut SyncTask<T, F> as *mut _,
+            )
+        }
+        match task.result {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => panic!("poller did not run the task"),
+        }
+    }
+
+    /// Calls `f` on the poller thread every `interval` until it returns
+    /// `false` or the [`Timer`] is dropped.
+    pub fn timer(&self, interval: Duration, f: impl FnMut() -> bool + Send + 'static) -> Timer {
+        Timer::new(self, interval, f)
+    }
+}
+
+type OnTaskFn = Option<Box<dyn FnOnce() + Send + 'static>>;
+
+extern "C" fn free_task(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut OnTaskFn);
+            }
+        }
+    });
+}
+
+extern "C" fn on_mk_task(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| unsafe {
+        let task = &mut *(user_data as *mut OnTaskFn);
+        if let Some(f) = task.take() {
+            f();
+        }
+    });
+}
+
+struct SyncTask<T, F> {
+    f: Option<F>,
+    result: Option<std::thread::Result<T>>,
+}
+
+extern "C" fn on_mk_sync_task<T, F: FnOnce() -> T>(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| unsafe {
+        let task = &mut *(user_data as *mut SyncTask<T, F>);
+        if let Some(f) = task.f.take() {
+            // caught here rather than by ffi_guard so run_sync can rethrow it
+            task.result = Some(std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)));
+        }
+    });
+}
+
+/// A repeating timer on a poller thread, cancelled when dropped.
+pub struct Timer(mk_timer);
+
+unsafe impl Send for Timer {}
+unsafe impl Sync for Timer {}
+
+impl Timer {
+    /// Calls `f` on `poller` every `interval` until it returns `false` or the
+    /// timer is dropped.
+    pub fn new(
+        poller: &EventPoller,
+        interval: Duration,
+        f: impl FnMut() -> bool + Send + 'static,
+    ) -> Self {
+        let interval = (interval.as_millis() as u64).max(1);
+        let state = TimerState {
+            interval,
+            f: Box::new(f),
+        };
+        Timer(unsafe {
+            mk_timer_create2(
+                poller.as_raw(),
+                interval,
+                Some(on_mk_timer_tick),
+                box_to_mut_void_ptr!(state),
+                Some(free_timer_state),
+            )
+        })
+    }
+
+    /// Stops the timer; same as dropping it.
+    pub fn cancel(self) {}
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe { mk_timer_release(self.0) }
+    }
+}
+
+struct TimerState {
+    interval: u64,
+    f: Box<dyn FnMut() -> bool + Send + 'static>,
+}
+
+extern "C" fn free_timer_state(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut TimerState);
+            }
+        }
+    });
+}
+
+/// Returns the delay until the next tick, 0 stops the timer.
+extern "C" fn on_mk_timer_tick(user_data: *mut ::std::os::raw::c_void) -> u64 {
+    crate::ffi_guard(|| unsafe {
+        let state = &mut *(user_data as *mut TimerState);
+        if (state.f)() {
+            state.interval
+        } else {
+            0
+        }
+    })
+}
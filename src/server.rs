@@ -1,40 +1,287 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use rszlm_sys::*;
 
-use crate::{box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr};
+use crate::{
+    box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr,
+    init::{EnvIni, Runtime},
+};
 
-pub fn http_server_start(port: u16, ssl: bool) {
-    unsafe {
-        mk_http_server_start(port, ssl as i32);
+#[deprecated(note = "use `ServerSet`, which reports the bound port and start failures")]
+pub fn http_server_start(port: u16, ssl: bool) -> u16 {
+    unsafe { mk_http_server_start(port, ssl as i32) }
+}
+
+#[deprecated(note = "use `ServerSet`, which reports the bound port and start failures")]
+pub fn rtsp_server_start(port: u16, ssl: bool) -> u16 {
+    unsafe { mk_rtsp_server_start(port, ssl as i32) }
+}
+
+#[deprecated(note = "use `ServerSet`, which reports the bound port and start failures")]
+pub fn rtmp_server_start(port: u16, ssl: bool) -> u16 {
+    unsafe { mk_rtmp_server_start(port, ssl as i32) }
+}
+
+#[deprecated(note = "use `ServerSet`, which reports the bound port and start failures")]
+pub fn rtp_server_start(port: u16) -> u16 {
+    unsafe { mk_rtp_server_start(port) }
+}
+
+#[deprecated(note = "use `ServerSet`, which reports the bound port and start failures")]
+pub fn srt_server_start(port: u16) -> u16 {
+    unsafe { mk_srt_server_start(port) }
+}
+
+/// Starts ZLMediaKit's telnet debug shell (media list, threads, statistics, ...)
+/// and returns the bound port.
+///
+/// Logins go through [`Event::on_shell_login`](crate::event::Event::on_shell_login);
+/// without a handler every login is accepted, so only expose it on trusted
+/// interfaces or register one.
+pub fn shell_server_start(port: u16) -> anyhow::Result<u16> {
+    Runtime::ensure_initialized()?;
+    match unsafe { mk_shell_server_start(port) } {
+        0 => anyhow::bail!("failed to start shell server on port {}", port),
+        port => Ok(port),
     }
 }
 
-pub fn rtsp_server_start(port: u16, ssl: bool) {
+pub fn stop_all_server() {
     unsafe {
-        mk_rtsp_server_start(port, ssl as i32);
+        mk_stop_all_server();
     }
 }
 
-pub fn rtmp_server_start(port: u16, ssl: bool) {
-    unsafe {
-        mk_rtmp_server_start(port, ssl as i32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Http,
+    Rtsp,
+    Rtmp,
+    /// GB28181 / raw RTP over udp+tcp
+    Rtp,
+    Srt,
+    /// ZLMediaKit's telnet debug shell, see [`shell_server_start`]
+    Shell,
+    #[cfg(feature = "webrtc")]
+    Rtc,
+}
+
+impl Protocol {
+    pub fn supports_ssl(&self) -> bool {
+        matches!(self, Protocol::Http | Protocol::Rtsp | Protocol::Rtmp)
+    }
+
+    /// Starts the server, returning the bound port or 0 on failure.
+    fn start(&self, port: u16, ssl: bool) -> u16 {
+        unsafe {
+            match self {
+                Protocol::Http => mk_http_server_start(port, ssl as i32),
+                Protocol::Rtsp => mk_rtsp_server_start(port, ssl as i32),
+                Protocol::Rtmp => mk_rtmp_server_start(port, ssl as i32),
+                Protocol::Rtp => mk_rtp_server_start(port),
+                Protocol::Srt => mk_srt_server_start(port),
+                Protocol::Shell => mk_shell_server_start(port),
+                #[cfg(feature = "webrtc")]
+                Protocol::Rtc => mk_rtc_server_start(port),
+            }
+        }
     }
 }
 
-pub fn rtp_server_start(port: u16) {
-    unsafe {
-        mk_rtp_server_start(port);
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Protocol::Http => "http",
+            Protocol::Rtsp => "rtsp",
+            Protocol::Rtmp => "rtmp",
+            Protocol::Rtp => "rtp",
+            Protocol::Srt => "srt",
+            Protocol::Shell => "shell",
+            #[cfg(feature = "webrtc")]
+            Protocol::Rtc => "rtc",
+        };
+        f.write_str(name)
     }
 }
 
-pub fn srt_server_start(port: u16) {
-    unsafe {
-        mk_srt_server_start(port);
+/// Whether a [`RunningServers`] is alive; ZLMediaKit can only stop all
+/// servers at once, so there is at most one.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// One server started by [`ServerSet::start`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundServer {
+    pub protocol: Protocol,
+    pub ssl: bool,
+    /// The port that was asked for, 0 for an ephemeral port.
+    pub requested_port: u16,
+    /// The port the server actually listens on.
+    pub port: u16,
+}
+
+/// Builder for the set of protocol servers to run.
+///
+/// ```ignore
+/// let servers = ServerSet::new()
+///     .http(0)
+///     .rtsp(8554)
+///     .rtmp(1935)
+///     .start()?;
+/// let http_port = servers.port(Protocol::Http, false).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerSet {
+    servers: Vec<(Protocol, u16, bool)>,
+    bind: Option<IpAddr>,
+}
+
+impl ServerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a server; `port` 0 picks a free port, see [`RunningServers::port`].
+    pub fn add(mut self, protocol: Protocol, port: u16, ssl: bool) -> Self {
+        self.servers.push((protocol, port, ssl));
+        self
+    }
+
+    pub fn http(self, port: u16) -> Self {
+        self.add(Protocol::Http, port, false)
+    }
+
+    pub fn https(self, port: u16) -> Self {
+        self.add(Protocol::Http, port, true)
+    }
+
+    pub fn rtsp(self, port: u16) -> Self {
+        self.add(Protocol::Rtsp, port, false)
+    }
+
+    pub fn rtsps(self, port: u16) -> Self {
+        self.add(Protocol::Rtsp, port, true)
+    }
+
+    pub fn rtmp(self, port: u16) -> Self {
+        self.add(Protocol::Rtmp, port, false)
+    }
+
+    pub fn rtmps(self, port: u16) -> Self {
+        self.add(Protocol::Rtmp, port, true)
+    }
+
+    pub fn rtp(self, port: u16) -> Self {
+        self.add(Protocol::Rtp, port, false)
+    }
+
+    pub fn srt(self, port: u16) -> Self {
+        self.add(Protocol::Srt, port, false)
+    }
+
+    pub fn shell(self, port: u16) -> Self {
+        self.add(Protocol::Shell, port, false)
+    }
+
+    #[cfg(feature = "webrtc")]
+    pub fn rtc(self, port: u16) -> Self {
+        self.add(Protocol::Rtc, port, false)
+    }
+
+    /// Listen address of all servers, written to the global `general.listen_ip`
+    /// before they start. Defaults to all interfaces.
+    pub fn bind(mut self, addr: IpAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Starts every server in order.
+    ///
+    /// If one fails (port in use, missing certificate, ...) the servers
+    /// already started are stopped again and an error naming the failing
+    /// server is returned. Fails as well while another [`RunningServers`] is
+    /// alive, as ZLMediaKit cannot stop one set without the other.
+    pub fn start(self) -> anyhow::Result<RunningServers> {
+        Runtime::ensure_initialized()?;
+        for (protocol, port, ssl) in &self.servers {
+            anyhow::ensure!(
+                !*ssl || protocol.supports_ssl(),
+                "{} server on port {} does not support ssl",
+                protocol,
+                port
+            );
+        }
+
+        if let Some(addr) = self.bind {
+            EnvIni::global()
+                .lock()
+                .unwrap()
+                .set_option("general.listen_ip", &addr.to_string());
+        }
+
+        if RUNNING.swap(true, Ordering::SeqCst) {
+            anyhow::bail!("servers are already running, drop the RunningServers first");
+        }
+        let mut running = RunningServers {
+            servers: Vec::with_capacity(self.servers.len()),
+        };
+        for (protocol, requested_port, ssl) in self.servers {
+            let port = protocol.start(requested_port, ssl);
+            if port == 0 {
+                // dropping `running` stops what was started so far
+                anyhow::bail!(
+                    "failed to start {}{} server on port {}",
+                    protocol,
+                    if ssl { " (ssl)" } else { "" },
+                    requested_port
+                );
+            }
+            running.servers.push(BoundServer {
+                protocol,
+                ssl,
+                requested_port,
+                port,
+            });
+        }
+        Ok(running)
     }
 }
 
-pub fn stop_all_server() {
-    unsafe {
-        mk_stop_all_server();
+/// Servers started by [`ServerSet::start`], with their bound ports; dropping
+/// it shuts them down.
+///
+/// ZLMediaKit only offers a global stop, so servers started through the
+/// deprecated `*_server_start` functions are stopped as well.
+#[must_use = "dropping RunningServers stops the servers"]
+#[derive(Debug)]
+pub struct RunningServers {
+    servers: Vec<BoundServer>,
+}
+
+impl RunningServers {
+    pub fn servers(&self) -> &[BoundServer] {
+        &self.servers
+    }
+
+    /// Bound port of the first server of the given protocol.
+    pub fn port(&self, protocol: Protocol, ssl: bool) -> Option<u16> {
+        self.servers
+            .iter()
+            .find(|s| s.protocol == protocol && s.ssl == ssl)
+            .map(|s| s.port)
+    }
+
+    /// Stops the servers now, same as dropping.
+    pub fn stop(self) {}
+}
+
+impl Drop for RunningServers {
+    fn drop(&mut self) {
+        stop_all_server();
+        RUNNING.store(false, Ordering::SeqCst);
     }
 }
 
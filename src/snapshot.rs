@@ -0,0 +1,291 @@
+//! Still images of live streams, e.g. thumbnails for stream listings.
+//!
+//! [`grab`] taps the video track of a registered stream and waits for the
+//! next keyframe. With the `transcode` feature it is decoded and returned as
+//! a JPEG. Without it (or if decoding fails) the result is that keyframe as
+//! delivered by ZLMediaKit: for H264/H265 an Annex-B access unit with its
+//! parameter sets in front, which any decoder (or `ffmpeg -i - -frames:v 1
+//! x.jpg`) accepts.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event::HttpRequestMessage,
+    frame::Frame,
+    obj::{CodecId, MediaSource},
+    DEFAULT_VHOST,
+};
+
+/// Format of [`grab`]'s result, sniffed from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Jpeg,
+    /// Encoded keyframe, see the module docs.
+    Keyframe,
+}
+
+impl SnapshotFormat {
+    pub fn of(data: &[u8]) -> Self {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            SnapshotFormat::Jpeg
+        } else {
+            SnapshotFormat::Keyframe
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Jpeg => "image/jpeg",
+            SnapshotFormat::Keyframe => "application/octet-stream",
+        }
+    }
+}
+
+/// Why [`grab`] found nothing to return; other errors come from decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// No such stream is registered.
+    NotFound(String),
+    /// The stream has no video track.
+    NoVideo(String),
+    /// No keyframe arrived within the timeout.
+    Timeout(Duration),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotFound(stream) => write!(f, "stream {} not found", stream),
+            SnapshotError::NoVideo(stream) => write!(f, "stream {} has no video", stream),
+            SnapshotError::Timeout(timeout) => write!(f, "no keyframe within {:?}", timeout),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Waits up to `timeout` for the next keyframe of `vhost/app/stream`.
+///
+/// Blocks the calling thread; never call it from a ZLMediaKit poller thread,
+/// which may be the one delivering the frames.
+pub fn grab(vhost: &str, app: &str, stream: &str, timeout: Duration) -> anyhow::Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let name = || format!("{}/{}/{}", vhost, app, stream);
+    let track = MediaSource::find_any(vhost, app, stream, |source| source.video_track())
+        .ok_or_else(|| SnapshotError::NotFound(name()))?
+        .ok_or_else(|| SnapshotError::NoVideo(name()))?;
+
+    let annexb = matches!(track.codec_id(), Ok(CodecId::H264 | CodecId::H265));
+    let (tx, rx) = mpsc::sync_channel(1);
+    let mut collector = KeyframeCollector::new(annexb);
+    let delegate = track.add_delegate(move |frame| {
+        if let Some(keyframe) = collector.push(frame) {
+            let _ = tx.try_send(keyframe);
+        }
+    });
+    let result = rx.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+    track.del_delegate(delegate);
+
+    let (keyframe, _frames) = result.map_err(|_| SnapshotError::Timeout(timeout))?;
+    #[cfg(feature = "transcode")]
+    if let Ok(jpeg) = to_jpeg(&track, &_frames) {
+        return Ok(jpeg);
+    }
+    Ok(keyframe)
+}
+
+#[cfg(feature = "transcode")]
+fn to_jpeg(track: &crate::obj::Track, frames: &[Frame]) -> anyhow::Result<Vec<u8>> {
+    use crate::transcode::{Decoder, PixelFormat, SwsContext};
+
+    let decoder = Decoder::new(track, 1);
+    let (tx, rx) = mpsc::channel();
+    decoder.on_frame(move |pix| {
+        let _ = tx.send(pix);
+    });
+    for frame in frames {
+        decoder.decode(frame, false, true);
+    }
+    decoder.flush();
+    let pix = rx
+        .try_recv()
+        .map_err(|_| anyhow::anyhow!("keyframe did not decode"))?;
+
+    let (width, height) = (pix.width(), pix.height());
+    let rgb = SwsContext::new(PixelFormat::Rgb24, width, height)
+        .scale(&pix)
+        .ok_or_else(|| anyhow::anyhow!("rgb conversion failed"))?;
+    let data = rgb
+        .plane_packed(0, width * 3)
+        .ok_or_else(|| anyhow::anyhow!("rgb conversion failed"))?;
+
+    let mut jpeg = Vec::new();
+    jpeg_encoder::Encoder::new(&mut jpeg, 85).encode(
+        &data,
+        u16::try_from(width)?,
+        u16::try_from(height)?,
+        jpeg_encoder::ColorType::Rgb,
+    )?;
+    Ok(jpeg)
+}
+
+/// Assembles the first keyframe, including all of its slices and the
+/// parameter sets sent right before it, as bytes and as the frames they
+/// came in (for decoding).
+struct KeyframeCollector {
+    annexb: bool,
+    config: Vec<Frame>,
+    last_was_config: bool,
+    keyframe: Option<(u64, Vec<Frame>)>,
+    done: bool,
+}
+
+impl KeyframeCollector {
+    fn new(annexb: bool) -> Self {
+        Self {
+            annexb,
+            config: Vec::new(),
+            last_was_config: false,
+            keyframe: None,
+            done: false,
+        }
+    }
+
+    /// Returns the keyframe once the frame after it arrives.
+    fn push(&mut self, frame: &Frame) -> Option<(Vec<u8>, Vec<Frame>)> {
+        if self.done {
+            return None;
+        }
+
+        let same_picture = matches!(&self.keyframe, Some((dts, _)) if *dts == frame.dts());
+        if self.keyframe.is_some() && !(frame.is_key_frame() && same_picture) {
+            return self.finish();
+        }
+
+        if frame.is_config_frame() {
+            if !self.last_was_config {
+                self.config.clear();
+            }
+            self.config.push(frame.clone());
+            self.last_was_config = true;
+            return None;
+        }
+        self.last_was_config = false;
+
+        if frame.is_key_frame() {
+            match &mut self.keyframe {
+                Some((_, frames)) => frames.push(frame.clone()),
+                None => {
+                    let mut frames = if self.annexb {
+                        std::mem::take(&mut self.config)
+                    } else {
+                        Vec::new()
+                    };
+                    frames.push(frame.clone());
+                    self.keyframe = Some((frame.dts(), frames));
+                    if !self.annexb {
+                        // one frame is one picture
+                        return self.finish();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Option<(Vec<u8>, Vec<Frame>)> {
+        self.done = true;
+        let (_, frames) = self.keyframe.take()?;
+        let data = frames
+            .iter()
+            .flat_map(|frame| frame.data())
+            .copied()
+            .collect();
+        Some((data, frames))
+    }
+}
+
+/// Grabs in flight from [`handle_http`], each on its own thread.
+static GRABS: AtomicUsize = AtomicUsize::new(0);
+
+/// More concurrent HTTP grabs than this are refused with 503.
+const MAX_GRABS: usize = 16;
+
+/// Answers `GET {prefix}/{app}/{stream}` with a snapshot. The vhost is taken
+/// from the `vhost` query parameter, the wait from `timeout` (seconds,
+/// default 5). Returns whether the request was consumed.
+///
+/// A missing stream (or one without video) is answered with 404, a timeout
+/// or too many concurrent grabs with 503, other failures with 500.
+///
+/// Each grab runs on its own thread, at most [`MAX_GRABS`] at a time, so
+/// this can be called from an `on_http_request` callback.
+pub fn handle_http(msg: &HttpRequestMessage, prefix: &str) -> bool {
+    let url = msg.parser.url();
+    let Some(path) = url.strip_prefix(prefix) else {
+        return false;
+    };
+    let Some((app, stream)) = path.trim_start_matches('/').split_once('/') else {
+        return false;
+    };
+    if app.is_empty() || stream.is_empty() || stream.contains('/') {
+        return false;
+    }
+
+    let (app, stream) = (app.to_string(), stream.to_string());
+    let vhost = match msg.parser.query("vhost") {
+        vhost if vhost.is_empty() => DEFAULT_VHOST.to_string(),
+        vhost => vhost,
+    };
+    let timeout = msg
+        .parser
+        .query("timeout")
+        .parse()
+        .map(Duration::from_secs_f32)
+        .unwrap_or(Duration::from_secs(5));
+    let invoker = msg.invoker.clone();
+
+    if GRABS.fetch_add(1, Ordering::AcqRel) >= MAX_GRABS {
+        GRABS.fetch_sub(1, Ordering::AcqRel);
+        invoker.invoke(503, vec![], "too many snapshot requests");
+        return true;
+    }
+    let spawned = std::thread::Builder::new()
+        .name("snapshot".to_string())
+        .spawn({
+            let invoker = invoker.clone();
+            move || {
+                match grab(&vhost, &app, &stream, timeout) {
+                    Ok(data) => {
+                        let content_type = SnapshotFormat::of(&data).content_type();
+                        invoker.invoke_bytes(
+                            200,
+                            vec!["Content-Type".to_string(), content_type.to_string()],
+                            &data,
+                        )
+                    }
+                    Err(e) => {
+                        let status = match e.downcast_ref::<SnapshotError>() {
+                            Some(SnapshotError::NotFound(_) | SnapshotError::NoVideo(_)) => 404,
+                            Some(SnapshotError::Timeout(_)) => 503,
+                            None => 500,
+                        };
+                        invoker.invoke(status, vec![], &e.to_string())
+                    }
+                }
+                GRABS.fetch_sub(1, Ordering::AcqRel);
+            }
+        });
+    if let Err(e) = spawned {
+        GRABS.fetch_sub(1, Ordering::AcqRel);
+        invoker.invoke(500, vec![], &e.to_string());
+    }
+    true
+}
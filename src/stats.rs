@@ -0,0 +1,205 @@
+//! Object counters of ZLMediaKit, via `mk_get_statistic`.
+//!
+//! A growing count that never falls back (e.g. `RtpPacket` or `Buffer` while
+//! traffic is stable) usually means a leak. The statistic is collected on
+//! ZLMediaKit's poller threads, so every entry point is asynchronous:
+//! [`snapshot`] returns a future, [`snapshot_with`] takes a callback and
+//! [`snapshot_blocking`] waits on the calling thread.
+//!
+//! The C API has no per-thread poller load query, so only object counts are
+//! available here.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    future::Future,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use rszlm_sys::*;
+
+use crate::{box_to_mut_void_ptr, config::parse_ini, init::Runtime};
+
+/// Live object counts, keyed by ZLMediaKit class name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub objects: BTreeMap<String, u64>,
+}
+
+macro_rules! statistic_getters {
+    ($($fn:ident => $name:literal),* $(,)?) => {
+        $(
+            #[doc = concat!("Live `", $name, "` objects.")]
+            pub fn $fn(&self) -> u64 {
+                self.get($name)
+            }
+        )*
+    };
+}
+
+impl Statistics {
+    /// Count of `name`, 0 if ZLMediaKit did not report it.
+    pub fn get(&self, name: &str) -> u64 {
+        self.objects.get(name).copied().unwrap_or_default()
+    }
+
+    statistic_getters! {
+        media_source => "MediaSource",
+        multi_media_source_muxer => "MultiMediaSourceMuxer",
+        tcp_server => "TcpServer",
+        tcp_session => "TcpSession",
+        udp_server => "UdpServer",
+        udp_session => "UdpSession",
+        tcp_client => "TcpClient",
+        socket => "Socket",
+        frame => "Frame",
+        frame_imp => "FrameImp",
+        buffer => "Buffer",
+        buffer_raw => "BufferRaw",
+        buffer_like_string => "BufferLikeString",
+        buffer_list => "BufferList",
+        rtp_packet => "RtpPacket",
+        rtmp_packet => "RtmpPacket",
+    }
+
+    /// Renders the counts in the Prometheus text exposition format as the
+    /// `zlm_objects{type="..."}` gauge.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP zlm_objects Live ZLMediaKit objects by type.\n");
+        out.push_str("# TYPE zlm_objects gauge\n");
+        for (name, count) in &self.objects {
+            let _ = writeln!(out, "zlm_objects{{type=\"{}\"}} {}", name, count);
+        }
+        out
+    }
+
+    fn from_ini(text: &str) -> Self {
+        let objects = parse_ini(text)
+            .into_iter()
+            .filter_map(|(key, value)| {
+                // values are plain counters; the optional memory debug entries are not
+                let count = value.parse().ok()?;
+                let name = key.rsplit('.').next().unwrap_or(&key).to_string();
+                Some((name, count))
+            })
+            .collect();
+        Self { objects }
+    }
+}
+
+type OnStatisticFn = Option<Box<dyn FnOnce(Statistics) + Send + 'static>>;
+
+/// Requests a statistic; `cb` runs on a ZLMediaKit poller thread. It is not
+/// called if ZLMediaKit drops the request (e.g. while shutting down).
+pub fn snapshot_with(cb: impl FnOnce(Statistics) + Send + 'static) -> anyhow::Result<()> {
+    Runtime::ensure_initialized()?;
+    let cb: OnStatisticFn = Some(Box::new(cb));
+    unsafe {
+        mk_get_statistic(
+            Some(on_mk_get_statistic),
+            box_to_mut_void_ptr!(cb),
+            Some(free_on_statistic_cb),
+        )
+    }
+    Ok(())
+}
+
+/// Requests a statistic and waits up to `timeout` for it.
+pub fn snapshot_blocking(timeout: Duration) -> anyhow::Result<Statistics> {
+    let (tx, rx) = mpsc::channel();
+    snapshot_with(move |stats| {
+        let _ = tx.send(stats);
+    })?;
+    rx.recv_timeout(timeout)
+        .map_err(|e| anyhow::anyhow!("statistic not received: {}", e))
+}
+
+/// Requests a statistic; the future resolves once ZLMediaKit has collected it,
+/// or to an error if the request was dropped.
+pub fn snapshot() -> StatisticsFuture {
+    let shared = Arc::new(Mutex::new(Pending::default()));
+    let completer = Completer(shared.clone());
+    if let Err(e) = snapshot_with(move |stats| completer.complete(stats)) {
+        let mut pending = shared.lock().unwrap();
+        pending.error = Some(e);
+        pending.done = true;
+    }
+    StatisticsFuture(shared)
+}
+
+#[derive(Default)]
+struct Pending {
+    result: Option<Statistics>,
+    error: Option<anyhow::Error>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Marks the request done when dropped, so a request ZLMediaKit never answers
+/// still wakes the future.
+struct Completer(Arc<Mutex<Pending>>);
+
+impl Completer {
+    fn complete(self, stats: Statistics) {
+        self.0.lock().unwrap().result = Some(stats);
+    }
+}
+
+impl Drop for Completer {
+    fn drop(&mut self) {
+        let waker = {
+            let mut pending = self.0.lock().unwrap();
+            pending.done = true;
+            pending.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by [`snapshot`].
+pub struct StatisticsFuture(Arc<Mutex<Pending>>);
+
+impl Future for StatisticsFuture {
+    type Output = anyhow::Result<Statistics>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut pending = self.0.lock().unwrap();
+        if !pending.done {
+            pending.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        if let Some(e) = pending.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        match pending.result.take() {
+            Some(stats) => Poll::Ready(Ok(stats)),
+            None => Poll::Ready(Err(anyhow::anyhow!("statistic request was dropped"))),
+        }
+    }
+}
+
+/// Frees the boxed callback when ZLMediaKit's shared_ptr deleter fires.
+extern "C" fn free_on_statistic_cb(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut OnStatisticFn);
+            }
+        }
+    });
+}
+
+extern "C" fn on_mk_get_statistic(user_data: *mut ::std::os::raw::c_void, ini: mk_ini) {
+    crate::ffi_guard(|| unsafe {
+        let cb = &mut *(user_data as *mut OnStatisticFn);
+        if let Some(cb) = cb.take() {
+            cb(Statistics::from_ini(&crate::init::dump_ini(ini)));
+        }
+    });
+}
@@ -0,0 +1,545 @@
+//! Timestamp cleanup for frames fed into [`Media`](crate::media::Media).
+//!
+//! [`Frame::new`](crate::frame::Frame::new) expects millisecond DTS/PTS that
+//! start near zero and never go back. [`Normalizer`] turns camera and
+//! pipeline clocks into that:
+//!
+//! - converts from the track's clock rate (90000 for video RTP/TS, the sample
+//!   rate for audio RTP, 1000 for milliseconds)
+//! - unwraps rollovers of 32 bit (RTP) or 33 bit (MPEG-TS) counters
+//! - rebases jumps larger than [`max_jump`](Normalizer::max_jump) and clamps
+//!   small backwards steps, so DTS stays monotonic
+//! - derives DTS from PTS for streams with B-frames that only carry PTS (RTP)
+//! - starts audio and video on a common time line
+//!
+//! ```ignore
+//! let media = Media::new(DEFAULT_VHOST, "live", "cam", 0.0, false, false);
+//! media.set_normalizer(Normalizer::new(90000, 8000).wrap_bits(32));
+//! // RTP timestamps straight from the packets
+//! media.input_data(CodecId::H264, None, Some(rtp_ts), &annexb);
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{frame::Frame, obj::CodecId};
+
+/// Which time line a timestamp belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+impl From<CodecId> for TrackKind {
+    fn from(codec: CodecId) -> Self {
+        if codec.is_video() {
+            TrackKind::Video
+        } else {
+            TrackKind::Audio
+        }
+    }
+}
+
+/// Normalized timestamps in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamps {
+    pub dts: u64,
+    pub pts: u64,
+}
+
+/// Up to how many frames B-frame detection looks back.
+const REORDER_WINDOW: usize = 16;
+
+/// Converts and repairs the timestamps of one audio and one video track.
+#[derive(Debug)]
+pub struct Normalizer {
+    video: TrackClock,
+    audio: TrackClock,
+    wrap_bits: u32,
+    max_jump: Duration,
+    shared_clock: bool,
+    /// Shared clock: milliseconds of the first timestamp of any track.
+    origin: Option<i64>,
+    started: Option<Instant>,
+}
+
+impl Normalizer {
+    /// `video_clock_rate` and `audio_clock_rate` are the input timestamp
+    /// units per second, e.g. `Normalizer::new(90000, 48000)` for RTP.
+    pub fn new(video_clock_rate: u32, audio_clock_rate: u32) -> Self {
+        Self {
+            video: TrackClock::new(video_clock_rate, 40),
+            audio: TrackClock::new(audio_clock_rate, 20),
+            wrap_bits: 64,
+            max_jump: Duration::from_secs(1),
+            shared_clock: false,
+            origin: None,
+            started: None,
+        }
+    }
+
+    /// Input timestamps are counters of this many bits that roll over:
+    /// 32 for RTP, 33 for MPEG-TS. Default 64, i.e. no rollover.
+    pub fn wrap_bits(mut self, bits: u32) -> Self {
+        self.wrap_bits = bits.clamp(1, 64);
+        self
+    }
+
+    /// Steps of DTS larger than this, forwards or backwards, are treated as
+    /// a discontinuity: the time line continues one frame after the last
+    /// output. Default one second.
+    pub fn max_jump(mut self, max_jump: Duration) -> Self {
+        self.max_jump = max_jump;
+        self
+    }
+
+    /// Audio and video timestamps come from the same clock (e.g. MPEG-TS, or
+    /// RTP with RTCP-derived times), so their offset is kept. Otherwise each
+    /// track starts at the time its first frame arrived.
+    pub fn shared_clock(mut self, shared: bool) -> Self {
+        self.shared_clock = shared;
+        self
+    }
+
+    /// Number of B-frames between references, if known (e.g. from the SPS).
+    /// Otherwise it is learnt from the PTS order, which may repeat a DTS
+    /// when the first B-frame shows up.
+    pub fn reorder_depth(mut self, depth: usize) -> Self {
+        self.video.reorder_depth = depth.min(REORDER_WINDOW);
+        self.video.reorder_fixed = true;
+        self
+    }
+
+    /// Normalizes one frame's timestamps. Give both DTS and PTS if the input
+    /// has them; with only PTS, DTS is derived for video; with only DTS, PTS
+    /// equals DTS; with neither, the frame follows the last one.
+    pub fn normalize(&mut self, kind: TrackKind, dts: Option<u64>, pts: Option<u64>) -> Timestamps {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let wrap_bits = self.wrap_bits;
+        let track = match kind {
+            TrackKind::Video => &mut self.video,
+            TrackKind::Audio => &mut self.audio,
+        };
+
+        let (dts, pts) = match (dts, pts) {
+            (Some(dts), Some(pts)) => (
+                track.unwrap_ms(dts, wrap_bits, 0),
+                track.unwrap_ms(pts, wrap_bits, 1),
+            ),
+            (Some(dts), None) => {
+                let dts = track.unwrap_ms(dts, wrap_bits, 0);
+                (dts, dts)
+            }
+            (None, Some(pts)) => {
+                let pts = track.unwrap_ms(pts, wrap_bits, 1);
+                let dts = match kind {
+                    TrackKind::Video => track.derive_dts(pts),
+                    TrackKind::Audio => pts,
+                };
+                (dts, pts)
+            }
+            (None, None) => {
+                // one frame after the last output, in input time
+                let dts = track.last_out.map_or(0, |last| last + track.duration) - track.offset;
+                (dts, dts)
+            }
+        };
+
+        if track.last_out.is_none() {
+            // the first frame of a track decides where it sits on the common
+            // time line
+            let start = if self.shared_clock {
+                let absolute = track.origin_ms + dts;
+                absolute - *self.origin.get_or_insert(absolute)
+            } else {
+                started.elapsed().as_millis() as i64
+            };
+            track.offset = start - dts;
+        }
+
+        track.fix(dts, pts, self.max_jump.as_millis() as i64)
+    }
+
+    /// Normalizes the timestamps and builds the frame.
+    pub fn frame(
+        &mut self,
+        codec: CodecId,
+        dts: Option<u64>,
+        pts: Option<u64>,
+        data: &[u8],
+    ) -> Frame {
+        let ts = self.normalize(codec.into(), dts, pts);
+        Frame::new(codec, ts.dts, ts.pts, data)
+    }
+}
+
+#[derive(Debug)]
+struct TrackClock {
+    clock_rate: u32,
+    /// Per input (0 DTS, 1 PTS): first raw value and last unwrapped value.
+    raw: [Option<(u64, i64)>; 2],
+    /// First raw timestamp in milliseconds, for [`Normalizer::shared_clock`].
+    origin_ms: i64,
+    offset: i64,
+    last_out: Option<i64>,
+    /// Last good frame duration in milliseconds.
+    duration: i64,
+    reorder_depth: usize,
+    reorder_fixed: bool,
+    recent_pts: VecDeque<i64>,
+    /// PTS values not yet handed out as DTS, with their counts.
+    pending_pts: BTreeMap<i64, usize>,
+    pending_len: usize,
+}
+
+impl TrackClock {
+    fn new(clock_rate: u32, duration: i64) -> Self {
+        Self {
+            clock_rate: clock_rate.max(1),
+            raw: [None, None],
+            origin_ms: 0,
+            offset: 0,
+            last_out: None,
+            duration,
+            reorder_depth: 0,
+            reorder_fixed: false,
+            recent_pts: VecDeque::new(),
+            pending_pts: BTreeMap::new(),
+            pending_len: 0,
+        }
+    }
+
+    /// Unwraps `value` and converts it to milliseconds since the first DTS
+    /// (or PTS) of this track.
+    fn unwrap_ms(&mut self, value: u64, wrap_bits: u32, index: usize) -> i64 {
+        let mask = if wrap_bits >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << wrap_bits) - 1
+        };
+        let value = value & mask;
+
+        // both inputs count from the same origin, so PTS - DTS survives
+        let origin = match self.raw[0].or(self.raw[1]) {
+            Some((first, _)) => first,
+            None => {
+                self.origin_ms = (value as i128 * 1000 / self.clock_rate as i128) as i64;
+                value
+            }
+        };
+        let unwrapped = match self.raw[index] {
+            None => wrap_diff(value, origin, mask, wrap_bits),
+            Some((_, last)) => {
+                let last_raw = (origin as i64).wrapping_add(last) as u64 & mask;
+                last + wrap_diff(value, last_raw, mask, wrap_bits)
+            }
+        };
+        self.raw[index] = Some((origin, unwrapped));
+        (unwrapped as i128 * 1000 / self.clock_rate as i128) as i64
+    }
+
+    /// Decode order DTS for a PTS-only video stream: the smallest of the last
+    /// `reorder_depth + 1` PTS values.
+    fn derive_dts(&mut self, pts: i64) -> i64 {
+        if !self.reorder_fixed {
+            let later = self.recent_pts.iter().filter(|&&seen| seen > pts).count();
+            self.reorder_depth = self.reorder_depth.max(later);
+            if self.recent_pts.len() == REORDER_WINDOW {
+                self.recent_pts.pop_front();
+            }
+            self.recent_pts.push_back(pts);
+        }
+
+        *self.pending_pts.entry(pts).or_default() += 1;
+        self.pending_len += 1;
+        if self.pending_len <= self.reorder_depth {
+            // still filling up; repeats the last DTS at worst
+            return self.pending_pts.keys().next().copied().unwrap_or(pts);
+        }
+        let (&dts, count) = self.pending_pts.iter_mut().next().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.pending_pts.remove(&dts);
+        }
+        self.pending_len -= 1;
+        dts
+    }
+
+    fn fix(&mut self, dts: i64, pts: i64, max_jump: i64) -> Timestamps {
+        let mut out = dts + self.offset;
+        if let Some(last) = self.last_out {
+            let step = out - last;
+            if step.abs() > max_jump {
+                // discontinuity: continue one frame after the last output
+                self.offset += last + self.duration - out;
+                out = last + self.duration;
+            } else if step < 0 {
+                out = last;
+            } else if step > 0 {
+                self.duration = step;
+            }
+        }
+        let out = out.max(0);
+        self.last_out = Some(out);
+        Timestamps {
+            dts: out as u64,
+            pts: (pts + self.offset).max(out) as u64,
+        }
+    }
+}
+
+/// `value - base` on a counter of `wrap_bits` bits, the shorter way round.
+fn wrap_diff(value: u64, base: u64, mask: u64, wrap_bits: u32) -> i64 {
+    let diff = value.wrapping_sub(base) & mask;
+    if wrap_bits >= 64 {
+        diff as i64
+    } else if diff > mask / 2 {
+        diff as i64 - (mask as i64 + 1)
+    } else {
+        diff as i64
+    }
+}
+
+/// How long (input time, ms) a switch waits for a video keyframe while the
+/// new input sends no video at all, e.g. an audio-only backup.
+const MAX_AUDIO_ONLY_WAIT: u64 = 3000;
+
+/// Joins the time lines of inputs switched between into one output time
+/// line, for frames that are already in milliseconds.
+///
+/// After [`switch`](Self::switch), frames are dropped up to the new input's
+/// first video keyframe (or config frame), which then continues where the
+/// previous input ended; an input that sends only audio for 3 seconds is
+/// taken as it is. DTS stays monotonic per track.
+#[derive(Debug)]
+pub struct Splicer {
+    video: bool,
+    /// Input to output time, set by the first frame after a switch.
+    offset: Option<i64>,
+    waiting_keyframe: bool,
+    /// Input DTS since which the new input has sent no video.
+    audio_only_since: Option<u64>,
+    /// Per track (video or not): last output DTS and frame duration.
+    last: HashMap<bool, (u64, u64)>,
+    /// Where the next input continues.
+    end: u64,
+}
+
+impl Splicer {
+    /// `video`: the output has a video track, so switches wait for a video
+    /// keyframe; otherwise they take effect at once.
+    pub fn new(video: bool) -> Self {
+        Self {
+            video,
+            offset: None,
+            waiting_keyframe: false,
+            audio_only_since: None,
+            last: HashMap::new(),
+            end: 0,
+        }
+    }
+
+    /// Following frames come from another input.
+    pub fn switch(&mut self) {
+        self.offset = None;
+        self.waiting_keyframe = true;
+        self.audio_only_since = None;
+    }
+
+    /// The current input starts over (a looped file): its time line is
+    /// joined again, without waiting for a keyframe.
+    pub fn rebase(&mut self) {
+        self.offset = None;
+    }
+
+    /// The current input jumps (a seek): its time line continues at
+    /// `position`, also backwards, without waiting for a keyframe.
+    pub fn jump(&mut self, position: u64) {
+        self.offset = None;
+        self.last.clear();
+        self.end = position;
+    }
+
+    /// Still dropping frames up to the first keyframe after a switch.
+    pub fn is_switching(&self) -> bool {
+        self.waiting_keyframe
+    }
+
+    /// Output timestamps of a frame, `None` if it is dropped.
+    pub fn splice(
+        &mut self,
+        codec: CodecId,
+        dts: u64,
+        pts: u64,
+        keyframe: bool,
+    ) -> Option<Timestamps> {
+        let video = codec.is_video();
+        if self.waiting_keyframe {
+            // audio of the new input waits for its first video keyframe too,
+            // unless there is no video in the output or the input
+            if self.video && !(video && keyframe) {
+                if video {
+                    self.audio_only_since = None;
+                    return None;
+                }
+                let since = *self.audio_only_since.get_or_insert(dts);
+                if dts.saturating_sub(since) < MAX_AUDIO_ONLY_WAIT {
+                    return None;
+                }
+            }
+            self.waiting_keyframe = false;
+        }
+
+        let offset = *self.offset.get_or_insert(self.end as i64 - dts as i64);
+        let mut out_dts = (dts as i64 + offset).max(0) as u64;
+        let duration = match self.last.get_mut(&video) {
+            Some((last_dts, duration)) => {
+                if out_dts > *last_dts && out_dts - *last_dts < 1000 {
+                    *duration = out_dts - *last_dts;
+                } else {
+                    // overlap or gap at a switch: keep going one step later
+                    out_dts = out_dts.max(*last_dts + 1);
+                }
+                *last_dts = out_dts;
+                *duration
+            }
+            None => {
+                let duration = if video { 40 } else { 20 };
+                self.last.insert(video, (out_dts, duration));
+                duration
+            }
+        };
+        self.end = self.end.max(out_dts + duration);
+        Some(Timestamps {
+            dts: out_dts,
+            pts: ((pts as i64 + offset).max(0) as u64).max(out_dts),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output DTS of video frames with DTS = PTS given in input units.
+    fn video_dts(normalizer: &mut Normalizer, input: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        input
+            .into_iter()
+            .map(|ts| {
+                normalizer
+                    .normalize(TrackKind::Video, Some(ts), Some(ts))
+                    .dts
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unwraps_32_bit_rollover() {
+        let mut normalizer = Normalizer::new(90000, 8000)
+            .wrap_bits(32)
+            .shared_clock(true);
+        // 40 ms steps, wrapping after the 13th frame
+        let start = (1u64 << 32) - 12 * 3600;
+        let input = (0..20).map(|i| (start + i * 3600) & 0xFFFF_FFFF);
+        let expected: Vec<u64> = (0..20).map(|i| i * 40).collect();
+        assert_eq!(video_dts(&mut normalizer, input), expected);
+    }
+
+    #[test]
+    fn unwraps_33_bit_rollover() {
+        let mut normalizer = Normalizer::new(90000, 8000)
+            .wrap_bits(33)
+            .shared_clock(true);
+        let start = (1u64 << 33) - 5 * 3600;
+        let input = (0..10).map(|i| (start + i * 3600) & 0x1_FFFF_FFFF);
+        let expected: Vec<u64> = (0..10).map(|i| i * 40).collect();
+        assert_eq!(video_dts(&mut normalizer, input), expected);
+    }
+
+    #[test]
+    fn clamps_backwards_steps() {
+        let mut normalizer = Normalizer::new(1000, 1000).shared_clock(true);
+        assert_eq!(
+            video_dts(&mut normalizer, [0, 40, 80, 60, 120]),
+            [0, 40, 80, 80, 120]
+        );
+    }
+
+    #[test]
+    fn rebases_large_jumps() {
+        let mut normalizer = Normalizer::new(1000, 1000).shared_clock(true);
+        // forwards: continues one frame after the last output
+        assert_eq!(
+            video_dts(&mut normalizer, [0, 40, 80, 10_080, 10_120]),
+            [0, 40, 80, 120, 160]
+        );
+        // backwards
+        assert_eq!(video_dts(&mut normalizer, [500, 540]), [200, 240]);
+    }
+
+    #[test]
+    fn derives_dts_from_pts() {
+        let mut normalizer = Normalizer::new(1000, 1000)
+            .shared_clock(true)
+            .reorder_depth(2);
+        // I0 P3 B1 B2 P6 B4 B5 in decode order
+        let pts = [0, 120, 40, 80, 240, 160, 200];
+        let out: Vec<Timestamps> = pts
+            .iter()
+            .map(|&pts| normalizer.normalize(TrackKind::Video, None, Some(pts)))
+            .collect();
+        let dts: Vec<u64> = out.iter().map(|ts| ts.dts).collect();
+        assert_eq!(dts, [0, 0, 0, 40, 80, 120, 160]);
+        for (ts, pts) in out.iter().zip(pts) {
+            assert_eq!(ts.pts, pts);
+            assert!(ts.dts <= ts.pts);
+        }
+    }
+
+    #[test]
+    fn splices_at_keyframe() {
+        let mut splicer = Splicer::new(true);
+        for dts in [0, 40, 80] {
+            let ts = splicer.splice(CodecId::H264, dts, dts, dts == 0).unwrap();
+            assert_eq!(ts.dts, dts);
+        }
+
+        splicer.switch();
+        assert!(splicer.is_switching());
+        assert_eq!(splicer.splice(CodecId::AAC, 5_000, 5_000, false), None);
+        assert_eq!(splicer.splice(CodecId::H264, 5_000, 5_000, false), None);
+        // continues where the first input ended
+        let ts = splicer.splice(CodecId::H264, 5_040, 5_080, true).unwrap();
+        assert_eq!(ts, Timestamps { dts: 120, pts: 160 });
+        assert!(!splicer.is_switching());
+        let ts = splicer.splice(CodecId::H264, 5_080, 5_080, false).unwrap();
+        assert_eq!(ts.dts, 160);
+        let ts = splicer.splice(CodecId::AAC, 5_060, 5_060, false).unwrap();
+        assert_eq!(ts.dts, 140);
+        // a frame from before the switch point stays monotonic per track
+        let ts = splicer.splice(CodecId::H264, 5_000, 5_000, false).unwrap();
+        assert_eq!(ts.dts, 161);
+    }
+
+    #[test]
+    fn takes_audio_only_input_after_waiting() {
+        let mut splicer = Splicer::new(true);
+        splicer.splice(CodecId::H264, 0, 0, true).unwrap();
+        splicer.switch();
+        for dts in (0..MAX_AUDIO_ONLY_WAIT).step_by(20) {
+            assert_eq!(splicer.splice(CodecId::AAC, dts, dts, false), None);
+        }
+        let ts = splicer
+            .splice(
+                CodecId::AAC,
+                MAX_AUDIO_ONLY_WAIT,
+                MAX_AUDIO_ONLY_WAIT,
+                false,
+            )
+            .unwrap();
+        assert_eq!(ts.dts, 40);
+    }
+}
@@ -0,0 +1,324 @@
+//! Decoding and pixel conversion (`transcode` feature).
+//!
+//! Needs a ZLMediaKit built with FFmpeg (`ENABLE_FFMPEG`), which is what the
+//! `feature_transcode2` prebuilt package is.
+//!
+//! ```ignore
+//! let decoder = Decoder::new(&track, 2);
+//! let rgb = SwsContext::new(PixelFormat::Rgb24, 640, 360);
+//! decoder.on_frame(move |pix| {
+//!     if let Some(small) = rgb.scale(&pix) {
+//!         infer(small.plane(0).unwrap(), small.linesize(0));
+//!     }
+//! });
+//! let delegate = track.add_delegate(move |frame| decoder.decode(frame, true, true));
+//! ```
+
+use std::ffi::CString;
+
+use rszlm_sys::*;
+
+use crate::{box_to_mut_void_ptr, frame::Frame, obj::Track};
+
+/// FFmpeg's `AVPixelFormat`, for the formats worth naming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    Yuv420p,
+    Yuyv422,
+    Rgb24,
+    Bgr24,
+    Yuv422p,
+    Yuv444p,
+    Gray8,
+    /// Full range YUV 4:2:0, what JPEG decoders produce.
+    Yuvj420p,
+    Nv12,
+    Nv21,
+    Argb,
+    Rgba,
+    Abgr,
+    Bgra,
+    Other(i32),
+}
+
+impl From<i32> for PixelFormat {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => PixelFormat::Yuv420p,
+            1 => PixelFormat::Yuyv422,
+            2 => PixelFormat::Rgb24,
+            3 => PixelFormat::Bgr24,
+            4 => PixelFormat::Yuv422p,
+            5 => PixelFormat::Yuv444p,
+            8 => PixelFormat::Gray8,
+            12 => PixelFormat::Yuvj420p,
+            23 => PixelFormat::Nv12,
+            24 => PixelFormat::Nv21,
+            25 => PixelFormat::Argb,
+            26 => PixelFormat::Rgba,
+            27 => PixelFormat::Abgr,
+            28 => PixelFormat::Bgra,
+            other => PixelFormat::Other(other),
+        }
+    }
+}
+
+impl From<PixelFormat> for i32 {
+    fn from(value: PixelFormat) -> Self {
+        match value {
+            PixelFormat::Yuv420p => 0,
+            PixelFormat::Yuyv422 => 1,
+            PixelFormat::Rgb24 => 2,
+            PixelFormat::Bgr24 => 3,
+            PixelFormat::Yuv422p => 4,
+            PixelFormat::Yuv444p => 5,
+            PixelFormat::Gray8 => 8,
+            PixelFormat::Yuvj420p => 12,
+            PixelFormat::Nv12 => 23,
+            PixelFormat::Nv21 => 24,
+            PixelFormat::Argb => 25,
+            PixelFormat::Rgba => 26,
+            PixelFormat::Abgr => 27,
+            PixelFormat::Bgra => 28,
+            PixelFormat::Other(other) => other,
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Row count of each plane for a picture `height` rows high; `None` for
+    /// [`Other`](Self::Other).
+    fn plane_heights(&self, height: usize) -> Option<Vec<usize>> {
+        let half = height.div_ceil(2);
+        Some(match self {
+            PixelFormat::Yuv420p | PixelFormat::Yuvj420p => vec![height, half, half],
+            PixelFormat::Yuv422p | PixelFormat::Yuv444p => vec![height, height, height],
+            PixelFormat::Nv12 | PixelFormat::Nv21 => vec![height, half],
+            PixelFormat::Yuyv422
+            | PixelFormat::Rgb24
+            | PixelFormat::Bgr24
+            | PixelFormat::Gray8
+            | PixelFormat::Argb
+            | PixelFormat::Rgba
+            | PixelFormat::Abgr
+            | PixelFormat::Bgra => vec![height],
+            PixelFormat::Other(_) => return None,
+        })
+    }
+}
+
+/// A decoded picture (an `AVFrame`), reference counted by ZLMediaKit.
+pub struct FramePix(mk_frame_pix);
+
+unsafe impl Send for FramePix {}
+unsafe impl Sync for FramePix {}
+
+impl FramePix {
+    /// Takes a new reference to a picture borrowed from a callback.
+    pub(crate) fn from_borrowed(pix: mk_frame_pix) -> Self {
+        FramePix(unsafe { mk_frame_pix_ref(pix) })
+    }
+
+    fn av_frame(&self) -> *mut AVFrame {
+        unsafe { mk_frame_pix_get_av_frame(self.0) }
+    }
+
+    pub fn width(&self) -> usize {
+        unsafe { mk_get_av_frame_width(self.av_frame()) }.max(0) as usize
+    }
+
+    pub fn height(&self) -> usize {
+        unsafe { mk_get_av_frame_height(self.av_frame()) }.max(0) as usize
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        unsafe { mk_get_av_frame_format(self.av_frame()) }.into()
+    }
+
+    pub fn pts(&self) -> i64 {
+        unsafe { mk_get_av_frame_pts(self.av_frame()) }
+    }
+
+    pub fn dts(&self) -> i64 {
+        unsafe { mk_get_av_frame_dts(self.av_frame()) }
+    }
+
+    /// Number of planes, 0 if the format is not known to rszlm.
+    pub fn plane_count(&self) -> usize {
+        self.format()
+            .plane_heights(self.height())
+            .map_or(0, |heights| heights.len())
+    }
+
+    /// Bytes per row of plane `index`, padding included.
+    pub fn linesize(&self, index: usize) -> usize {
+        if index >= 8 {
+            return 0;
+        }
+        unsafe { *mk_get_av_frame_line_size(self.av_frame()).add(index) }.max(0) as usize
+    }
+
+    /// Plane `index`, `linesize(index)` bytes per row including padding.
+    pub fn plane(&self, index: usize) -> Option<&[u8]> {
+        let rows = *self.format().plane_heights(self.height())?.get(index)?;
+        let data = unsafe { *mk_get_av_frame_data(self.av_frame()).add(index) };
+        if data.is_null() {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts(data, self.linesize(index) * rows) })
+    }
+
+    /// Plane `index` with `row_bytes` bytes of every row, without padding.
+    pub fn plane_packed(&self, index: usize, row_bytes: usize) -> Option<Vec<u8>> {
+        let linesize = self.linesize(index);
+        if row_bytes > linesize {
+            return None;
+        }
+        let plane = self.plane(index)?;
+        Some(
+            plane
+                .chunks(linesize)
+                .flat_map(|row| &row[..row_bytes])
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl Clone for FramePix {
+    fn clone(&self) -> Self {
+        FramePix::from_borrowed(self.0)
+    }
+}
+
+impl Drop for FramePix {
+    fn drop(&mut self) {
+        unsafe { mk_frame_pix_unref(self.0) }
+    }
+}
+
+/// An FFmpeg decoder for one track.
+pub struct Decoder(mk_decoder);
+
+unsafe impl Send for Decoder {}
+unsafe impl Sync for Decoder {}
+
+impl Decoder {
+    /// `thread_num` is FFmpeg's decoding thread count.
+    pub fn new(track: &Track, thread_num: i32) -> Self {
+        Decoder(unsafe { mk_decoder_create(track.inner(), thread_num) })
+    }
+
+    /// Uses the first available FFmpeg decoder of `codec_names`, e.g.
+    /// `["h264_cuvid", "h264"]`.
+    pub fn with_codecs(
+        track: &Track,
+        thread_num: i32,
+        codec_names: &[&str],
+    ) -> anyhow::Result<Self> {
+        let names = codec_names
+            .iter()
+            .map(|name| CString::new(*name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut ptrs: Vec<_> = names.iter().map(|name| name.as_ptr()).collect();
+        ptrs.push(std::ptr::null());
+        Ok(Decoder(unsafe {
+            mk_decoder_create2(track.inner(), thread_num, ptrs.as_mut_ptr())
+        }))
+    }
+
+    /// Called with every decoded picture; on the decoder's own thread when
+    /// decoding asynchronously, otherwise inside [`decode`](Self::decode).
+    pub fn on_frame(&self, cb: impl FnMut(FramePix) + Send + 'static) {
+        let cb: OnDecodeFn = Box::new(cb);
+        unsafe {
+            mk_decoder_set_cb2(
+                self.0,
+                Some(on_mk_decode),
+                box_to_mut_void_ptr!(cb),
+                Some(free_on_decode_cb),
+            )
+        }
+    }
+
+    /// `async_decode` queues the frame for a background thread;
+    /// `enable_merge` merges H264/H265 slices of one picture first.
+    pub fn decode(&self, frame: &Frame, async_decode: bool, enable_merge: bool) {
+        unsafe {
+            mk_decoder_decode(
+                self.0,
+                frame.as_c_ptr(),
+                async_decode as i32,
+                enable_merge as i32,
+            )
+        }
+    }
+
+    /// Queue length for asynchronous decoding; older frames are dropped beyond it.
+    pub fn set_max_async_frame_size(&self, size: usize) {
+        unsafe { mk_decoder_set_max_async_frame_size(self.0, size) }
+    }
+
+    /// Releases the decoder after draining pictures it still buffers.
+    pub fn flush(mut self) {
+        unsafe { mk_decoder_release(self.0, 1) };
+        self.0 = std::ptr::null_mut();
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { mk_decoder_release(self.0, 0) }
+        }
+    }
+}
+
+type OnDecodeFn = Box<dyn FnMut(FramePix) + Send + 'static>;
+
+extern "C" fn free_on_decode_cb(user_data: *mut ::std::os::raw::c_void) {
+    crate::ffi_guard(|| {
+        if !user_data.is_null() {
+            unsafe {
+                let _ = Box::from_raw(user_data as *mut OnDecodeFn);
+            }
+        }
+    });
+}
+
+extern "C" fn on_mk_decode(user_data: *mut ::std::os::raw::c_void, pix: mk_frame_pix) {
+    crate::ffi_guard(|| {
+        let cb = unsafe { &mut *(user_data as *mut OnDecodeFn) };
+        cb(FramePix::from_borrowed(pix));
+    });
+}
+
+/// Pixel format conversion and resizing (`sws_scale`).
+pub struct SwsContext(mk_swscale);
+
+unsafe impl Send for SwsContext {}
+unsafe impl Sync for SwsContext {}
+
+impl SwsContext {
+    /// Converts to `format` at `width` x `height`.
+    pub fn new(format: PixelFormat, width: usize, height: usize) -> Self {
+        SwsContext(unsafe { mk_swscale_create(format.into(), width as i32, height as i32) })
+    }
+
+    /// Converts `frame`; `None` if FFmpeg rejected it.
+    pub fn scale(&self, frame: &FramePix) -> Option<FramePix> {
+        let out = unsafe { mk_swscale_input_frame2(self.0, frame.0) };
+        if out.is_null() {
+            None
+        } else {
+            // input_frame2 hands over a new reference
+            Some(FramePix(out))
+        }
+    }
+}
+
+impl Drop for SwsContext {
+    fn drop(&mut self) {
+        unsafe { mk_swscale_release(self.0) }
+    }
+}
@@ -1,11 +1,20 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use once_cell::sync::Lazy;
 use rszlm_sys::*;
 
-use crate::{box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr};
+use crate::{
+    box_to_mut_void_ptr, const_ptr_to_string, const_str_to_ptr,
+    event::{RtcSctpStateMessage, EVENTS},
+    obj::{RtcTransport, RtcTransportId},
+};
 
-pub fn rtc_server_start(port: u16) {
-    unsafe {
-        mk_rtc_server_start(port);
-    }
+#[deprecated(note = "use `ServerSet::rtc`, which reports the bound port and start failures")]
+pub fn rtc_server_start(port: u16) -> u16 {
+    unsafe { mk_rtc_server_start(port) }
 }
 
 /// get answer sdp
@@ -61,3 +70,224 @@ extern "C" fn on_webrtc_get_answer_sdp(
         cb(answer, err);
     });
 }
+
+/// SCTP payload protocol identifier for a UTF-8 DataChannel message (RFC 8831).
+pub const PPID_STRING: u32 = 51;
+/// SCTP payload protocol identifier for a binary DataChannel message (RFC 8831).
+pub const PPID_BINARY: u32 = 53;
+
+/// The transport of an open [`DataChannel`]. Only used under the channel's
+/// lock, which is cleared before ZLMediaKit frees the transport.
+struct OpenTransport(mk_rtc_transport);
+
+unsafe impl Send for OpenTransport {}
+unsafe impl Sync for OpenTransport {}
+
+static CHANNELS: Lazy<RwLock<HashMap<RtcTransportId, Arc<DataChannel>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+type OnDataChannelOpenFn = Arc<dyn Fn(Arc<DataChannel>) + Send + Sync + 'static>;
+type OnDataChannelMessageFn = Arc<dyn Fn(&DataChannel, DataChannelMessage) + Send + Sync + 'static>;
+type OnDataChannelCloseFn = Arc<dyn Fn(&DataChannel) + Send + Sync + 'static>;
+
+/// One message received on a DataChannel.
+#[derive(Debug, Clone)]
+pub struct DataChannelMessage {
+    /// SCTP stream id of the channel the message arrived on.
+    pub sid: u16,
+    /// SCTP payload protocol identifier, see [`PPID_STRING`] / [`PPID_BINARY`].
+    pub ppid: u32,
+    pub data: Vec<u8>,
+}
+
+impl DataChannelMessage {
+    pub fn is_text(&self) -> bool {
+        self.ppid == PPID_STRING
+    }
+
+    /// The payload as text, if it was sent as a string message and is valid UTF-8.
+    pub fn as_text(&self) -> Option<&str> {
+        if self.is_text() {
+            std::str::from_utf8(&self.data).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// A WebRTC session's SCTP association, with per-session handlers.
+///
+/// Call [`DataChannel::listen`] once after init; every transport that reaches
+/// `Connected` is registered and handed to the `on_open` callback, and removed
+/// again on `Closed`/`Failed`. Open channels can be looked up with
+/// [`DataChannel::get`] / [`DataChannel::all`].
+///
+/// ZLMediaKit does not report which app/stream a transport serves, so a
+/// channel has none until the signaling layer sets it with
+/// [`bind`](DataChannel::bind).
+pub struct DataChannel {
+    id: RtcTransportId,
+    /// Cleared on close; sends hold the read lock, so none reaches a freed
+    /// transport.
+    transport: RwLock<Option<OpenTransport>>,
+    media: RwLock<Option<(String, String)>>,
+    on_message: RwLock<Option<OnDataChannelMessageFn>>,
+    on_close: RwLock<Option<OnDataChannelCloseFn>>,
+}
+
+impl DataChannel {
+    fn new(transport: &RtcTransport) -> Self {
+        Self {
+            id: transport.id(),
+            transport: RwLock::new(Some(OpenTransport(transport.0))),
+            media: RwLock::new(None),
+            on_message: RwLock::new(None),
+            on_close: RwLock::new(None),
+        }
+    }
+
+    /// Registers on the `on_rtc_sctp_connected`/`received`/`closed`/`failed`
+    /// events of [`EVENTS`] to maintain the channel registry. Handlers set
+    /// there before are still called, ahead of the registry's; set later,
+    /// they replace it.
+    pub fn listen(on_open: impl Fn(Arc<DataChannel>) + Send + Sync + 'static) {
+        let on_open: OnDataChannelOpenFn = Arc::new(on_open);
+        let mut events = EVENTS.write().unwrap();
+
+        let previous = events.on_rtc_sctp_connected.clone();
+        events.on_rtc_sctp_connected(move |msg| {
+            let channel = Arc::new(DataChannel::new(msg.transport()));
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            CHANNELS
+                .write()
+                .unwrap()
+                .insert(channel.id(), channel.clone());
+            on_open(channel);
+        });
+
+        let previous = events.on_rtc_sctp_received.clone();
+        events.on_rtc_sctp_received(move |msg| {
+            let message = match &msg {
+                RtcSctpStateMessage::Received(transport, sid, ppid, data) => {
+                    DataChannel::get(transport.id()).map(|channel| {
+                        let message = DataChannelMessage {
+                            sid: *sid,
+                            ppid: *ppid,
+                            data: data.clone(),
+                        };
+                        (channel, message)
+                    })
+                }
+                _ => None,
+            };
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            if let Some((channel, message)) = message {
+                let cb = channel.on_message.read().unwrap().clone();
+                if let Some(cb) = cb {
+                    cb(&channel, message);
+                }
+            }
+        });
+
+        let previous = events.on_rtc_sctp_closed.clone();
+        events.on_rtc_sctp_closed(move |msg| {
+            let id = msg.transport().id();
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            DataChannel::remove(id);
+        });
+
+        let previous = events.on_rtc_sctp_failed.clone();
+        events.on_rtc_sctp_failed(move |msg| {
+            let id = msg.transport().id();
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            DataChannel::remove(id);
+        });
+    }
+
+    fn remove(id: RtcTransportId) {
+        let channel = CHANNELS.write().unwrap().remove(&id);
+        if let Some(channel) = channel {
+            // waits for sends in flight; ZLMediaKit frees the transport
+            // after this event
+            channel.transport.write().unwrap().take();
+            let cb = channel.on_close.read().unwrap().clone();
+            if let Some(cb) = cb {
+                cb(&channel);
+            }
+        }
+    }
+
+    pub fn get(id: RtcTransportId) -> Option<Arc<DataChannel>> {
+        CHANNELS.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn all() -> Vec<Arc<DataChannel>> {
+        CHANNELS.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn id(&self) -> RtcTransportId {
+        self.id
+    }
+
+    /// The session closed or failed; sends fail from then on.
+    pub fn is_closed(&self) -> bool {
+        self.transport.read().unwrap().is_none()
+    }
+
+    /// Associates the session with the app/stream it was negotiated for.
+    pub fn bind(&self, app: &str, stream: &str) {
+        *self.media.write().unwrap() = Some((app.to_string(), stream.to_string()));
+    }
+
+    pub fn app(&self) -> Option<String> {
+        self.media
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(app, _)| app.clone())
+    }
+
+    pub fn stream(&self) -> Option<String> {
+        self.media.read().unwrap().as_ref().map(|(_, s)| s.clone())
+    }
+
+    pub fn send(&self, data: &[u8], sid: u16, ppid: u32) -> anyhow::Result<()> {
+        match self.transport.read().unwrap().as_ref() {
+            Some(transport) => {
+                RtcTransport::from(transport.0).send_datachannel(data, sid, ppid);
+                Ok(())
+            }
+            None => anyhow::bail!("data channel {} is closed", self.id),
+        }
+    }
+
+    pub fn send_text(&self, text: &str, sid: u16) -> anyhow::Result<()> {
+        self.send(text.as_bytes(), sid, PPID_STRING)
+    }
+
+    pub fn send_binary(&self, data: &[u8], sid: u16) -> anyhow::Result<()> {
+        self.send(data, sid, PPID_BINARY)
+    }
+
+    /// Called on a ZLMediaKit poller thread for each received message.
+    pub fn on_message(
+        &self,
+        cb: impl Fn(&DataChannel, DataChannelMessage) + Send + Sync + 'static,
+    ) {
+        *self.on_message.write().unwrap() = Some(Arc::new(cb));
+    }
+
+    /// Called once when the session closes or fails, after it was removed
+    /// from the registry; sends fail from then on.
+    pub fn on_close(&self, cb: impl Fn(&DataChannel) + Send + Sync + 'static) {
+        *self.on_close.write().unwrap() = Some(Arc::new(cb));
+    }
+}